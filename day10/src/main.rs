@@ -1,22 +1,59 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    io::{stdin, Stdin},
+    io::{stdin, stdout, Stdin, Write},
+    ops::{Index, IndexMut},
+    thread::sleep,
+    time::Duration,
 };
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{ensure, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use fixedbitset::FixedBitSet;
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// Report the pipe shape inferred for the start tile
+    #[arg(long, global = true)]
+    explain: bool,
+    /// Print the answer to part1 or part2 along with details of the main loop in the given format
+    #[arg(long, global = true, value_enum)]
+    export: Option<ExportFormat>,
+    /// Animate the two walkers moving outward from the start
+    #[arg(long, global = true)]
+    visualize: bool,
+    /// Delay between animation frames, in milliseconds
+    #[arg(long, global = true, default_value_t = 5)]
+    frame_delay: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
 }
 
 #[derive(Subcommand)]
 enum Part {
     Part1,
-    Part2,
+    Part2 {
+        #[arg(long, value_enum, default_value_t = Solver::Crossing)]
+        solver: Solver,
+    },
+    /// Draw the main loop, shading interior tiles and dimming unconnected pipes
+    Render,
+    /// Report every closed loop in the grid
+    Loops,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Scan each row, toggling inside/outside upon crossing the loop
+    Crossing,
+    /// Compute the loop's area with the shoelace formula and apply Pick's theorem
+    Shoelace,
+    /// Flood-fill from outside at double resolution so the fill can squeeze between pipes
+    Flood,
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -62,16 +99,16 @@ enum GridSpace {
 }
 
 impl GridSpace {
-    fn possible_connections(&self) -> Vec<Direction> {
+    fn possible_connections(&self) -> &'static [Direction] {
         match self {
-            GridSpace::VerticalPipe => vec![Direction::North, Direction::South],
-            GridSpace::HorizontalPipe => vec![Direction::West, Direction::East],
-            GridSpace::NorthEastBend => vec![Direction::North, Direction::East],
-            GridSpace::NorthWestBend => vec![Direction::North, Direction::West],
-            GridSpace::SouthWestBend => vec![Direction::South, Direction::West],
-            GridSpace::SouthEastBend => vec![Direction::South, Direction::East],
-            GridSpace::Ground => vec![],
-            GridSpace::Start => vec![
+            GridSpace::VerticalPipe => &[Direction::North, Direction::South],
+            GridSpace::HorizontalPipe => &[Direction::West, Direction::East],
+            GridSpace::NorthEastBend => &[Direction::North, Direction::East],
+            GridSpace::NorthWestBend => &[Direction::North, Direction::West],
+            GridSpace::SouthWestBend => &[Direction::South, Direction::West],
+            GridSpace::SouthEastBend => &[Direction::South, Direction::East],
+            GridSpace::Ground => &[],
+            GridSpace::Start => &[
                 Direction::North,
                 Direction::South,
                 Direction::West,
@@ -79,6 +116,22 @@ impl GridSpace {
             ],
         }
     }
+
+    fn from_connections(connections: &[Direction]) -> Option<GridSpace> {
+        [
+            GridSpace::VerticalPipe,
+            GridSpace::HorizontalPipe,
+            GridSpace::NorthEastBend,
+            GridSpace::NorthWestBend,
+            GridSpace::SouthWestBend,
+            GridSpace::SouthEastBend,
+        ]
+        .into_iter()
+        .find(|space| {
+            let possible = space.possible_connections();
+            possible.len() == connections.len() && possible.iter().all(|d| connections.contains(d))
+        })
+    }
 }
 
 #[derive(Debug, Error)]
@@ -103,35 +156,79 @@ impl TryFrom<char> for GridSpace {
     }
 }
 
-type GridRow = Vec<GridSpace>;
-type Grid = Vec<GridRow>;
+impl From<&GridSpace> for char {
+    fn from(value: &GridSpace) -> Self {
+        match value {
+            GridSpace::VerticalPipe => '|',
+            GridSpace::HorizontalPipe => '-',
+            GridSpace::NorthEastBend => 'L',
+            GridSpace::NorthWestBend => 'J',
+            GridSpace::SouthWestBend => '7',
+            GridSpace::SouthEastBend => 'F',
+            GridSpace::Ground => '.',
+            GridSpace::Start => 'S',
+        }
+    }
+}
+
+type Coords = (usize, usize);
 
-fn grid_row(line: &str) -> Result<GridRow, ParseGridSpaceError> {
-    line.chars().map(|c| GridSpace::try_from(c)).collect()
+/// The grid of pipes, stored row-major in a single flat vector
+struct Grid {
+    width: usize,
+    height: usize,
+    spaces: Vec<GridSpace>,
 }
 
+impl Grid {
+    fn len(&self) -> usize {
+        self.spaces.len()
+    }
+
+    fn index_of(&self, (r, c): Coords) -> usize {
+        r * self.width + c
+    }
+
+    fn coords_of(&self, idx: usize) -> Coords {
+        (idx / self.width, idx % self.width)
+    }
+}
+
+impl Index<Coords> for Grid {
+    type Output = GridSpace;
+
+    fn index(&self, coords: Coords) -> &Self::Output {
+        &self.spaces[self.index_of(coords)]
+    }
+}
+
+impl IndexMut<Coords> for Grid {
+    fn index_mut(&mut self, coords: Coords) -> &mut Self::Output {
+        let idx = self.index_of(coords);
+        &mut self.spaces[idx]
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Grid rows have inconsistent lengths")]
+struct RaggedGridError;
+
 #[derive(Debug, Error)]
 #[error("Missing start position")]
 struct MissingStartError;
 
-fn find_start(grid: &Grid) -> Result<(usize, usize), MissingStartError> {
-    for (i, row) in grid.iter().enumerate() {
-        for (j, space) in row.iter().enumerate() {
-            if let GridSpace::Start = space {
-                return Ok((i, j));
-            }
-        }
-    }
-
-    Err(MissingStartError)
+fn find_start(grid: &Grid) -> Result<Coords, MissingStartError> {
+    grid.spaces
+        .iter()
+        .position(|space| *space == GridSpace::Start)
+        .map(|idx| grid.coords_of(idx))
+        .ok_or(MissingStartError)
 }
 
 #[derive(Debug, Error)]
 #[error("Tried to move out of bounds")]
 struct OutOfBoundsError;
 
-type Coords = (usize, usize);
-
 fn try_move(grid: &Grid, coords: Coords, dir: Direction) -> Result<Coords, OutOfBoundsError> {
     let (r, c) = coords;
     let (dr, dc) = match dir {
@@ -144,7 +241,7 @@ fn try_move(grid: &Grid, coords: Coords, dir: Direction) -> Result<Coords, OutOf
     let r = r.checked_add_signed(dr).ok_or(OutOfBoundsError)?;
     let c = c.checked_add_signed(dc).ok_or(OutOfBoundsError)?;
 
-    if r > grid.len() || c > grid[0].len() {
+    if r >= grid.height || c >= grid.width {
         return Err(OutOfBoundsError);
     }
 
@@ -152,98 +249,390 @@ fn try_move(grid: &Grid, coords: Coords, dir: Direction) -> Result<Coords, OutOf
 }
 
 fn grid(inp: Stdin) -> Result<Grid> {
-    let mut grid = Grid::new();
+    let mut spaces = Vec::new();
+    let mut width = 0;
+    let mut height = 0;
     for line in inp.lines() {
         let line = line?;
-        grid.push(grid_row(&line)?);
+        for c in line.chars() {
+            spaces.push(GridSpace::try_from(c)?);
+        }
+        if height == 0 {
+            width = spaces.len();
+        }
+        height += 1;
+        if spaces.len() != width * height {
+            return Err(RaggedGridError.into());
+        }
     }
 
-    Ok(grid)
+    Ok(Grid {
+        width,
+        height,
+        spaces,
+    })
 }
 
-fn connections(grid: &Grid, coords: Coords) -> Result<Vec<Direction>> {
-    let space = &grid[coords.0][coords.1];
-    if let GridSpace::Start = space {
-        let mut res = Vec::new();
+#[derive(Debug, Error)]
+#[error("Start tile connects to {0} neighbors rather than two")]
+struct AmbiguousStartError(usize);
+
+/// Replace the start tile with the pipe shape implied by its neighbors, returning that shape
+fn resolve_start(grid: &mut Grid, start: Coords) -> Result<&GridSpace, AmbiguousStartError> {
+    let mut res = Vec::new();
+    for &dir in GridSpace::Start.possible_connections() {
+        let Ok(coords) = try_move(grid, start, dir) else {
+            continue;
+        };
+        if grid[coords]
+            .possible_connections()
+            .contains(&dir.opposite())
+        {
+            res.push(dir)
+        }
+    }
 
-        for dir in space.possible_connections() {
-            let coords = try_move(&grid, coords, dir)?;
-            if grid[coords.0][coords.1]
-                .possible_connections()
-                .contains(&dir.opposite())
-            {
-                res.push(dir)
+    grid[start] = GridSpace::from_connections(&res).ok_or(AmbiguousStartError(res.len()))?;
+    Ok(&grid[start])
+}
+
+fn connections(grid: &Grid, coords: Coords) -> &'static [Direction] {
+    grid[coords].possible_connections()
+}
+
+/// Tiles enclosed by the main loop
+fn find_inside(grid: &Grid, on_loop: &FixedBitSet) -> FixedBitSet {
+    let mut inside = FixedBitSet::with_capacity(grid.len());
+    for i in 0..grid.height {
+        let mut is_inside = false;
+        let mut seen: Option<Direction> = None;
+
+        for j in 0..grid.width {
+            if on_loop[grid.index_of((i, j))] {
+                let connections: Vec<_> = connections(grid, (i, j))
+                    .iter()
+                    .copied()
+                    .filter(Direction::is_vertical)
+                    .collect();
+
+                if connections.len() >= 2 {
+                    is_inside = !is_inside;
+                } else if connections.is_empty() {
+                    continue;
+                } else {
+                    (is_inside, seen) = match (seen, connections[0]) {
+                        (None, dir) => (is_inside, Some(dir)),
+                        (Some(Direction::North), Direction::North) => (is_inside, None),
+                        (Some(Direction::North), Direction::South) => (!is_inside, None),
+                        (Some(Direction::South), Direction::North) => (!is_inside, None),
+                        (Some(Direction::South), Direction::South) => (is_inside, None),
+                        _ => unreachable!(),
+                    }
+                }
+            } else if is_inside {
+                inside.insert(grid.index_of((i, j)));
             }
         }
-
-        Ok(res)
-    } else {
-        Ok(space.possible_connections())
     }
-}
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let grid = grid(stdin())?;
-    let start = find_start(&grid)?;
+    inside
+}
 
-    let mut to_visit = VecDeque::new();
-    let mut visited: HashMap<Coords, usize> = HashMap::new();
-    to_visit.push_back((start, 0));
-    visited.insert(start, 0);
+/// Number of tiles enclosed by the main loop, found by flood-filling the outside of the loop on a
+/// grid of double resolution in which adjacent connected pipes are joined by an extra blocked cell
+fn count_inside_flood(grid: &Grid, path: &[Coords]) -> usize {
+    // tile (r, c) maps to (2r + 1, 2c + 1), leaving a border of open cells around the grid
+    let height = 2 * grid.height + 1;
+    let width = 2 * grid.width + 1;
+    let mut blocked = FixedBitSet::with_capacity(height * width);
+    for &(r, c) in path {
+        let (r, c) = (2 * r + 1, 2 * c + 1);
+        blocked.insert(r * width + c);
+        for dir in connections(grid, (r / 2, c / 2)) {
+            let (r, c) = match dir {
+                Direction::North => (r - 1, c),
+                Direction::South => (r + 1, c),
+                Direction::West => (r, c - 1),
+                Direction::East => (r, c + 1),
+            };
+            blocked.insert(r * width + c);
+        }
+    }
 
-    while let Some((coords, dist)) = to_visit.pop_front() {
-        for dir in connections(&grid, coords)? {
-            let coords = try_move(&grid, coords, dir)?;
-            if visited.contains_key(&coords) {
+    let mut outside = FixedBitSet::with_capacity(height * width);
+    let mut to_visit: Vec<Coords> = vec![(0, 0)];
+    outside.insert(0);
+    while let Some((r, c)) = to_visit.pop() {
+        let neighbors = [
+            (r.wrapping_sub(1), c),
+            (r + 1, c),
+            (r, c.wrapping_sub(1)),
+            (r, c + 1),
+        ];
+        for (r, c) in neighbors {
+            if r >= height || c >= width {
+                continue;
+            }
+            let idx = r * width + c;
+            if blocked[idx] || outside.put(idx) {
                 continue;
             }
+            to_visit.push((r, c));
+        }
+    }
+
+    (0..grid.height)
+        .flat_map(|r| (0..grid.width).map(move |c| (2 * r + 1) * width + 2 * c + 1))
+        .filter(|&idx| !blocked[idx] && !outside[idx])
+        .count()
+}
 
-            to_visit.push_back((coords, dist + 1));
-            visited.insert(coords, dist + 1);
+#[derive(Debug, Error)]
+#[error("Start tile is not part of a closed loop")]
+struct MissingLoopError;
+
+/// Follow the pipe from `start` until it closes into a loop, returning the tiles in order.
+/// Returns `None` upon reaching a dead end or a tile in `seen`.
+fn walk_loop(grid: &Grid, start: Coords, seen: &mut FixedBitSet) -> Option<Vec<Coords>> {
+    let mut path = vec![start];
+    seen.insert(grid.index_of(start));
+    let mut dir = *connections(grid, start).first()?;
+    let mut cur = start;
+    loop {
+        let next = try_move(grid, cur, dir).ok()?;
+        let next_connections = connections(grid, next);
+        if !next_connections.contains(&dir.opposite()) {
+            return None;
+        }
+        if next == start {
+            return Some(path);
         }
+        if seen.put(grid.index_of(next)) {
+            return None;
+        }
+
+        path.push(next);
+        dir = *next_connections.iter().find(|&&d| d != dir.opposite())?;
+        cur = next;
     }
+}
 
-    let res = match args.part {
-        Part::Part1 => visited.values().max().unwrap().to_owned(),
-        Part::Part2 => {
-            let mut total = 0;
-            for (i, row) in grid.iter().enumerate() {
-                let mut is_inside = false;
-                let mut seen: Option<Direction> = None;
-
-                for (j, _) in row.iter().enumerate() {
-                    if visited.contains_key(&(i, j)) {
-                        let connections: Vec<_> = connections(&grid, (i, j))?
-                            .into_iter()
-                            .filter(Direction::is_vertical)
-                            .collect();
-
-                        if connections.len() >= 2 {
-                            is_inside = !is_inside;
-                        } else if connections.len() == 0 {
-                            continue;
-                        } else {
-                            (is_inside, seen) = match (seen, connections[0]) {
-                                (None, dir) => (is_inside, Some(dir)),
-                                (Some(Direction::North), Direction::North) => (is_inside, None),
-                                (Some(Direction::North), Direction::South) => (!is_inside, None),
-                                (Some(Direction::South), Direction::North) => (!is_inside, None),
-                                (Some(Direction::South), Direction::South) => (is_inside, None),
-                                _ => unreachable!(),
-                            }
-                        }
-                    } else if is_inside {
-                        total += 1;
-                    }
+/// Every closed loop in the grid, in order of their first tile
+fn find_all_loops(grid: &Grid) -> Vec<Vec<Coords>> {
+    let mut seen = FixedBitSet::with_capacity(grid.len());
+    let mut loops = Vec::new();
+    for (idx, space) in grid.spaces.iter().enumerate() {
+        if *space == GridSpace::Ground || seen[idx] {
+            continue;
+        }
+        if let Some(path) = walk_loop(grid, grid.coords_of(idx), &mut seen) {
+            loops.push(path);
+        }
+    }
+
+    loops
+}
+
+/// Number of tiles enclosed by a loop, using the shoelace formula and Pick's theorem
+fn enclosed_area(path: &[Coords]) -> usize {
+    let twice_area: i64 = path
+        .iter()
+        .zip(path.iter().cycle().skip(1))
+        .map(|(&(r1, c1), &(r2, c2))| r1 as i64 * c2 as i64 - r2 as i64 * c1 as i64)
+        .sum();
+
+    (twice_area.unsigned_abs() as usize + 2 - path.len()) / 2
+}
+
+fn export_json(
+    grid: &Grid,
+    path: &[Coords],
+    on_loop: &FixedBitSet,
+    inside: &FixedBitSet,
+    answer: usize,
+) {
+    let coords = |(r, c): Coords| format!("[{r}, {c}]");
+    let distance = path.len() / 2;
+    let farthest = path[distance];
+
+    println!("{{");
+    println!("  \"answer\": {answer},");
+    println!(
+        "  \"loop\": [{}],",
+        path.iter()
+            .copied()
+            .map(coords)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!(
+        "  \"farthest\": {{\"coords\": {}, \"distance\": {distance}}},",
+        coords(farthest)
+    );
+    println!("  \"tiles\": [");
+    for i in 0..grid.height {
+        let tiles: Vec<&str> = (0..grid.width)
+            .map(|j| grid.index_of((i, j)))
+            .map(|idx| {
+                if on_loop[idx] {
+                    "\"loop\""
+                } else if inside[idx] {
+                    "\"inside\""
+                } else {
+                    "\"outside\""
                 }
+            })
+            .collect();
+        let sep = if i + 1 < grid.height { "," } else { "" };
+        println!("    [{}]{sep}", tiles.join(", "));
+    }
+    println!("  ]");
+    println!("}}");
+}
+
+/// Box-drawing character for a pipe connecting the given directions
+fn pipe_char(connections: &[Direction]) -> char {
+    let has = |dir| connections.contains(&dir);
+    match (
+        has(Direction::North),
+        has(Direction::South),
+        has(Direction::West),
+        has(Direction::East),
+    ) {
+        (true, true, _, _) => '│',
+        (_, _, true, true) => '─',
+        (true, _, _, true) => '└',
+        (true, _, true, _) => '┘',
+        (_, true, true, _) => '┐',
+        (_, true, _, true) => '┌',
+        _ => '?',
+    }
+}
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+fn render(grid: &Grid, on_loop: &FixedBitSet, inside: &FixedBitSet) {
+    for (i, row) in grid.spaces.chunks(grid.width).enumerate() {
+        let mut line = String::new();
+        for (j, space) in row.iter().enumerate() {
+            let idx = grid.index_of((i, j));
+            if on_loop[idx] {
+                line.push(pipe_char(space.possible_connections()));
+            } else if inside[idx] {
+                line.push('░');
+            } else if let GridSpace::Ground = space {
+                line.push(' ');
+            } else {
+                line.push_str(DIM);
+                line.push(pipe_char(space.possible_connections()));
+                line.push_str(RESET);
             }
+        }
+        println!("{line}");
+    }
+}
 
-            total
+const BOLD: &str = "\x1b[1m";
+
+/// Animate walkers setting off in both directions around the loop until they meet at the farthest tile
+fn visualize(grid: &Grid, path: &[Coords], frame_delay: u64) -> Result<()> {
+    let mut out = stdout().lock();
+    write!(out, "\x1b[2J\x1b[H{DIM}")?;
+    for row in grid.spaces.chunks(grid.width) {
+        let line: String = row
+            .iter()
+            .map(|space| match space {
+                GridSpace::Ground => ' ',
+                space => pipe_char(space.possible_connections()),
+            })
+            .collect();
+        writeln!(out, "{line}")?;
+    }
+    write!(out, "{RESET}")?;
+
+    for dist in 0..=path.len() / 2 {
+        for (r, c) in [path[dist], path[(path.len() - dist) % path.len()]] {
+            // cursor positions are 1-based
+            write!(
+                out,
+                "\x1b[{};{}H{BOLD}{}{RESET}",
+                r + 1,
+                c + 1,
+                pipe_char(connections(grid, (r, c)))
+            )?;
+        }
+        write!(out, "\x1b[{};1HMax distance: {dist}", grid.height + 1)?;
+        out.flush()?;
+        sleep(Duration::from_millis(frame_delay));
+    }
+    writeln!(out)?;
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.export.is_some() {
+        ensure!(
+            matches!(args.part, Part::Part1 | Part::Part2 { .. }),
+            "--export only goes with part1 or part2"
+        );
+        // anything else on stdout would break the exported document
+        ensure!(
+            !args.explain && !args.visualize,
+            "--export can't be combined with --explain or --visualize"
+        );
+    }
+    let mut grid = grid(stdin())?;
+    let start = find_start(&grid)?;
+    let start_tile = resolve_start(&mut grid, start)?;
+    if args.explain {
+        println!("Start tile at {start:?} is `{}`", char::from(start_tile));
+    }
+    let mut on_loop = FixedBitSet::with_capacity(grid.len());
+    let path = walk_loop(&grid, start, &mut on_loop).ok_or(MissingLoopError)?;
+
+    if args.visualize {
+        visualize(&grid, &path, args.frame_delay)?;
+    }
+
+    let res = match args.part {
+        // the farthest tile is halfway around the loop
+        Part::Part1 => path.len() / 2,
+        Part::Part2 {
+            solver: Solver::Crossing,
+        } => find_inside(&grid, &on_loop).count_ones(..),
+        Part::Part2 {
+            solver: Solver::Shoelace,
+        } => enclosed_area(&path),
+        Part::Part2 {
+            solver: Solver::Flood,
+        } => count_inside_flood(&grid, &path),
+        Part::Loops => {
+            for path in find_all_loops(&grid) {
+                println!(
+                    "Loop through {:?}{}: length {}, encloses {} tiles",
+                    path[0],
+                    if path.contains(&start) { " (main)" } else { "" },
+                    path.len(),
+                    enclosed_area(&path),
+                );
+            }
+            return Ok(());
+        }
+        Part::Render => {
+            render(&grid, &on_loop, &find_inside(&grid, &on_loop));
+            return Ok(());
         }
     };
 
-    println!("{res}");
+    match args.export {
+        Some(ExportFormat::Json) => {
+            export_json(&grid, &path, &on_loop, &find_inside(&grid, &on_loop), res)
+        }
+        None => println!("{res}"),
+    }
 
     Ok(())
 }
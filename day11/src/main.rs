@@ -1,10 +1,7 @@
-use std::{
-    collections::HashSet,
-    io::{stdin, Stdin},
-};
+use std::io::{stdin, Stdin};
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use itertools::Itertools;
 use thiserror::Error;
 
@@ -12,12 +9,27 @@ use thiserror::Error;
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Sweep)]
+    solver: Solver,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Sum the distance between every pair of galaxies
+    Pairwise,
+    /// Sort the coordinates along each axis and sum their contributions in a single pass
+    Sweep,
 }
 
 #[derive(Subcommand)]
 enum Part {
     Part1,
     Part2,
+    /// Report each galaxy's nearest neighbor and the minimum spanning tree over all galaxies
+    Analyze {
+        #[arg(long, default_value_t = 2)]
+        expansion: usize,
+    },
 }
 
 #[derive(PartialEq, Eq)]
@@ -60,79 +72,141 @@ fn grid(inp: Stdin) -> Result<Grid> {
     Ok(res)
 }
 
+/// Position of each row (or column) once every empty row (or column) before it has been
+/// expanded by `multiplier`, computed as a running prefix sum
+fn expanded_positions(empty: impl Iterator<Item = bool>, multiplier: usize) -> Vec<usize> {
+    empty
+        .scan(0, |pos, is_empty| {
+            let cur = *pos;
+            *pos += if is_empty { multiplier } else { 1 };
+            Some(cur)
+        })
+        .collect()
+}
+
+/// Sum of |a - b| over all pairs of values
+fn sum_pairwise_diffs(mut values: Vec<usize>) -> usize {
+    values.sort_unstable();
+    // once sorted, values[i] is the larger of the pair when paired with each of the i values before it
+    let mut prefix = 0;
+    let mut total = 0;
+    for (i, v) in values.into_iter().enumerate() {
+        total += i * v - prefix;
+        prefix += v;
+    }
+    total
+}
+
+type Coords = (usize, usize);
+
+fn distance((r0, c0): Coords, (r1, c1): Coords) -> usize {
+    r0.abs_diff(r1) + c0.abs_diff(c1)
+}
+
+/// Total edge weight of the minimum spanning tree, using Prim's algorithm over the complete graph
+fn mst_weight(galaxies: &[Coords]) -> usize {
+    let mut in_tree = vec![false; galaxies.len()];
+    // cheapest edge connecting each galaxy to the tree so far
+    let mut best = vec![usize::MAX; galaxies.len()];
+    let mut total = 0;
+    if !galaxies.is_empty() {
+        best[0] = 0;
+    }
+
+    for _ in 0..galaxies.len() {
+        let (next, &cost) = best
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !in_tree[i])
+            .min_by_key(|&(_, cost)| cost)
+            .unwrap();
+        in_tree[next] = true;
+        total += cost;
+
+        for (i, &galaxy) in galaxies.iter().enumerate() {
+            if !in_tree[i] {
+                best[i] = best[i].min(distance(galaxies[next], galaxy));
+            }
+        }
+    }
+
+    total
+}
+
+fn analyze(galaxies: &[Coords], expanded: &[Coords]) {
+    for (i, (&galaxy, &pos)) in galaxies.iter().zip(expanded).enumerate() {
+        let nearest = expanded
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(j, &other)| (distance(pos, other), j))
+            .min();
+        match nearest {
+            Some((dist, j)) => println!(
+                "Galaxy {} at {galaxy:?}: nearest is galaxy {} at distance {dist}",
+                i + 1,
+                j + 1
+            ),
+            None => println!("Galaxy {} at {galaxy:?}: no other galaxies", i + 1),
+        }
+    }
+    println!("Minimum spanning tree total: {}", mst_weight(expanded));
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
     let grid = grid(stdin())?;
-    let empty_rows = grid
-        .iter()
-        .enumerate()
-        .filter_map(|(r, row)| {
-            if row.iter().all(|space| *space == GridSpace::Empty) {
-                Some(r)
-            } else {
-                None
-            }
-        })
-        .collect::<HashSet<_>>();
-
-    let empty_cols = (0..grid[0].len())
-        .filter_map(|c| {
-            if grid.iter().all(|row| row[c] == GridSpace::Empty) {
-                Some(c)
-            } else {
-                None
-            }
-        })
-        .collect::<HashSet<_>>();
 
-    let galaxies = grid
+    let multiplier = match args.part {
+        Part::Part1 => 2usize,
+        Part::Part2 => 1000000usize,
+        Part::Analyze { expansion } => expansion,
+    };
+
+    let row_positions = expanded_positions(
+        grid.iter()
+            .map(|row| row.iter().all(|space| *space == GridSpace::Empty)),
+        multiplier,
+    );
+    let col_positions = expanded_positions(
+        (0..grid[0].len()).map(|c| grid.iter().all(|row| row[c] == GridSpace::Empty)),
+        multiplier,
+    );
+
+    let original = grid
         .iter()
         .enumerate()
         .flat_map(|(r, row)| {
             row.iter()
                 .enumerate()
-                .filter_map(|(c, space)| {
-                    if *space == GridSpace::Galaxy {
-                        Some((r, c))
-                    } else {
-                        None
-                    }
-                })
+                .filter(|(_, space)| **space == GridSpace::Galaxy)
+                .map(|(c, _)| (r, c))
                 .collect::<Vec<_>>()
         })
         .collect::<Vec<_>>();
+    let galaxies = original
+        .iter()
+        .map(|&(r, c)| (row_positions[r], col_positions[c]))
+        .collect::<Vec<_>>();
 
-    let multiplier = match args.part {
-        Part::Part1 => 2usize,
-        Part::Part2 => 1000000usize,
-    };
-
-    let mut res: usize = 0;
-    for (&(r0, c0), &(r1, c1)) in galaxies.iter().tuple_combinations() {
-        let row_dist: usize = (r0.min(r1)..r0.max(r1))
-            .map(|r| {
-                if empty_rows.contains(&r) {
-                    multiplier
-                } else {
-                    1
-                }
-            })
-            .sum();
-
-        let col_dist: usize = (c0.min(c1)..c0.max(c1))
-            .map(|c| {
-                if empty_cols.contains(&c) {
-                    multiplier
-                } else {
-                    1
-                }
-            })
-            .sum();
-
-        res += row_dist + col_dist;
+    if let Part::Analyze { .. } = args.part {
+        analyze(&original, &galaxies);
+        return Ok(());
     }
 
+    let res: usize = match args.solver {
+        Solver::Pairwise => galaxies
+            .iter()
+            .tuple_combinations()
+            .map(|(&a, &b)| distance(a, b))
+            .sum(),
+        Solver::Sweep => {
+            let (rows, cols) = galaxies.into_iter().unzip();
+            sum_pairwise_diffs(rows) + sum_pairwise_diffs(cols)
+        }
+    };
+
     println!("{res}");
 
     Ok(())
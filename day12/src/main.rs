@@ -1,7 +1,12 @@
-use std::{io::stdin, iter::once, num::NonZeroUsize};
+use std::{
+    collections::HashMap,
+    io::stdin,
+    iter::once,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
 
-use clap::{Parser, Subcommand};
-use memoize::memoize;
+use clap::{builder::RangedU64ValueParser, value_parser, Parser, Subcommand, ValueEnum};
 use nom::{
     character::complete::{char, digit1, one_of, space1},
     combinator::{map_res, recognize},
@@ -15,12 +20,35 @@ use thiserror::Error;
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Dp)]
+    solver: Solver,
+    /// Print the count for each record and its share of the total
+    #[arg(long, global = true)]
+    per_line: bool,
+    /// Report counts modulo this number
+    #[arg(long, global = true, value_parser = value_parser!(u64).range(1..))]
+    modulus: Option<u64>,
+    /// Report progress and the slowest records so far on stderr
+    #[arg(long, global = true)]
+    progress: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Bottom-up DP over each spring in turn
+    Dp,
+    /// Top-down recursion, memoized per record
+    Memo,
 }
 
 #[derive(Subcommand)]
 enum Part {
     Part1,
-    Part2,
+    Part2 {
+        /// Number of copies of each record in the unfolded row
+        #[arg(long, default_value_t = 5, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+        repeat: usize,
+    },
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -74,113 +102,230 @@ fn record(input: &str) -> IResult<&str, Record> {
     Ok((input, Record { row, group_sizes }))
 }
 
-#[memoize]
+/// Count arrangements with a DP over (group index, length of the current run of damaged springs),
+/// advanced one spring at a time
 fn num_arrangements(
-    row: Vec<SpringCondition>,
-    group_sizes: Vec<GroupSize>,
-    start_constraint: Option<SpringCondition>,
-) -> usize {
-    match (row.split_first(), start_constraint) {
-        // end of row, must also have reached end of groups
-        (None, _) => group_sizes.is_empty().into(),
+    row: &[SpringCondition],
+    group_sizes: &[GroupSize],
+    counting: Counting,
+) -> Result<u128, OverflowError> {
+    let num_groups = group_sizes.len();
+    let max_run = group_sizes.iter().map(|s| s.get()).max().unwrap_or(0);
+    let idx = |group: usize, run: usize| group * (max_run + 1) + run;
 
-        // invalid constraint
-        (_, Some(SpringCondition::Unknown)) => unreachable!(),
+    let mut counts = vec![0u128; (num_groups + 1) * (max_run + 1)];
+    let mut next = counts.clone();
+    counts[idx(0, 0)] = 1;
 
-        // not matching constraint
-        (Some((SpringCondition::Operational, _)), Some(SpringCondition::Damaged)) => 0,
-        (Some((SpringCondition::Damaged, _)), Some(SpringCondition::Operational)) => 0,
+    // a trailing operational spring closes off any group still in progress
+    for &spring in row.iter().chain(once(&SpringCondition::Operational)) {
+        next.fill(0);
+        for group in 0..=num_groups {
+            for run in 0..=max_run {
+                let count = counts[idx(group, run)];
+                if count == 0 {
+                    continue;
+                }
 
-        (Some((SpringCondition::Operational, row)), _) => {
-            num_arrangements(row.to_vec(), group_sizes, None)
-        }
+                let mut add = |i: usize| -> Result<(), OverflowError> {
+                    next[i] = counting.add(next[i], count)?;
+                    Ok(())
+                };
+                if let SpringCondition::Operational | SpringCondition::Unknown = spring {
+                    if run == 0 {
+                        add(idx(group, 0))?;
+                    } else if run == group_sizes[group].get() {
+                        add(idx(group + 1, 0))?;
+                    }
+                }
 
-        (Some((SpringCondition::Damaged, row)), _) => {
-            let Some((&first, rest_sizes)) = group_sizes.split_first() else {
-                return 0;
-            };
-            let remaining_in_first_group = first.get() - 1;
-            match remaining_in_first_group {
-                // must reach end of group
-                0 => num_arrangements(
-                    row.to_vec(),
-                    rest_sizes.to_vec(),
-                    Some(SpringCondition::Operational),
-                ),
-
-                // group must continue
-                n => num_arrangements(
-                    row.to_vec(),
-                    once(GroupSize::try_from(n).unwrap())
-                        .chain(rest_sizes.iter().copied())
-                        .collect::<Vec<_>>(),
-                    Some(SpringCondition::Damaged),
-                ),
+                if let SpringCondition::Damaged | SpringCondition::Unknown = spring {
+                    if group < num_groups && run < group_sizes[group].get() {
+                        add(idx(group, run + 1))?;
+                    }
+                }
             }
         }
+        std::mem::swap(&mut counts, &mut next);
+    }
 
-        (Some((SpringCondition::Unknown, row)), constraint) => {
-            let mut res = 0;
-
-            if let None | Some(SpringCondition::Operational) = constraint {
-                // try replacing the first element with Operational
-                res += num_arrangements(
-                    once(SpringCondition::Operational)
-                        .chain(row.iter().copied())
-                        .collect::<Vec<_>>(),
-                    group_sizes.clone(),
-                    start_constraint,
-                );
-            }
+    Ok(counts[idx(num_groups, 0)])
+}
+
+/// Recursive counter which places a whole group at a time, memoizing on positions within the
+/// borrowed record rather than on copies of the remaining row and groups
+struct MemoCounter<'a> {
+    row: &'a [SpringCondition],
+    group_sizes: &'a [GroupSize],
+    counting: Counting,
+    cache: HashMap<(usize, usize), u128>,
+}
+
+impl<'a> MemoCounter<'a> {
+    fn new(row: &'a [SpringCondition], group_sizes: &'a [GroupSize], counting: Counting) -> Self {
+        MemoCounter {
+            row,
+            group_sizes,
+            counting,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Arrangements of `row[pos..]` matching `group_sizes[group..]`
+    fn count(&mut self, pos: usize, group: usize) -> Result<u128, OverflowError> {
+        if pos >= self.row.len() {
+            return Ok((group == self.group_sizes.len()).into());
+        }
+        if let Some(&res) = self.cache.get(&(pos, group)) {
+            return Ok(res);
+        }
+
+        let mut res = 0;
+        let spring = self.row[pos];
+
+        if spring != SpringCondition::Damaged {
+            // leave this spring operational
+            let rest = self.count(pos + 1, group)?;
+            res = self.counting.add(res, rest)?;
+        }
 
-            if let None | Some(SpringCondition::Damaged) = constraint {
-                // try replacing the first element with Damaged
-                res += num_arrangements(
-                    once(SpringCondition::Damaged)
-                        .chain(row.iter().copied())
-                        .collect::<Vec<_>>(),
-                    group_sizes.clone(),
-                    start_constraint,
-                );
+        if spring != SpringCondition::Operational && group < self.group_sizes.len() {
+            // start the next group here, which must be followed by an operational spring or the end of the row
+            let end = pos + self.group_sizes[group].get();
+            if end <= self.row.len()
+                && !self.row[pos..end].contains(&SpringCondition::Operational)
+                && self.row.get(end) != Some(&SpringCondition::Damaged)
+            {
+                let rest = self.count(end + 1, group + 1)?;
+                res = self.counting.add(res, rest)?;
             }
+        }
+
+        self.cache.insert((pos, group), res);
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Arrangement count overflowed; try again with --modulus")]
+struct OverflowError;
 
-            res
+/// Arithmetic on counts, optionally modulo some number no larger than u64::MAX
+#[derive(Clone, Copy)]
+struct Counting {
+    modulus: Option<u128>,
+}
+
+impl Counting {
+    fn add(&self, a: u128, b: u128) -> Result<u128, OverflowError> {
+        match self.modulus {
+            Some(m) => Ok((a + b) % m),
+            None => a.checked_add(b).ok_or(OverflowError),
         }
     }
 }
 
+fn count(
+    args: &Args,
+    row: &[SpringCondition],
+    group_sizes: &[GroupSize],
+    repeat: usize,
+) -> Result<u128, OverflowError> {
+    let counting = Counting {
+        modulus: args.modulus.map(u128::from),
+    };
+    // row is repeated, joined by 'Unknown'
+    let row_len = row.len() * repeat + repeat - 1;
+    let row = row
+        .iter()
+        .copied()
+        .chain(once(SpringCondition::Unknown))
+        .cycle()
+        .take(row_len)
+        .collect::<Vec<_>>();
+
+    // group sizes are repeated
+    let group_sizes_len = group_sizes.len() * repeat;
+    let group_sizes = group_sizes
+        .iter()
+        .copied()
+        .cycle()
+        .take(group_sizes_len)
+        .collect::<Vec<_>>();
+
+    match args.solver {
+        Solver::Dp => num_arrangements(&row, &group_sizes, counting),
+        Solver::Memo => MemoCounter::new(&row, &group_sizes, counting).count(0, 0),
+    }
+}
+
+const SLOWEST_SHOWN: usize = 3;
+
+/// Slowest records so far, as (record index, duration), slowest first
+fn update_slowest(slowest: &mut Vec<(usize, Duration)>, record: usize, duration: Duration) {
+    let pos = slowest.partition_point(|&(_, d)| d >= duration);
+    if pos < SLOWEST_SHOWN {
+        slowest.insert(pos, (record, duration));
+        slowest.truncate(SLOWEST_SHOWN);
+    }
+}
+
+fn report_progress(done: usize, total: usize, slowest: &[(usize, Duration)]) {
+    let slowest: Vec<String> = slowest
+        .iter()
+        .map(|(i, d)| format!("line {} ({d:.2?})", i + 1))
+        .collect();
+    eprint!(
+        "\r{done}/{total} records; slowest: {}\x1b[K",
+        slowest.join(", ")
+    );
+    if done == total {
+        eprintln!();
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    let mut result = 0;
+    let mut records = Vec::new();
     for line in stdin().lines() {
         let line = line?;
         let (_, record) = record(&line).map_err(|e| e.to_owned())?;
+        records.push(record);
+    }
 
-        result += match args.part {
-            Part::Part1 => num_arrangements(record.row, record.group_sizes, None),
-            Part::Part2 => {
-                // row is repeated 5 times, joined by 'Unknown'
-                let row_len = record.row.len() * 5 + 4;
-                let row = record
-                    .row
-                    .into_iter()
-                    .chain(once(SpringCondition::Unknown))
-                    .cycle()
-                    .take(row_len)
-                    .collect::<Vec<_>>();
-
-                // group sizes are repeated 5 times
-                let group_sizes_len = record.group_sizes.len() * 5;
-                let group_sizes = record
-                    .group_sizes
-                    .into_iter()
-                    .cycle()
-                    .take(group_sizes_len)
-                    .collect::<Vec<_>>();
-
-                num_arrangements(row, group_sizes, None)
-            }
+    let repeat = match args.part {
+        Part::Part1 => 1,
+        Part::Part2 { repeat } => repeat,
+    };
+
+    let mut counts = Vec::new();
+    let mut slowest = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        let start = Instant::now();
+        counts.push(count(&args, &record.row, &record.group_sizes, repeat)?);
+
+        if args.progress {
+            update_slowest(&mut slowest, i, start.elapsed());
+            report_progress(counts.len(), records.len(), &slowest);
+        }
+    }
+
+    let counting = Counting {
+        modulus: args.modulus.map(u128::from),
+    };
+    let mut result = 0;
+    for &count in &counts {
+        result = counting.add(result, count)?;
+    }
+    if args.per_line {
+        for (i, &count) in counts.iter().enumerate() {
+            let share = if result == 0 {
+                0.
+            } else {
+                100. * count as f64 / result as f64
+            };
+            println!("Line {}: {count} ({share:.4}%)", i + 1);
         }
     }
 
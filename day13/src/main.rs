@@ -1,15 +1,48 @@
-use std::{io::stdin, iter::from_fn};
+use std::{
+    fs::File,
+    io::{stdin, BufRead, BufReader, BufWriter, Write},
+    iter::from_fn,
+    path::PathBuf,
+};
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{ensure, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// Describe the reflection found in each pattern
+    #[arg(long, global = true)]
+    explain: bool,
+    /// How to score patterns with more than one valid reflection
+    #[arg(long, global = true, value_enum, default_value_t = Strategy::All)]
+    strategy: Strategy,
+    /// Read patterns from this file instead of stdin
+    #[arg(long, global = true, value_name = "PATH")]
+    input: Option<PathBuf>,
+    /// Write each smudged pattern, with the smudge fixed and marked, to this file; part2 only
+    #[arg(long, global = true, value_name = "PATH")]
+    export: Option<PathBuf>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Strategy {
+    /// Score only the first reflection found, checking vertical mirrors before horizontal
+    First,
+    /// Score every reflection
+    All,
+    /// Fail if any pattern has more than one reflection
+    Error,
+}
+
+#[derive(Debug, Error)]
+#[error("Patterns {0:?} have more than one valid reflection")]
+struct AmbiguousPatternsError(Vec<usize>);
+
 #[derive(PartialEq, Eq, Subcommand)]
 enum Part {
     Part1,
@@ -26,10 +59,19 @@ enum PatternSpace {
 #[error("`{0}` is an invalid grid space")]
 struct ParsePatternError(char);
 
+impl From<PatternSpace> for char {
+    fn from(value: PatternSpace) -> Self {
+        match value {
+            PatternSpace::Ash => '.',
+            PatternSpace::Rocks => '#',
+        }
+    }
+}
+
 impl TryFrom<char> for PatternSpace {
     type Error = ParsePatternError;
 
-    fn try_from(value: char) -> std::prelude::v1::Result<Self, Self::Error> {
+    fn try_from(value: char) -> Result<Self, Self::Error> {
         match value {
             '.' => Ok(PatternSpace::Ash),
             '#' => Ok(PatternSpace::Rocks),
@@ -41,78 +83,359 @@ impl TryFrom<char> for PatternSpace {
 type PatternRow = Vec<PatternSpace>;
 type Pattern = Vec<PatternRow>;
 
-fn patterns() -> impl Iterator<Item = Pattern> {
-    let mut inp = stdin().lines();
+#[derive(Debug, Error)]
+enum ReadPatternError {
+    #[error("Pattern {pattern}, line {line}, column {column}: {source}")]
+    InvalidSpace {
+        pattern: usize,
+        line: usize,
+        column: usize,
+        source: ParsePatternError,
+    },
+    #[error("Pattern {pattern}, line {line}: expected {expected} columns but found {found}")]
+    NotRectangular {
+        pattern: usize,
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Blank-line separated patterns, numbered from 1, read lazily from `inp`
+fn patterns(inp: impl BufRead) -> impl Iterator<Item = Result<Pattern, ReadPatternError>> {
+    let mut inp = inp.lines().enumerate();
+    let mut pattern_idx = 0;
 
     from_fn(move || {
+        pattern_idx += 1;
         let mut cur = Pattern::new();
-        while let Some(line) = inp.next() {
-            let line = line.expect("Error reading input");
+        for (line_idx, line) in inp.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
             if line.is_empty() {
+                if cur.is_empty() {
+                    // tolerate repeated blank lines between patterns
+                    continue;
+                }
                 break;
             };
 
-            cur.push(
-                line.chars()
-                    .map(|c| PatternSpace::try_from(c).expect("Error parsing input"))
-                    .collect::<Vec<_>>(),
-            );
+            let row = line
+                .chars()
+                .enumerate()
+                .map(|(column, c)| {
+                    PatternSpace::try_from(c).map_err(|source| ReadPatternError::InvalidSpace {
+                        pattern: pattern_idx,
+                        line: line_idx + 1,
+                        column: column + 1,
+                        source,
+                    })
+                })
+                .collect::<Result<PatternRow, _>>();
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if let Some(first) = cur.first() {
+                if first.len() != row.len() {
+                    return Some(Err(ReadPatternError::NotRectangular {
+                        pattern: pattern_idx,
+                        line: line_idx + 1,
+                        expected: first.len(),
+                        found: row.len(),
+                    }));
+                }
+            }
+            cur.push(row);
         }
 
         if cur.is_empty() {
             return None;
         }
-        Some(cur)
+        Some(Ok(cur))
     })
 }
 
-fn summarize(patterns: impl Iterator<Item = Pattern>, part: Part) -> usize {
+type Coords = (usize, usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Orientation {
+    Vertical,
+    Horizontal,
+}
+
+#[derive(Debug)]
+struct Reflection {
+    orientation: Orientation,
+    /// Number of columns (or rows) before the mirror
+    index: usize,
+    /// The cell which must be flipped for the reflection to hold, if any
+    smudge: Option<Coords>,
+}
+
+impl Reflection {
+    fn score(&self) -> usize {
+        match self.orientation {
+            Orientation::Vertical => self.index,
+            Orientation::Horizontal => 100 * self.index,
+        }
+    }
+}
+
+/// Every mirror line across which the pattern reflects with exactly the number of mismatches required by the part
+fn find_reflections(pattern: &Pattern, part: &Part) -> Vec<Reflection> {
+    let allowed_differences = match part {
+        Part::Part1 => 0,
+        Part::Part2 => 1,
+    };
+    let rows = pattern.len();
+    let cols = pattern.first().expect("Zero length Pattern").len();
+    let mut res = Vec::new();
+
+    for i in 1..cols {
+        let different: Vec<Coords> = pattern
+            .iter()
+            .enumerate()
+            .flat_map(|(r, row)| {
+                (0..i)
+                    .rev()
+                    .zip(i..cols)
+                    .filter(move |&(x, y)| row[x] != row[y])
+                    .map(move |(x, _)| (r, x))
+            })
+            .take(allowed_differences + 1)
+            .collect();
+        if different.len() == allowed_differences {
+            res.push(Reflection {
+                orientation: Orientation::Vertical,
+                index: i,
+                smudge: different.first().copied(),
+            });
+        }
+    }
+
+    for i in 1..rows {
+        let different: Vec<Coords> = (0..i)
+            .rev()
+            .zip(i..rows)
+            .flat_map(|(rx, ry)| {
+                (0..cols)
+                    .filter(move |&c| pattern[rx][c] != pattern[ry][c])
+                    .map(move |c| (rx, c))
+            })
+            .take(allowed_differences + 1)
+            .collect();
+        if different.len() == allowed_differences {
+            res.push(Reflection {
+                orientation: Orientation::Horizontal,
+                index: i,
+                smudge: different.first().copied(),
+            });
+        }
+    }
+
+    res
+}
+
+fn explain(pattern_idx: usize, reflections: &[Reflection]) {
+    if reflections.is_empty() {
+        println!("Pattern {pattern_idx}: no reflection found");
+    }
+    for reflection in reflections {
+        let (kind, axis) = match reflection.orientation {
+            Orientation::Vertical => ("vertical", "column"),
+            Orientation::Horizontal => ("horizontal", "row"),
+        };
+        print!(
+            "Pattern {pattern_idx}: {kind} mirror after {axis} {}",
+            reflection.index
+        );
+        if let Some((r, c)) = reflection.smudge {
+            print!(", smudge at row {}, column {}", r + 1, c + 1);
+        }
+        println!();
+    }
+}
+
+/// Write the pattern with the smudge flipped, marking the flipped cell with `O` (now rocks) or `o` (now ash)
+fn export_smudge(
+    out: &mut impl Write,
+    pattern_idx: usize,
+    pattern: &Pattern,
+    reflection: &Reflection,
+    (sr, sc): Coords,
+) -> std::io::Result<()> {
+    let (kind, axis) = match reflection.orientation {
+        Orientation::Vertical => ("vertical", "column"),
+        Orientation::Horizontal => ("horizontal", "row"),
+    };
+    writeln!(
+        out,
+        "Pattern {pattern_idx}: smudge at row {}, column {}; {kind} mirror after {axis} {}",
+        sr + 1,
+        sc + 1,
+        reflection.index
+    )?;
+
+    for (r, row) in pattern.iter().enumerate() {
+        let line: String = row
+            .iter()
+            .enumerate()
+            .map(|(c, &space)| match ((r, c) == (sr, sc), space) {
+                (true, PatternSpace::Ash) => 'O',
+                (true, PatternSpace::Rocks) => 'o',
+                (false, space) => space.into(),
+            })
+            .collect();
+        writeln!(out, "{line}")?;
+    }
+    writeln!(out)
+}
+
+/// Every reflection found in a single pattern
+struct PatternResult {
+    reflections: Vec<Reflection>,
+}
+
+impl PatternResult {
+    fn score(&self, strategy: Strategy) -> usize {
+        match strategy {
+            Strategy::First => self.reflections.first().map_or(0, Reflection::score),
+            Strategy::All | Strategy::Error => self.reflections.iter().map(Reflection::score).sum(),
+        }
+    }
+}
+
+fn analyze_all(patterns: &[Pattern], part: &Part) -> Vec<PatternResult> {
+    #[cfg(feature = "parallel")]
+    let patterns = patterns.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let patterns = patterns.iter();
+
+    // collecting keeps the results in input order
+    patterns
+        .map(|pattern| PatternResult {
+            reflections: find_reflections(pattern, part),
+        })
+        .collect()
+}
+
+/// Number of patterns held in memory at once
+const BATCH_SIZE: usize = 1024;
+
+fn summarize(
+    patterns: impl Iterator<Item = Result<Pattern, ReadPatternError>>,
+    args: &Args,
+) -> Result<usize> {
+    let mut export = match &args.export {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+
     let mut res = 0;
-    for pattern in patterns {
-        let rows = pattern.len();
-        let cols = pattern.get(0).expect("Zero length Pattern").len();
-
-        for i in 1..cols {
-            let num_different = pattern
-                .iter()
-                .flat_map(|row| {
-                    row[..i]
-                        .iter()
-                        .rev()
-                        .zip(row[i..].iter())
-                        .filter(|(x, y)| x != y)
-                })
-                .count();
-            if (part == Part::Part1 && num_different == 0)
-                || (part == Part::Part2 && num_different == 1)
-            {
-                res += i
-            }
+    let mut ambiguous = Vec::new();
+    let mut patterns = patterns.peekable();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut offset = 0;
+    while patterns.peek().is_some() {
+        batch.clear();
+        for pattern in patterns.by_ref().take(BATCH_SIZE) {
+            batch.push(pattern?);
         }
 
-        for i in 1..rows {
-            let num_different = pattern[..i]
-                .iter()
-                .rev()
-                .zip(pattern[i..].iter())
-                .flat_map(|(rx, ry)| rx.iter().zip(ry.iter()).filter(|(x, y)| x != y))
-                .count();
-            if (part == Part::Part1 && num_different == 0)
-                || (part == Part::Part2 && num_different == 1)
-            {
-                res += 100 * i
+        for (i, (pattern, result)) in batch
+            .iter()
+            .zip(analyze_all(&batch, &args.part))
+            .enumerate()
+        {
+            let pattern_idx = offset + i + 1;
+            if args.explain {
+                explain(pattern_idx, &result.reflections);
+            }
+            if let Some(out) = export.as_mut() {
+                for reflection in &result.reflections {
+                    if let Some(smudge) = reflection.smudge {
+                        export_smudge(out, pattern_idx, pattern, reflection, smudge)?;
+                    }
+                }
+            }
+            if result.reflections.len() > 1 {
+                ambiguous.push(pattern_idx);
             }
+            res += result.score(args.strategy);
         }
+        offset += batch.len();
     }
 
-    res
+    if !ambiguous.is_empty() {
+        if args.strategy == Strategy::Error {
+            return Err(AmbiguousPatternsError(ambiguous).into());
+        }
+        eprintln!("Warning: patterns {ambiguous:?} have more than one valid reflection");
+    }
+
+    if let Some(mut out) = export {
+        out.flush()?;
+    }
+
+    Ok(res)
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    ensure!(
+        args.export.is_none() || args.part == Part::Part2,
+        "--export only applies to part2, where patterns have smudges"
+    );
 
-    let res = summarize(patterns(), args.part);
+    let res = match &args.input {
+        Some(path) => summarize(patterns(BufReader::new(File::open(path)?)), &args)?,
+        None => summarize(patterns(stdin().lock()), &args)?,
+    };
     println!("{res}");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+#.##..##.
+..#.##.#.
+##......#
+##......#
+..#.##.#.
+..##..##.
+#.#.##.#.
+
+#...##..#
+#....#..#
+..##..###
+#####.##.
+#####.##.
+..##..###
+#....#..#
+";
+
+    fn summarize_example(part: &str) -> usize {
+        let args = Args::parse_from(["day13", part]);
+        summarize(patterns(EXAMPLE.as_bytes()), &args).unwrap()
+    }
+
+    #[test]
+    fn part1_example() {
+        assert_eq!(summarize_example("part1"), 405);
+    }
+
+    #[test]
+    fn part2_example() {
+        assert_eq!(summarize_example("part2"), 400);
+    }
+}
@@ -1,25 +1,51 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    io::{stdin, BufRead},
-    mem::swap,
+    collections::hash_map::DefaultHasher,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{stdin, BufRead, BufWriter, Write},
+    iter::once,
+    path::{Path, PathBuf},
 };
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use fixedbitset::FixedBitSet;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Scan)]
+    solver: Solver,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Scan each line of a grid of spaces
+    Scan,
+    /// Slide whole runs of rounded rocks within per-row and per-column bitmasks
+    Bitset,
 }
 
 #[derive(PartialEq, Eq, Subcommand)]
 enum Part {
-    Part1,
+    Part1 {
+        /// Direction to tilt the platform
+        #[arg(long, value_enum, default_value_t = Direction::North)]
+        direction: Direction,
+    },
     Part2 {
         #[arg(default_value_t = 1000000000)]
         cycles: usize,
+        /// Tilts making up one spin cycle, as a sequence of `N`, `S`, `E` and `W`
+        #[arg(long, value_parser = tilts, default_value = "NWSE")]
+        directions: Tilts,
+        /// Write the total load after every spin cycle until the cycle is detected to this CSV file
+        #[arg(long, value_name = "PATH")]
+        export: Option<PathBuf>,
     },
 }
 
@@ -56,30 +82,12 @@ fn row(line: &str) -> Result<Row, ParseSpaceError> {
 type Grid = Vec<Row>;
 
 fn grid(inp: impl BufRead) -> Result<Grid> {
-    inp.lines()
-        .into_iter()
-        .map(|line| Ok(row(&line?)?))
-        .collect()
+    inp.lines().map(|line| Ok(row(&line?)?)).collect()
 }
 
 type Coords = (usize, usize);
 
-fn all_rounded_rocks(grid: &Grid) -> Vec<Coords> {
-    grid.iter()
-        .enumerate()
-        .flat_map(|(i, row)| {
-            row.iter().enumerate().filter_map(move |(j, space)| {
-                if let Space::RoundedRock = space {
-                    Some((i, j))
-                } else {
-                    None
-                }
-            })
-        })
-        .collect()
-}
-
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
 enum Direction {
     North,
     South,
@@ -87,137 +95,381 @@ enum Direction {
     West,
 }
 
-fn get_coord(grid: &Grid, (r, c): Coords, direction: Direction) -> Option<Coords> {
-    match direction {
-        Direction::North => {
-            if r == 0 {
-                None
-            } else {
-                Some((r - 1, c))
-            }
+#[derive(Debug, Error)]
+#[error("`{0}` is an invalid Direction")]
+struct ParseDirectionError(char);
+
+impl TryFrom<char> for Direction {
+    type Error = ParseDirectionError;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value.to_ascii_uppercase() {
+            'N' => Ok(Self::North),
+            'S' => Ok(Self::South),
+            'E' => Ok(Self::East),
+            'W' => Ok(Self::West),
+            _ => Err(ParseDirectionError(value)),
         }
-        Direction::South => {
-            if r + 1 == grid.len() {
-                None
-            } else {
-                Some((r + 1, c))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("At least one tilt is required")]
+struct EmptyTiltsError;
+
+/// A sequence of tilts applied in order
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Tilts(Vec<Direction>);
+
+fn tilts(s: &str) -> Result<Tilts> {
+    let directions = s
+        .chars()
+        .map(Direction::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    if directions.is_empty() {
+        return Err(EmptyTiltsError.into());
+    }
+    Ok(Tilts(directions))
+}
+
+/// Coordinates of the `i`th space of `line`, counting from the edge rocks roll towards
+fn line_coords(grid: &Grid, line: usize, i: usize, direction: Direction) -> Coords {
+    match direction {
+        Direction::North => (i, line),
+        Direction::South => (grid.len() - 1 - i, line),
+        Direction::East => (line, grid[0].len() - 1 - i),
+        Direction::West => (line, i),
+    }
+}
+
+/// A representation of the platform that rocks can be rolled around
+trait Platform: Clone {
+    fn roll(self, direction: Direction) -> Self;
+
+    /// The positions of the rounded rocks, which are the only part of the platform that changes between
+    /// cycles
+    fn compact_state(&self) -> FixedBitSet;
+
+    /// The load on the north support beams
+    fn total_load(&self) -> usize;
+}
+
+impl Platform for Grid {
+    fn roll(mut self, direction: Direction) -> Self {
+        let (lines, len) = match direction {
+            Direction::North | Direction::South => (self[0].len(), self.len()),
+            Direction::East | Direction::West => (self.len(), self[0].len()),
+        };
+
+        for line in 0..lines {
+            // the closest space to the edge that a rounded rock can still slide into
+            let mut free = 0;
+            for i in 0..len {
+                let (r, c) = line_coords(&self, line, i, direction);
+                match self[r][c] {
+                    Space::CubeShapedRock => free = i + 1,
+                    Space::RoundedRock => {
+                        if free != i {
+                            let (fr, fc) = line_coords(&self, line, free, direction);
+                            self[fr][fc] = Space::RoundedRock;
+                            self[r][c] = Space::Empty;
+                        }
+                        free += 1;
+                    }
+                    Space::Empty => {}
+                }
             }
         }
-        Direction::East => {
-            if c + 1 == grid[0].len() {
-                None
-            } else {
-                Some((r, c + 1))
+
+        self
+    }
+
+    fn compact_state(&self) -> FixedBitSet {
+        let width = self[0].len();
+        let mut state = FixedBitSet::with_capacity(self.len() * width);
+        for (r, row) in self.iter().enumerate() {
+            for (c, space) in row.iter().enumerate() {
+                if let Space::RoundedRock = space {
+                    state.insert(r * width + c);
+                }
             }
         }
-        Direction::West => {
-            if c == 0 {
-                None
-            } else {
-                Some((r, c - 1))
-            }
+        state
+    }
+
+    fn total_load(&self) -> usize {
+        self.iter()
+            .enumerate()
+            .map(|(i, row)| {
+                (self.len() - i)
+                    * row
+                        .iter()
+                        .filter(|space| matches!(space, Space::RoundedRock))
+                        .count()
+            })
+            .sum()
+    }
+}
+
+/// Rounded and cube-shaped rocks along each of a set of parallel lines, one bit per space
+#[derive(Clone)]
+struct Lanes {
+    rounded: Vec<FixedBitSet>,
+    cubes: Vec<FixedBitSet>,
+}
+
+/// The platform kept both as row and column lanes, so that every tilt slides along whole lanes
+#[derive(Clone)]
+struct BitGrid {
+    height: usize,
+    width: usize,
+    rows: Lanes,
+    cols: Lanes,
+}
+
+/// Flip lanes of `len` bits into `len` lanes, one bit per original lane
+fn transpose(lanes: &[FixedBitSet], len: usize) -> Vec<FixedBitSet> {
+    let mut res = vec![FixedBitSet::with_capacity(lanes.len()); len];
+    for (i, lane) in lanes.iter().enumerate() {
+        for j in lane.ones() {
+            res[j].insert(i);
         }
     }
+    res
 }
 
-fn do_swap(grid: &mut Grid, (r, c): Coords, (nr, nc): Coords) {
-    if c == nc {
-        let (x, y) = grid.split_at_mut(r.max(nr));
-        swap(&mut x[r.min(nr)][c], &mut y[0][c]);
-    } else if r == nr {
-        let (x, y) = grid[r].split_at_mut(c.max(nc));
-        swap(&mut x[c.min(nc)], &mut y[0]);
-    } else {
-        unreachable!()
+/// Move every run of rounded rocks between cube-shaped rocks to the start or end of its run
+fn slide(rounded: &mut FixedBitSet, cubes: &FixedBitSet, towards_start: bool) {
+    let mut start = 0;
+    for end in cubes.ones().chain(once(rounded.len())) {
+        let n = rounded.count_ones(start..end);
+        rounded.set_range(start..end, false);
+        if towards_start {
+            rounded.set_range(start..start + n, true);
+        } else {
+            rounded.set_range(end - n..end, true);
+        }
+        start = end + 1;
     }
 }
 
-fn roll(mut grid: Grid, direction: Direction) -> Grid {
-    let mut rocks = all_rounded_rocks(&grid);
-    rocks.sort_by_key(|&(row, col)| match direction {
-        Direction::North => row,
-        Direction::South => grid.len() - row,
-        Direction::East => grid[0].len() - col,
-        Direction::West => col,
-    });
+/// Lanes slide independently, so with the `parallel` feature they are slid on separate threads
+fn slide_all(lanes: &mut Lanes, towards_start: bool) {
+    #[cfg(feature = "parallel")]
+    let rounded = lanes.rounded.par_iter_mut();
+    #[cfg(not(feature = "parallel"))]
+    let rounded = lanes.rounded.iter_mut();
+
+    rounded
+        .zip(&lanes.cubes)
+        .for_each(|(rounded, cubes)| slide(rounded, cubes, towards_start));
+}
+
+impl From<&Grid> for BitGrid {
+    fn from(grid: &Grid) -> Self {
+        let height = grid.len();
+        let width = grid.first().map_or(0, Vec::len);
+        let mut rows = Lanes {
+            rounded: vec![FixedBitSet::with_capacity(width); height],
+            cubes: vec![FixedBitSet::with_capacity(width); height],
+        };
+        for (r, row) in grid.iter().enumerate() {
+            for (c, space) in row.iter().enumerate() {
+                match space {
+                    Space::RoundedRock => rows.rounded[r].insert(c),
+                    Space::CubeShapedRock => rows.cubes[r].insert(c),
+                    Space::Empty => {}
+                }
+            }
+        }
+        let cols = Lanes {
+            rounded: transpose(&rows.rounded, width),
+            cubes: transpose(&rows.cubes, width),
+        };
+        Self {
+            height,
+            width,
+            rows,
+            cols,
+        }
+    }
+}
 
-    for original_coords in rocks {
-        let mut coords = original_coords;
-        while let Some(new_coords) = get_coord(&grid, coords, direction) {
-            let Space::Empty = grid[new_coords.0][new_coords.1] else {
-                break;
-            };
-            coords = new_coords;
+impl Platform for BitGrid {
+    fn roll(mut self, direction: Direction) -> Self {
+        match direction {
+            Direction::North | Direction::South => {
+                slide_all(&mut self.cols, direction == Direction::North);
+                self.rows.rounded = transpose(&self.cols.rounded, self.height);
+            }
+            Direction::East | Direction::West => {
+                slide_all(&mut self.rows, direction == Direction::West);
+                self.cols.rounded = transpose(&self.rows.rounded, self.width);
+            }
         }
-        if coords != original_coords {
-            do_swap(&mut grid, original_coords, coords);
+        self
+    }
+
+    fn compact_state(&self) -> FixedBitSet {
+        let mut state = FixedBitSet::with_capacity(self.height * self.width);
+        for (r, row) in self.rows.rounded.iter().enumerate() {
+            for c in row.ones() {
+                state.insert(r * self.width + c);
+            }
         }
+        state
     }
 
-    grid
+    fn total_load(&self) -> usize {
+        self.rows
+            .rounded
+            .iter()
+            .enumerate()
+            .map(|(r, row)| (self.height - r) * row.count_ones(..))
+            .sum()
+    }
 }
 
-fn get_total_load(grid: &Grid) -> usize {
-    grid.iter()
-        .enumerate()
-        .map(|(i, row)| {
-            (grid.len() - i)
-                * row
-                    .iter()
-                    .filter(|space| {
-                        if let Space::RoundedRock = space {
-                            true
-                        } else {
-                            false
-                        }
-                    })
-                    .count()
-        })
-        .sum()
+fn spin_cycle<P: Platform>(platform: P, tilts: &Tilts) -> P {
+    tilts
+        .0
+        .iter()
+        .fold(platform, |platform, &direction| platform.roll(direction))
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn state_hash(state: &FixedBitSet) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    hasher.finish()
+}
 
-    let mut grid = grid(stdin().lock())?;
+/// A platform along with its compact state, compared by hash before falling back to the full state
+#[derive(Clone)]
+struct Snapshot<P> {
+    platform: P,
+    state: FixedBitSet,
+    hash: u64,
+}
 
-    match args.part {
-        Part::Part1 => {
-            grid = roll(grid, Direction::North);
+impl<P: Platform> Snapshot<P> {
+    fn new(platform: P) -> Self {
+        let state = platform.compact_state();
+        let hash = state_hash(&state);
+        Self {
+            platform,
+            state,
+            hash,
         }
-        Part::Part2 { cycles } => {
-            let mut i = 0;
-            let mut seen: HashMap<Grid, usize> = HashMap::new();
-            seen.insert(grid.to_owned(), 0);
-
-            let cycle_length = loop {
-                grid = roll(grid, Direction::North);
-                grid = roll(grid, Direction::West);
-                grid = roll(grid, Direction::South);
-                grid = roll(grid, Direction::East);
-                i += 1;
+    }
 
-                match seen.entry(grid.to_owned()) {
-                    Entry::Occupied(e) => {
-                        break i - e.get();
-                    }
-                    Entry::Vacant(e) => e.insert(i),
-                };
-            };
-            while i + cycle_length <= cycles {
-                i += cycle_length;
+    fn spin(self, tilts: &Tilts) -> Self {
+        Self::new(spin_cycle(self.platform, tilts))
+    }
+
+    fn same_state(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.state == other.state
+    }
+}
+
+/// Spin until a state repeats, returning the platform after the first repeat, its step and the cycle length.
+/// `on_spin` sees the initial platform and the platform after every spin cycle until the cycle is detected.
+fn find_cycle<P: Platform>(
+    initial: &P,
+    tilts: &Tilts,
+    mut on_spin: impl FnMut(&P),
+) -> (P, usize, usize) {
+    // Brent's algorithm: only two snapshots are held at any time, however long the cycle takes to start
+    on_spin(initial);
+    let mut power = 1;
+    let mut cycle_length = 1;
+    let mut tortoise = Snapshot::new(initial.to_owned());
+    let mut hare = Snapshot::new(initial.to_owned()).spin(tilts);
+    on_spin(&hare.platform);
+    while !tortoise.same_state(&hare) {
+        if power == cycle_length {
+            tortoise = hare.clone();
+            power *= 2;
+            cycle_length = 0;
+        }
+        hare = hare.spin(tilts);
+        on_spin(&hare.platform);
+        cycle_length += 1;
+    }
+
+    let mut tortoise = Snapshot::new(initial.to_owned());
+    let mut hare = (0..cycle_length).fold(tortoise.clone(), |hare, _| hare.spin(tilts));
+    let mut offset = 0;
+    while !tortoise.same_state(&hare) {
+        tortoise = tortoise.spin(tilts);
+        hare = hare.spin(tilts);
+        offset += 1;
+    }
+
+    (hare.platform, offset + cycle_length, cycle_length)
+}
+
+/// Write one `cycle,load` row per spin cycle, then report the detected offset and period on stderr so the file stays
+/// plain CSV
+fn export_loads(path: &Path, loads: &[usize], offset: usize, period: usize) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "cycle,load")?;
+    for (i, load) in loads.iter().enumerate() {
+        writeln!(out, "{i},{load}")?;
+    }
+    out.flush()?;
+    eprintln!(
+        "Wrote {} loads to {}: offset {offset}, period {period}",
+        loads.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn total_load<P: Platform>(mut platform: P, part: &Part) -> Result<usize> {
+    match part {
+        Part::Part1 { direction } => {
+            platform = platform.roll(*direction);
+        }
+        Part::Part2 {
+            cycles,
+            directions,
+            export,
+        } => {
+            let cycles = *cycles;
+            let mut loads = Vec::new();
+            let (cycled, mut i, cycle_length) = find_cycle(&platform, directions, |platform| {
+                if export.is_some() {
+                    loads.push(platform.total_load());
+                }
+            });
+            if let Some(path) = export {
+                export_loads(path, &loads, i - cycle_length, cycle_length)?;
+            }
+            if i <= cycles {
+                platform = cycled;
+                i += (cycles - i) / cycle_length * cycle_length;
+            } else {
+                i = 0;
             }
             while i < cycles {
-                grid = roll(grid, Direction::North);
-                grid = roll(grid, Direction::West);
-                grid = roll(grid, Direction::South);
-                grid = roll(grid, Direction::East);
+                platform = spin_cycle(platform, directions);
                 i += 1;
             }
         }
     }
 
-    println!("{}", get_total_load(&grid));
+    Ok(platform.total_load())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let grid = grid(stdin().lock())?;
+    let load = match args.solver {
+        Solver::Scan => total_load(grid, &args.part)?,
+        Solver::Bitset => total_load(BitGrid::from(&grid), &args.part)?,
+    };
+    println!("{load}");
 
     Ok(())
 }
@@ -0,0 +1,340 @@
+use std::{array::from_fn, collections::HashMap, fmt::Display};
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("`{0}` contains non-ascii characters")]
+pub struct NonAsciiError(pub String);
+
+/// The Holiday ASCII String Helper algorithm
+pub fn hash(s: &str) -> Result<u8, NonAsciiError> {
+    if !s.is_ascii() {
+        return Err(NonAsciiError(s.to_owned()));
+    }
+
+    Ok(s.bytes()
+        .fold(0, |s, c| ((s as u32 + c as u32) * 17 % 256) as u8))
+}
+
+/// The sum of the [`hash`] of each non-empty comma-separated token
+pub fn sum_hashes(tokens: &str) -> Result<u64, NonAsciiError> {
+    tokens
+        .split(',')
+        .filter(|token| !token.is_empty())
+        .map(|token| Ok(u64::from(hash(token)?)))
+        .sum()
+}
+
+/// Tokens hashed side by side by [`sum_hashes_simd`]
+const LANES: usize = 32;
+
+/// Characters of each lane laid out side by side at a time
+const BLOCK: usize = 64;
+
+/// The same result as [`sum_hashes`], hashing [`LANES`] tokens at once so that it vectorizes.
+///
+/// The input is split at commas into a stretch per lane, and every lane steps through its own stretch a character
+/// at a time, a block of characters from each laid out side by side. A comma adds the lane's hash to its sum and
+/// starts the next token, so lanes whose stretch has ended are padded with commas, which add nothing.
+pub fn sum_hashes_simd(tokens: &str) -> Result<u64, NonAsciiError> {
+    if !tokens.is_ascii() {
+        // leave it to the scalar version to pick out the token
+        return sum_hashes(tokens);
+    }
+    let bytes = tokens.as_bytes();
+
+    let stretch = bytes.len().div_ceil(LANES);
+    let mut starts = [bytes.len(); LANES + 1];
+    starts[0] = 0;
+    for lane in 1..LANES {
+        let from = (lane * stretch).max(starts[lane - 1]).min(bytes.len());
+        starts[lane] = bytes[from..]
+            .iter()
+            .position(|&c| c == b',')
+            .map_or(bytes.len(), |i| from + i + 1);
+    }
+    let stretches: [&[u8]; LANES] = from_fn(|lane| &bytes[starts[lane]..starts[lane + 1]]);
+    let longest = stretches
+        .iter()
+        .map(|stretch| stretch.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut hashes = [0u8; LANES];
+    let mut sums = [0u64; LANES];
+    let mut rows = [[b','; LANES]; BLOCK];
+    // reaching one past the longest stretch pads every lane with a final comma
+    for offset in (0..=longest).step_by(BLOCK) {
+        for (lane, stretch) in stretches.iter().enumerate() {
+            let block = stretch.get(offset..).unwrap_or_default();
+            let block = &block[..block.len().min(BLOCK)];
+            for (row, &c) in rows.iter_mut().zip(block) {
+                row[lane] = c;
+            }
+            for row in &mut rows[block.len()..] {
+                row[lane] = b',';
+            }
+        }
+
+        // a block adds at most 255 per row to each lane
+        let mut block_sums = [0u16; LANES];
+        for row in &rows {
+            for ((hash, sum), &c) in hashes.iter_mut().zip(&mut block_sums).zip(row) {
+                let end = c == b',';
+                *sum += if end { u16::from(*hash) } else { 0 };
+                *hash = if end {
+                    0
+                } else {
+                    hash.wrapping_add(c).wrapping_mul(17)
+                };
+            }
+        }
+        for (sum, block_sum) in sums.iter_mut().zip(block_sums) {
+            *sum += u64::from(block_sum);
+        }
+    }
+
+    Ok(sums.iter().sum())
+}
+
+/// Lenses in the order they were inserted. Removed lenses leave a gap, so that updates and removals don't
+/// need to shift the lenses behind them.
+#[derive(Default)]
+struct LensBox {
+    slots: Vec<Option<(String, u32)>>,
+    index: HashMap<String, usize>,
+}
+
+impl LensBox {
+    fn insert(&mut self, label: &str, focal_length: u32) {
+        match self.index.get(label) {
+            Some(&slot) => {
+                if let Some((_, contents)) = &mut self.slots[slot] {
+                    *contents = focal_length;
+                }
+            }
+            None => {
+                self.index.insert(label.to_owned(), self.slots.len());
+                self.slots.push(Some((label.to_owned(), focal_length)));
+            }
+        }
+    }
+
+    fn remove(&mut self, label: &str) -> Option<u32> {
+        let slot = self.index.remove(label)?;
+        let (_, focal_length) = self.slots[slot].take()?;
+
+        // compact once gaps dominate so the box doesn't grow without bound
+        if self.index.len() * 2 < self.slots.len() {
+            self.slots.retain(Option::is_some);
+            for (slot, (label, _)) in self.slots.iter().flatten().enumerate() {
+                self.index.insert(label.to_owned(), slot);
+            }
+        }
+        Some(focal_length)
+    }
+
+    fn lenses(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|(label, focal_length)| (label.as_str(), *focal_length))
+    }
+}
+
+const NUM_BOXES: usize = 256;
+
+/// The Holiday ASCII String Helper Manual Arrangement Procedure: 256 boxes of lenses, each keeping its lenses
+/// in insertion order
+pub struct Hashmap {
+    boxes: Vec<LensBox>,
+}
+
+impl Default for Hashmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hashmap {
+    pub fn new() -> Self {
+        Self {
+            boxes: (0..NUM_BOXES).map(|_| LensBox::default()).collect(),
+        }
+    }
+
+    /// Replace the focal length of the lens with this label, or add it behind the other lenses in its box
+    pub fn insert(&mut self, label: &str, focal_length: u32) -> Result<(), NonAsciiError> {
+        self.boxes[hash(label)? as usize].insert(label, focal_length);
+        Ok(())
+    }
+
+    /// Take out the lens with this label, if present, returning its focal length
+    pub fn remove(&mut self, label: &str) -> Result<Option<u32>, NonAsciiError> {
+        Ok(self.boxes[hash(label)? as usize].remove(label))
+    }
+
+    pub fn focusing_power(&self) -> u64 {
+        self.boxes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, lens_box)| {
+                lens_box
+                    .lenses()
+                    .enumerate()
+                    .map(move |(j, (_, focal_length))| {
+                        (i as u64 + 1) * (j as u64 + 1) * u64::from(focal_length)
+                    })
+            })
+            .sum()
+    }
+}
+
+/// A deliberately simple implementation of the same procedure, scanning each box for the label, to check
+/// [`Hashmap`] against
+#[derive(Default)]
+pub struct ReferenceHashmap {
+    boxes: HashMap<u8, Vec<(String, u32)>>,
+}
+
+impl ReferenceHashmap {
+    pub fn insert(&mut self, label: &str, focal_length: u32) -> Result<(), NonAsciiError> {
+        let inner = self.boxes.entry(hash(label)?).or_default();
+        match inner.iter_mut().find(|(l, _)| l == label) {
+            Some((_, contents)) => *contents = focal_length,
+            None => inner.push((label.to_owned(), focal_length)),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, label: &str) -> Result<Option<u32>, NonAsciiError> {
+        let inner = self.boxes.entry(hash(label)?).or_default();
+        let pos = inner.iter().position(|(l, _)| l == label);
+        Ok(pos.map(|pos| inner.remove(pos).1))
+    }
+
+    pub fn focusing_power(&self) -> u64 {
+        self.boxes
+            .iter()
+            .map(|(&i, inner)| {
+                inner
+                    .iter()
+                    .enumerate()
+                    .map(|(j, &(_, contents))| {
+                        (i as u64 + 1) * (j as u64 + 1) * u64::from(contents)
+                    })
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+}
+
+/// The non-empty boxes, one per line, as in the puzzle walkthrough
+impl Display for Hashmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, lens_box) in self.boxes.iter().enumerate() {
+            let mut lenses = lens_box.lenses().peekable();
+            if lenses.peek().is_none() {
+                continue;
+            }
+            write!(f, "Box {i}:")?;
+            for (label, focal_length) in lenses {
+                write!(f, " [{label} {focal_length}]")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_example() {
+        assert_eq!(hash("HASH"), Ok(52));
+        assert_eq!(hash("rn"), Ok(0));
+        assert_eq!(hash("qp"), Ok(1));
+        assert!(hash("é").is_err());
+    }
+
+    #[test]
+    fn sum_hashes_example() {
+        let steps = "rn=1,cm-,qp=3,cm=2,qp-,pc=4,ot=9,ab=5,pc-,pc=6,ot=7";
+        assert_eq!(sum_hashes(steps), Ok(1320));
+        assert_eq!(sum_hashes_simd(steps), Ok(1320));
+    }
+
+    #[test]
+    fn sum_hashes_simd_matches_sum_hashes() {
+        // tokens of every length up to the whole string, spilling over several batches
+        let s = "abcdefghijklmnopqrstuvwxyz0123456789=-ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+        let tokens: Vec<_> = (0..=s.len()).map(|len| &s[..len]).collect();
+        for n in 0..=tokens.len() {
+            let tokens = tokens[..n].join(",");
+            assert_eq!(sum_hashes_simd(&tokens), sum_hashes(&tokens), "{n} tokens");
+        }
+        assert!(sum_hashes_simd("ab,é").is_err());
+    }
+
+    #[test]
+    fn focusing_power_example() {
+        let mut hashmap = Hashmap::new();
+        hashmap.insert("rn", 1).unwrap();
+        assert_eq!(hashmap.remove("cm"), Ok(None));
+        hashmap.insert("qp", 3).unwrap();
+        hashmap.insert("cm", 2).unwrap();
+        assert_eq!(hashmap.remove("qp"), Ok(Some(3)));
+        hashmap.insert("pc", 4).unwrap();
+        hashmap.insert("ot", 9).unwrap();
+        hashmap.insert("ab", 5).unwrap();
+        assert_eq!(hashmap.remove("pc"), Ok(Some(4)));
+        hashmap.insert("pc", 6).unwrap();
+        hashmap.insert("ot", 7).unwrap();
+
+        assert_eq!(hashmap.focusing_power(), 145);
+    }
+
+    #[test]
+    fn reference_focusing_power_example() {
+        let mut hashmap = ReferenceHashmap::default();
+        hashmap.insert("rn", 1).unwrap();
+        assert_eq!(hashmap.remove("cm"), Ok(None));
+        hashmap.insert("qp", 3).unwrap();
+        hashmap.insert("cm", 2).unwrap();
+        assert_eq!(hashmap.remove("qp"), Ok(Some(3)));
+        hashmap.insert("pc", 4).unwrap();
+        hashmap.insert("ot", 9).unwrap();
+        hashmap.insert("ab", 5).unwrap();
+        assert_eq!(hashmap.remove("pc"), Ok(Some(4)));
+        hashmap.insert("pc", 6).unwrap();
+        hashmap.insert("ot", 7).unwrap();
+
+        assert_eq!(hashmap.focusing_power(), 145);
+    }
+
+    #[test]
+    fn update_keeps_position() {
+        let mut hashmap = Hashmap::new();
+        hashmap.insert("ot", 9).unwrap();
+        hashmap.insert("ab", 5).unwrap();
+        hashmap.insert("ot", 7).unwrap();
+
+        // both labels hash to box 3
+        assert_eq!(hashmap.focusing_power(), 4 * 7 + 4 * 2 * 5);
+    }
+
+    #[test]
+    fn removal_closes_gaps() {
+        let mut hashmap = Hashmap::new();
+        hashmap.insert("ot", 9).unwrap();
+        hashmap.insert("ab", 5).unwrap();
+        hashmap.insert("pc", 6).unwrap();
+        hashmap.remove("ot").unwrap();
+        hashmap.remove("ab").unwrap();
+        hashmap.insert("ab", 1).unwrap();
+
+        assert_eq!(hashmap.focusing_power(), 4 * 6 + 4 * 2);
+    }
+}
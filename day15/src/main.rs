@@ -1,40 +1,76 @@
-use std::{collections::HashMap, io::stdin};
+mod hashmap;
+
+use std::{
+    hint::black_box,
+    io::{self, stdin, BufRead},
+    str::from_utf8,
+    time::Instant,
+};
 
 use anyhow::{ensure, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alphanumeric1, digit1},
-    combinator::{map, map_res, recognize},
-    sequence::{separated_pair, terminated},
+    bytes::complete::{escaped_transform, is_not, tag},
+    character::complete::{anychar, char, digit1},
+    combinator::{all_consuming, map, map_res, recognize, verify},
+    sequence::{delimited, separated_pair, terminated},
     IResult,
 };
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use thiserror::Error;
 
+use hashmap::{sum_hashes, sum_hashes_simd, Hashmap, NonAsciiError, ReferenceHashmap};
+
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// HASH implementation used for part1
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Scalar)]
+    solver: Solver,
 }
 
-#[derive(PartialEq, Eq, Subcommand)]
-enum Part {
-    Part1,
-    Part2,
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// One character at a time
+    Scalar,
+    /// Many tokens at once, one per lane, a character of each at a time
+    Simd,
 }
 
-#[derive(Debug, Error)]
-enum InvalidInputError {
-    #[error("Input contains non-ascii characters")]
-    NonAscii,
-}
+/// The sum of the HASH of each token in a run of comma-separated tokens
+type SumFn = fn(&str) -> Result<u64, NonAsciiError>;
 
-fn hash(s: &str) -> Result<u8> {
-    ensure!(s.is_ascii(), InvalidInputError::NonAscii);
+impl Solver {
+    fn sum_fn(self) -> SumFn {
+        match self {
+            Solver::Scalar => sum_hashes,
+            Solver::Simd => sum_hashes_simd,
+        }
+    }
+}
 
-    Ok(s.chars()
-        .fold(0, |s, c| ((s as u32 + c as u32) * 17 % 256) as u8))
+#[derive(PartialEq, Eq, Subcommand)]
+enum Part {
+    Part1,
+    Part2 {
+        /// Print the non-empty boxes after each step
+        #[arg(long)]
+        explain: bool,
+        /// Stop explaining after this many steps
+        #[arg(long, requires = "explain")]
+        limit: Option<usize>,
+    },
+    /// Run part2 with both the optimized and a naive reference implementation and compare the results
+    Verify,
+    /// Time part1 with each HASH implementation over the whole input, held in memory
+    Bench {
+        /// Number of times to hash the input with each implementation
+        #[arg(default_value_t = 10)]
+        rounds: usize,
+    },
 }
 
 enum Operation {
@@ -51,78 +87,243 @@ fn num(input: &str) -> IResult<&str, u32> {
     map_res(recognize(digit1), str::parse)(input)
 }
 
-fn set_step(input: &str) -> IResult<&str, Step> {
-    map(
-        separated_pair(alphanumeric1, tag("="), num),
-        |(label, val)| Step {
-            label: label.to_owned(),
-            op: Operation::Set(val),
-        },
+/// A label in double quotes, in which `"` and `\\` must be escaped with a backslash
+fn quoted_label(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        escaped_transform(is_not("\\\""), '\\', anychar),
+        char('"'),
     )(input)
 }
 
+/// A bare label, in which `=`, `-`, `"` and `\\` must be escaped with a backslash
+fn bare_label(input: &str) -> IResult<&str, String> {
+    verify(
+        escaped_transform(is_not("\\=-\""), '\\', anychar),
+        |label: &str| !label.is_empty(),
+    )(input)
+}
+
+fn label(input: &str) -> IResult<&str, String> {
+    alt((quoted_label, bare_label))(input)
+}
+
+fn set_step(input: &str) -> IResult<&str, Step> {
+    map(separated_pair(label, tag("="), num), |(label, val)| Step {
+        label,
+        op: Operation::Set(val),
+    })(input)
+}
+
 fn delete_step(input: &str) -> IResult<&str, Step> {
-    map(terminated(alphanumeric1, tag("-")), |label: &str| Step {
-        label: label.to_owned(),
+    map(terminated(label, tag("-")), |label| Step {
+        label,
         op: Operation::Delete,
     })(input)
 }
 
 fn step(input: &str) -> IResult<&str, Step> {
-    alt((set_step, delete_step))(input)
+    all_consuming(alt((set_step, delete_step)))(input)
+}
+
+/// Comma-separated tokens read incrementally, reusing a single buffer so that the whole sequence never has to
+/// be held in memory. Newlines are ignored, even in the middle of a token.
+struct Tokenizer<R> {
+    inp: R,
+    token: Vec<u8>,
+}
+
+impl<R: BufRead> Tokenizer<R> {
+    fn new(inp: R) -> Self {
+        Self {
+            inp,
+            token: Vec::new(),
+        }
+    }
+
+    /// The next non-empty token, or `None` once the input is exhausted
+    fn next_token(&mut self) -> io::Result<Option<&str>> {
+        self.next_tokens(0)
+    }
+
+    /// At least `min_len` bytes of comma-separated tokens, ending at a token boundary, or `None` once the
+    /// input is exhausted
+    fn next_tokens(&mut self, min_len: usize) -> io::Result<Option<&str>> {
+        self.token.clear();
+        loop {
+            let buf = self.inp.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+
+            // only look for the closing comma once enough has been read
+            let start = min_len.saturating_sub(self.token.len()).min(buf.len());
+            let (chunk, consumed, done) = match buf[start..].iter().position(|&b| b == b',') {
+                Some(i) => (&buf[..start + i], start + i + 1, true),
+                None => (buf, buf.len(), false),
+            };
+            self.token
+                .extend(chunk.iter().filter(|&&b| b != b'\n' && b != b'\r'));
+            self.inp.consume(consumed);
+            if done && !self.token.is_empty() {
+                break;
+            }
+        }
+
+        if self.token.is_empty() {
+            return Ok(None);
+        }
+        from_utf8(&self.token)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Bytes of input hashed at a time, by each thread with the `parallel` feature
+const CHUNK_LEN: usize = 1 << 20;
+
+#[cfg(not(feature = "parallel"))]
+fn hash_sum(tokens: &mut Tokenizer<impl BufRead>, sum_fn: SumFn) -> Result<u64> {
+    let mut sum = 0;
+    while let Some(chunk) = tokens.next_tokens(CHUNK_LEN)? {
+        sum += sum_fn(chunk)?;
+    }
+    Ok(sum)
+}
+
+#[cfg(feature = "parallel")]
+fn hash_sum(tokens: &mut Tokenizer<impl BufRead>, sum_fn: SumFn) -> Result<u64> {
+    let threads = rayon::current_num_threads();
+
+    let mut sum = 0;
+    let mut chunks = Vec::with_capacity(threads);
+    loop {
+        chunks.clear();
+        while chunks.len() < threads {
+            match tokens.next_tokens(CHUNK_LEN)? {
+                Some(chunk) => chunks.push(chunk.to_owned()),
+                None => break,
+            }
+        }
+        if chunks.is_empty() {
+            return Ok(sum);
+        }
+
+        sum += chunks
+            .par_iter()
+            .map(|chunk| sum_fn(chunk))
+            .sum::<Result<u64, _>>()?;
+    }
+}
+
+#[derive(Debug, Error)]
+#[error(
+    "Focusing power diverged after {steps} steps: optimized {optimized}, reference {reference}"
+)]
+struct DivergenceError {
+    steps: usize,
+    optimized: u64,
+    reference: u64,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let mut tokens = Tokenizer::new(stdin().lock());
 
     let res = match args.part {
-        Part::Part1 => stdin()
-            .lines()
-            .map(|line| {
-                line?
-                    .split(',')
-                    .map(hash)
-                    .map(|x| x.map(u32::from))
-                    .sum::<Result<u32>>()
-            })
-            .sum::<Result<u32>>()?,
-        Part::Part2 => {
-            let mut hashmap: HashMap<u8, Vec<(String, u32)>> = HashMap::new();
-            for line in stdin().lines() {
-                let line = line?;
-                for token in line.split(',') {
-                    let (_, step) = step(token).map_err(|e| e.to_owned())?;
-                    let i = hash(&step.label)?;
-                    let inner = hashmap.entry(i).or_default();
-                    match step.op {
-                        Operation::Set(val) => {
-                            let mut found = false;
-                            for (label, contents) in inner.iter_mut() {
-                                if *label == step.label {
-                                    *contents = val;
-                                    found = true;
-                                    break;
-                                }
-                            }
-                            if !found {
-                                inner.push((step.label, val));
-                            }
+        Part::Part1 => hash_sum(&mut tokens, args.solver.sum_fn())?,
+        Part::Part2 { explain, limit } => {
+            let mut hashmap = Hashmap::new();
+            let mut steps = 0;
+            while let Some(token) = tokens.next_token()? {
+                let (_, step) = step(token).map_err(|e| e.to_owned())?;
+                match step.op {
+                    Operation::Set(val) => hashmap.insert(&step.label, val)?,
+                    Operation::Delete => {
+                        hashmap.remove(&step.label)?;
+                    }
+                };
+
+                steps += 1;
+                if explain && steps <= limit.unwrap_or(usize::MAX) {
+                    println!("After \"{token}\":\n{hashmap}");
+                }
+            }
+            if let Some(limit) = limit.filter(|&limit| explain && steps > limit) {
+                println!("... {} more steps not shown\n", steps - limit);
+            }
+
+            hashmap.focusing_power()
+        }
+        Part::Verify => {
+            let mut hashmap = Hashmap::new();
+            let mut reference = ReferenceHashmap::default();
+            let mut steps = 0;
+            while let Some(token) = tokens.next_token()? {
+                let (_, step) = step(token).map_err(|e| e.to_owned())?;
+                match step.op {
+                    Operation::Set(val) => {
+                        hashmap.insert(&step.label, val)?;
+                        reference.insert(&step.label, val)?;
+                    }
+                    Operation::Delete => {
+                        let removed = hashmap.remove(&step.label)?;
+                        let reference_removed = reference.remove(&step.label)?;
+                        if removed != reference_removed {
+                            eprintln!(
+                                "Step {}: removing {:?} took out {removed:?}, reference took out {reference_removed:?}",
+                                steps + 1,
+                                step.label
+                            );
                         }
-                        Operation::Delete => inner.retain(|(label, _)| *label != step.label),
-                    };
+                    }
+                };
+                steps += 1;
+            }
+
+            let (optimized, reference) = (hashmap.focusing_power(), reference.focusing_power());
+            if optimized != reference {
+                return Err(DivergenceError {
+                    steps,
+                    optimized,
+                    reference,
                 }
+                .into());
+            }
+            optimized
+        }
+        Part::Bench { rounds } => {
+            let input = tokens.next_tokens(usize::MAX)?.unwrap_or_default();
+            let mut results = Vec::new();
+            for solver in Solver::value_variants() {
+                let sum_fn = solver.sum_fn();
+                let start = Instant::now();
+                let mut sum = 0;
+                for _ in 0..rounds {
+                    sum = sum_fn(black_box(input))?;
+                }
+                let elapsed = start.elapsed() / rounds.max(1) as u32;
+                let name = solver.to_possible_value().expect("No skipped variants");
+                eprintln!(
+                    "{:>6}: {elapsed:?} per round, {:.1} MB/s",
+                    name.get_name(),
+                    input.len() as f64 / elapsed.as_secs_f64() / 1e6
+                );
+                results.push((sum, elapsed));
             }
 
-            hashmap
-                .iter()
-                .map(|(&i, inner)| {
-                    inner
-                        .iter()
-                        .enumerate()
-                        .map(|(j, &(_, contents))| (i as u32 + 1) * (j as u32 + 1) * contents)
-                        .sum::<u32>()
-                })
-                .sum()
+            let (scalar, scalar_time) = results[0];
+            for &(sum, elapsed) in &results[1..] {
+                ensure!(
+                    sum == scalar,
+                    "HASH implementations disagree: {sum} != {scalar}"
+                );
+                eprintln!(
+                    "speedup: {:.2}x",
+                    scalar_time.as_secs_f64() / elapsed.as_secs_f64()
+                );
+            }
+            scalar
         }
     };
 
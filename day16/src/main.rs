@@ -1,61 +1,202 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{hash_map::Entry, HashMap, VecDeque},
+    fmt::Display,
     io::{stdin, BufRead},
 };
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{ensure, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use fixedbitset::FixedBitSet;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Segments)]
+    solver: Solver,
+    /// Accept extension tiles: `X` absorbs beams, and `R` is a mirror that starts as `/` and flips between `/`
+    /// and `\` each time a beam hits it
+    #[arg(long, global = true)]
+    extensions: bool,
+    /// Print the path of the beam from the part's entry in the given format instead of the answer; for part2
+    /// this is the first best entry, and for what-if the part1 entry after every change
+    #[arg(long, global = true, value_enum)]
+    export: Option<ExportFormat>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Trace the beam from each start, one space at a time
+    Bfs,
+    /// Trace each straight run of the beam once, shared between all starts
+    Segments,
 }
 
 #[derive(PartialEq, Eq, Subcommand)]
 enum Part {
     Part1,
     Part2,
+    /// Change tiles one at a time, printing the part1 count before and after each change
+    WhatIf {
+        /// Replace the tile at a row and column, counting from 1, e.g. `10,20=/`; may be repeated
+        #[arg(long = "set", value_name = "ROW,COL=TILE", value_parser = edit, required = true)]
+        edits: Vec<Edit>,
+    },
 }
 
-enum Space {
-    Empty,
-    MirrorUpLeft,
-    MirrorUpRight,
-    SplitterHorizontal,
-    SplitterVertical,
+#[derive(Clone, PartialEq, Eq)]
+struct Edit {
+    coords: Coords,
+    symbol: char,
 }
 
 #[derive(Debug, Error)]
-#[error("`{0}` is an invalid space")]
-struct ParseSpaceError(char);
+#[error("`{0}` is not an edit of the form ROW,COL=TILE")]
+struct ParseEditError(String);
 
-impl TryFrom<char> for Space {
-    type Error = ParseSpaceError;
+fn edit(s: &str) -> Result<Edit, ParseEditError> {
+    let parse = || {
+        let (coords, symbol) = s.split_once('=')?;
+        let (r, c) = coords.split_once(',')?;
+        let mut symbol = symbol.chars();
+        let edit = Edit {
+            coords: (
+                r.trim().parse::<usize>().ok()?.checked_sub(1)?,
+                c.trim().parse::<usize>().ok()?.checked_sub(1)?,
+            ),
+            symbol: symbol.next()?,
+        };
+        symbol.next().is_none().then_some(edit)
+    };
+    parse().ok_or_else(|| ParseEditError(s.to_owned()))
+}
 
-    fn try_from(value: char) -> Result<Self, Self::Error> {
-        match value {
-            '.' => Ok(Space::Empty),
-            '\\' => Ok(Space::MirrorUpLeft),
-            '/' => Ok(Space::MirrorUpRight),
-            '-' => Ok(Space::SplitterHorizontal),
-            '|' => Ok(Space::SplitterVertical),
-            _ => Err(ParseSpaceError(value)),
-        }
+/// How a tile sends on a beam arriving heading in each direction, indexed by [`Direction`]
+type Behavior = [&'static [Direction]; 4];
+
+const PASS: Behavior = [
+    &[Direction::Up],
+    &[Direction::Down],
+    &[Direction::Left],
+    &[Direction::Right],
+];
+const MIRROR_UP_LEFT: Behavior = [
+    &[Direction::Left],
+    &[Direction::Right],
+    &[Direction::Up],
+    &[Direction::Down],
+];
+const MIRROR_UP_RIGHT: Behavior = [
+    &[Direction::Right],
+    &[Direction::Left],
+    &[Direction::Down],
+    &[Direction::Up],
+];
+const SPLIT_HORIZONTAL: Behavior = [
+    &[Direction::Left, Direction::Right],
+    &[Direction::Left, Direction::Right],
+    &[Direction::Left],
+    &[Direction::Right],
+];
+const SPLIT_VERTICAL: Behavior = [
+    &[Direction::Up],
+    &[Direction::Down],
+    &[Direction::Up, Direction::Down],
+    &[Direction::Up, Direction::Down],
+];
+const ABSORB: Behavior = [&[], &[], &[], &[]];
+
+struct Tile {
+    symbol: char,
+    /// Only accepted with `--extensions`
+    extension: bool,
+    /// The tile moves on to its next behavior each time a beam hits it, wrapping around
+    phases: &'static [Behavior],
+}
+
+/// Every known tile; new tiles only need an entry here
+static TILES: [Tile; 7] = [
+    Tile {
+        symbol: '.',
+        extension: false,
+        phases: &[PASS],
+    },
+    Tile {
+        symbol: '\\',
+        extension: false,
+        phases: &[MIRROR_UP_LEFT],
+    },
+    Tile {
+        symbol: '/',
+        extension: false,
+        phases: &[MIRROR_UP_RIGHT],
+    },
+    Tile {
+        symbol: '-',
+        extension: false,
+        phases: &[SPLIT_HORIZONTAL],
+    },
+    Tile {
+        symbol: '|',
+        extension: false,
+        phases: &[SPLIT_VERTICAL],
+    },
+    Tile {
+        symbol: 'X',
+        extension: true,
+        phases: &[ABSORB],
+    },
+    Tile {
+        symbol: 'R',
+        extension: true,
+        phases: &[MIRROR_UP_RIGHT, MIRROR_UP_LEFT],
+    },
+];
+
+impl Tile {
+    /// Directions a beam heading in `direction` leaves in, after the tile has already been hit `hits` times
+    fn successors(&self, direction: Direction, hits: usize) -> &'static [Direction] {
+        self.phases[hits % self.phases.len()][direction as usize]
+    }
+
+    fn is_stateful(&self) -> bool {
+        self.phases.len() > 1
     }
 }
 
+type Space = &'static Tile;
+
+#[derive(Debug, Error)]
+#[error("`{0}` is an invalid space")]
+struct ParseSpaceError(char);
+
+fn space(c: char, extensions: bool) -> Result<Space, ParseSpaceError> {
+    TILES
+        .iter()
+        .find(|tile| tile.symbol == c && (extensions || !tile.extension))
+        .ok_or(ParseSpaceError(c))
+}
+
 type Row = Vec<Space>;
 
-fn row(s: &str) -> Result<Row, ParseSpaceError> {
-    s.chars().map(Space::try_from).collect()
+fn row(s: &str, extensions: bool) -> Result<Row, ParseSpaceError> {
+    s.chars().map(|c| space(c, extensions)).collect()
 }
 
 type Grid = Vec<Row>;
 
-fn grid(inp: impl BufRead) -> Result<Grid> {
-    inp.lines().map(|line| Ok(row(&line?)?)).collect()
+fn grid(inp: impl BufRead, extensions: bool) -> Result<Grid> {
+    inp.lines()
+        .map(|line| Ok(row(&line?, extensions)?))
+        .collect()
 }
 
 type Coords = (usize, usize);
@@ -68,30 +209,20 @@ enum Direction {
     Right,
 }
 
-type State = (Coords, Direction);
-
-fn successor_directions(space: &Space, direction: &Direction) -> Vec<Direction> {
-    match (space, direction) {
-        (Space::Empty, _) => Vec::from([*direction]),
-        (Space::MirrorUpLeft, Direction::Up) => Vec::from([Direction::Left]),
-        (Space::MirrorUpLeft, Direction::Down) => Vec::from([Direction::Right]),
-        (Space::MirrorUpLeft, Direction::Left) => Vec::from([Direction::Up]),
-        (Space::MirrorUpLeft, Direction::Right) => Vec::from([Direction::Down]),
-        (Space::MirrorUpRight, Direction::Up) => Vec::from([Direction::Right]),
-        (Space::MirrorUpRight, Direction::Down) => Vec::from([Direction::Left]),
-        (Space::MirrorUpRight, Direction::Left) => Vec::from([Direction::Down]),
-        (Space::MirrorUpRight, Direction::Right) => Vec::from([Direction::Up]),
-        (Space::SplitterHorizontal, Direction::Up | Direction::Down) => {
-            Vec::from([Direction::Left, Direction::Right])
-        }
-        (Space::SplitterHorizontal, Direction::Left | Direction::Right) => Vec::from([*direction]),
-        (Space::SplitterVertical, Direction::Up | Direction::Down) => Vec::from([*direction]),
-        (Space::SplitterVertical, Direction::Left | Direction::Right) => {
-            Vec::from([Direction::Up, Direction::Down])
-        }
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Direction::Up => "up",
+            Direction::Down => "down",
+            Direction::Left => "left",
+            Direction::Right => "right",
+        };
+        write!(f, "{name}")
     }
 }
 
+type State = (Coords, Direction);
+
 fn try_move(grid: &Grid, (r, c): Coords, direction: &Direction) -> Option<Coords> {
     match direction {
         Direction::Up if r > 0 => Some((r - 1, c)),
@@ -103,56 +234,488 @@ fn try_move(grid: &Grid, (r, c): Coords, direction: &Direction) -> Option<Coords
 }
 
 fn count_energized(grid: &Grid, init_state: State) -> usize {
+    let width = grid[0].len();
+    let num_spaces = grid.len() * width;
+    let max_phases = TILES
+        .iter()
+        .map(|tile| tile.phases.len())
+        .max()
+        .unwrap_or(1);
+
     let mut queue = VecDeque::from([init_state]);
-    let mut visited = HashSet::from([init_state]);
-    let mut energized: HashSet<Coords> = HashSet::new();
+    // a state only repeats if its tile is also in the same phase, so visited states are indexed by
+    // (space, direction, phase)
+    let mut visited = FixedBitSet::with_capacity(num_spaces * 4 * max_phases);
+    let mut hits = vec![0; num_spaces];
+    let mut energized = FixedBitSet::with_capacity(num_spaces);
 
     while let Some((coords, direction)) = queue.pop_front() {
-        energized.insert(coords);
-        for direction in successor_directions(&grid[coords.0][coords.1], &direction) {
-            let Some(new_coords) = try_move(&grid, coords, &direction) else {
+        let space = coords.0 * width + coords.1;
+        let tile = grid[coords.0][coords.1];
+        let phase = hits[space] % tile.phases.len();
+        if visited.put((space * 4 + direction as usize) * max_phases + phase) {
+            continue;
+        }
+        if tile.is_stateful() {
+            hits[space] += 1;
+        }
+
+        energized.insert(space);
+        for &new_direction in tile.successors(direction, phase) {
+            if let Some(new_coords) = try_move(grid, coords, &new_direction) {
+                queue.push_back((new_coords, new_direction));
+            }
+        }
+    }
+
+    energized.count_ones(..)
+}
+
+/// A straight run of the beam, from the first space it energizes to the last, both inclusive
+struct BeamSegment {
+    start: Coords,
+    end: Coords,
+    direction: Direction,
+}
+
+/// The spaces energized from `init_state`, and the straight runs of the beam in the order they are reached
+fn beam_path(grid: &Grid, init_state: State) -> (FixedBitSet, Vec<BeamSegment>) {
+    let width = grid[0].len();
+    let num_spaces = grid.len() * width;
+    let max_phases = TILES
+        .iter()
+        .map(|tile| tile.phases.len())
+        .max()
+        .unwrap_or(1);
+
+    let mut queue = VecDeque::from([init_state]);
+    let mut visited = FixedBitSet::with_capacity(num_spaces * 4 * max_phases);
+    let mut hits = vec![0; num_spaces];
+    let mut energized = FixedBitSet::with_capacity(num_spaces);
+    let mut segments = Vec::new();
+
+    while let Some((start, direction)) = queue.pop_front() {
+        let mut coords = start;
+        let mut end = None;
+        loop {
+            let space = coords.0 * width + coords.1;
+            let tile = grid[coords.0][coords.1];
+            let phase = hits[space] % tile.phases.len();
+            if visited.put((space * 4 + direction as usize) * max_phases + phase) {
+                break;
+            }
+            if tile.is_stateful() {
+                hits[space] += 1;
+            }
+            energized.insert(space);
+            end = Some(coords);
+
+            let directions = tile.successors(direction, phase);
+            if directions == [direction] {
+                match try_move(grid, coords, &direction) {
+                    Some(new_coords) => coords = new_coords,
+                    None => break,
+                }
                 continue;
-            };
+            }
+            for &new_direction in directions {
+                if let Some(new_coords) = try_move(grid, coords, &new_direction) {
+                    queue.push_back((new_coords, new_direction));
+                }
+            }
+            break;
+        }
+
+        if let Some(end) = end {
+            segments.push(BeamSegment {
+                start,
+                end,
+                direction,
+            });
+        }
+    }
+
+    (energized, segments)
+}
+
+fn export_json(grid: &Grid, init_state: State) {
+    let coords = |(r, c): Coords| format!("[{r}, {c}]");
+    let (energized, segments) = beam_path(grid, init_state);
+    let width = grid[0].len();
+
+    println!("{{");
+    println!(
+        "  \"entry\": {{\"coords\": {}, \"direction\": \"{}\"}},",
+        coords(init_state.0),
+        init_state.1
+    );
+    println!(
+        "  \"energized\": [{}],",
+        energized
+            .ones()
+            .map(|space| coords((space / width, space % width)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!("  \"segments\": [");
+    for (i, segment) in segments.iter().enumerate() {
+        let sep = if i + 1 < segments.len() { "," } else { "" };
+        println!(
+            "    {{\"start\": {}, \"end\": {}, \"direction\": \"{}\"}}{sep}",
+            coords(segment.start),
+            coords(segment.end),
+            segment.direction
+        );
+    }
+    println!("  ]");
+    println!("}}");
+}
+
+/// Every state entering the grid from one of its edges
+fn edge_starts(grid: &Grid) -> Vec<State> {
+    (0..grid.len())
+        .flat_map(|r| {
+            [
+                ((r, 0), Direction::Right),
+                ((r, grid[0].len() - 1), Direction::Left),
+            ]
+        })
+        .chain((0..grid[0].len()).flat_map(|c| {
+            [
+                ((0, c), Direction::Down),
+                ((grid.len() - 1, c), Direction::Up),
+            ]
+        }))
+        .collect()
+}
+
+fn count_all_energized(grid: &Grid, starts: &[State]) -> Vec<usize> {
+    #[cfg(feature = "parallel")]
+    let starts = starts.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let starts = starts.iter();
+
+    starts
+        .map(|&init_state| count_energized(grid, init_state))
+        .collect()
+}
+
+/// Straight runs of the beam, each from the space where it enters up to the next tile that turns or splits
+/// it, or the edge of the grid
+#[derive(Default)]
+struct Segments {
+    entries: Vec<State>,
+    /// Flat indices of the spaces each segment passes through
+    spaces: Vec<Vec<usize>>,
+    successors: Vec<Vec<usize>>,
+    ids: HashMap<State, usize>,
+    /// The segments passing through each space, by flat index
+    through: HashMap<usize, Vec<usize>>,
+}
+
+impl Segments {
+    /// Every segment reachable from the given starts. Stateful tiles are treated as always being in their
+    /// first phase.
+    fn discover(grid: &Grid, starts: &[State]) -> Self {
+        let mut segments = Self::default();
+        let mut pending = Vec::new();
+        for &start in starts {
+            segments.id(start, &mut pending);
+        }
+        segments.trace(grid, pending);
+        segments
+    }
+
+    /// Re-trace the segments passing through a space after its tile has changed, discovering any segments
+    /// they now lead to. Every other segment is left as it was.
+    fn retrace(&mut self, grid: &Grid, (r, c): Coords) {
+        let changed = r * grid[0].len() + c;
+        let pending = self.through.remove(&changed).unwrap_or_default();
+        for &id in &pending {
+            for space in self.spaces[id].drain(..) {
+                if let Some(through) = self.through.get_mut(&space) {
+                    through.retain(|&other| other != id);
+                }
+            }
+            self.successors[id].clear();
+        }
+        self.trace(grid, pending);
+    }
+
+    /// Follow each pending segment to where it ends, queueing up any new segments it leads to
+    fn trace(&mut self, grid: &Grid, mut pending: Vec<usize>) {
+        while let Some(id) = pending.pop() {
+            let (mut coords, direction) = self.entries[id];
+            loop {
+                let space = coords.0 * grid[0].len() + coords.1;
+                self.spaces[id].push(space);
+                self.through.entry(space).or_default().push(id);
+                let directions = grid[coords.0][coords.1].successors(direction, 0);
+                if directions == [direction] {
+                    match try_move(grid, coords, &direction) {
+                        Some(new_coords) => coords = new_coords,
+                        None => break,
+                    }
+                    continue;
+                }
+
+                for &direction in directions {
+                    if let Some(new_coords) = try_move(grid, coords, &direction) {
+                        let successor = self.id((new_coords, direction), &mut pending);
+                        self.successors[id].push(successor);
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    /// The segment entered with this state, queueing it to be traced if it is new
+    fn id(&mut self, entry: State, pending: &mut Vec<usize>) -> usize {
+        match self.ids.entry(entry) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => {
+                let id = self.entries.len();
+                e.insert(id);
+                self.entries.push(entry);
+                self.spaces.push(Vec::new());
+                self.successors.push(Vec::new());
+                pending.push(id);
+                id
+            }
+        }
+    }
+
+    /// The number of spaces energized by a beam entering with this state, following only the segments it
+    /// reaches
+    fn energized_by(&self, entry: State, num_spaces: usize) -> usize {
+        let mut visited = FixedBitSet::with_capacity(self.entries.len());
+        let mut energized = FixedBitSet::with_capacity(num_spaces);
+        let mut pending = vec![self.ids[&entry]];
+        while let Some(id) = pending.pop() {
+            if visited.put(id) {
+                continue;
+            }
+            energized.extend(self.spaces[id].iter().copied());
+            pending.extend(&self.successors[id]);
+        }
+        energized.count_ones(..)
+    }
+
+    /// The number of spaces energized by a beam entering each segment.
+    ///
+    /// Segments that can reach each other energize the same spaces, so the segment graph is condensed into its
+    /// strongly connected components with Tarjan's algorithm. Components are completed after everything they
+    /// reach, so each one's energized spaces are built from those of its successors.
+    fn energized(&self, num_spaces: usize) -> Vec<usize> {
+        let n = self.entries.len();
+        let mut index = vec![usize::MAX; n];
+        let mut low = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut component = vec![usize::MAX; n];
+        let mut component_spaces: Vec<FixedBitSet> = Vec::new();
+        let mut next_index = 0;
 
-            let new_state: State = (new_coords, direction);
-            if visited.contains(&new_state) {
+        for root in 0..n {
+            if index[root] != usize::MAX {
                 continue;
             }
 
-            visited.insert(new_state);
-            queue.push_back(new_state);
+            // (segment, number of its successors visited so far)
+            let mut call_stack = vec![(root, 0)];
+            index[root] = next_index;
+            low[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some(&mut (v, ref mut i)) = call_stack.last_mut() {
+                if let Some(&w) = self.successors[v].get(*i) {
+                    *i += 1;
+                    if index[w] == usize::MAX {
+                        index[w] = next_index;
+                        low[w] = next_index;
+                        next_index += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        call_stack.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+
+                call_stack.pop();
+                if let Some(&(u, _)) = call_stack.last() {
+                    low[u] = low[u].min(low[v]);
+                }
+                if low[v] != index[v] {
+                    continue;
+                }
+
+                let id = component_spaces.len();
+                let mut members = Vec::new();
+                loop {
+                    let w = stack.pop().expect("Component root is on the stack");
+                    on_stack[w] = false;
+                    component[w] = id;
+                    members.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+
+                let mut spaces = FixedBitSet::with_capacity(num_spaces);
+                for &m in &members {
+                    spaces.extend(self.spaces[m].iter().copied());
+                    for &w in &self.successors[m] {
+                        if component[w] != id {
+                            spaces.union_with(&component_spaces[component[w]]);
+                        }
+                    }
+                }
+                component_spaces.push(spaces);
+            }
         }
+
+        component
+            .iter()
+            .map(|&c| component_spaces[c].count_ones(..))
+            .collect()
     }
+}
 
-    energized.len()
+/// The number of spaces energized from each start, in the same order
+fn energized_from(grid: &Grid, starts: &[State], solver: Solver) -> Vec<usize> {
+    match solver {
+        Solver::Bfs => count_all_energized(grid, starts),
+        Solver::Segments => {
+            let segments = Segments::discover(grid, starts);
+            let energized = segments.energized(grid.len() * grid[0].len());
+            starts
+                .iter()
+                .map(|start| energized[segments.ids[start]])
+                .collect()
+        }
+    }
+}
+
+/// The highest number of energized spaces, and every start achieving it
+fn best_starts(starts: &[State], energized: &[usize]) -> (usize, Vec<State>) {
+    let mut max = 0;
+    let mut best = Vec::new();
+    for (&start, &count) in starts.iter().zip(energized) {
+        if count > max {
+            max = count;
+            best.clear();
+        }
+        if count == max {
+            best.push(start);
+        }
+    }
+    (max, best)
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let grid = grid(stdin().lock())?;
-
-    let res = match args.part {
-        Part::Part1 => count_energized(&grid, ((0, 0), Direction::Right)),
-        Part::Part2 => (0..grid.len())
-            .flat_map(|r| {
-                [
-                    ((r, 0), Direction::Right),
-                    ((r, grid[0].len() - 1), Direction::Left),
-                ]
+    let mut grid = grid(stdin().lock(), args.extensions)?;
+    let edits = match &args.part {
+        Part::WhatIf { edits } => edits
+            .iter()
+            .map(|&Edit { coords, symbol }| {
+                ensure!(
+                    coords.0 < grid.len() && coords.1 < grid[0].len(),
+                    "Row {}, column {} is outside the grid",
+                    coords.0 + 1,
+                    coords.1 + 1
+                );
+                Ok((coords, space(symbol, args.extensions)?))
             })
-            .chain((0..grid[0].len()).flat_map(|c| {
-                [
-                    ((0, c), Direction::Down),
-                    ((grid.len() - 1, c), Direction::Up),
-                ]
-            }))
-            .map(|init_state| count_energized(&grid, init_state))
-            .max()
-            .unwrap(),
+            .collect::<Result<Vec<_>>>()?,
+        _ => Vec::new(),
     };
+    let solver = match args.solver {
+        Solver::Segments
+            if grid
+                .iter()
+                .flatten()
+                .chain(edits.iter().map(|(_, tile)| tile))
+                .any(|tile| tile.is_stateful()) =>
+        {
+            eprintln!(
+                "Warning: segments can't follow tiles that change when hit, using bfs instead"
+            );
+            Solver::Bfs
+        }
+        solver => solver,
+    };
+
+    match args.part {
+        Part::Part1 => {
+            let start = ((0, 0), Direction::Right);
+            if let Some(ExportFormat::Json) = args.export {
+                export_json(&grid, start);
+                return Ok(());
+            }
+            println!("{}", energized_from(&grid, &[start], solver)[0]);
+        }
+        Part::Part2 => {
+            let starts = edge_starts(&grid);
+            let energized = energized_from(&grid, &starts, solver);
+            let (max, best) = best_starts(&starts, &energized);
+            if let Some(ExportFormat::Json) = args.export {
+                export_json(&grid, best[0]);
+                return Ok(());
+            }
+            println!("{max}");
+            for ((r, c), direction) in best {
+                println!(
+                    "Entering at row {}, column {}, heading {direction}",
+                    r + 1,
+                    c + 1
+                );
+            }
+        }
+        Part::WhatIf { .. } => {
+            let start = ((0, 0), Direction::Right);
+            if let Some(ExportFormat::Json) = args.export {
+                for ((r, c), tile) in edits {
+                    grid[r][c] = tile;
+                }
+                export_json(&grid, start);
+                return Ok(());
+            }
+
+            let num_spaces = grid.len() * grid[0].len();
+            // only the segments solver can re-trace just the beams through a changed tile
+            let mut segments =
+                matches!(solver, Solver::Segments).then(|| Segments::discover(&grid, &[start]));
+            let count = |grid: &Grid, segments: &Option<Segments>| match segments {
+                Some(segments) => segments.energized_by(start, num_spaces),
+                None => energized_from(grid, &[start], solver)[0],
+            };
 
-    println!("{res}");
+            let mut before = count(&grid, &segments);
+            println!("{before}");
+            for ((r, c), tile) in edits {
+                grid[r][c] = tile;
+                if let Some(segments) = &mut segments {
+                    segments.retrace(&grid, (r, c));
+                }
+                let after = count(&grid, &segments);
+                println!(
+                    "Row {}, column {} set to `{}`: {after} ({:+})",
+                    r + 1,
+                    c + 1,
+                    tile.symbol,
+                    after as isize - before as isize
+                );
+                before = after;
+            }
+        }
+    }
 
     Ok(())
 }
@@ -1,29 +1,127 @@
+mod queue;
+
 use std::{
-    cmp::Reverse,
     collections::{BinaryHeap, HashMap},
-    io::{stdin, BufRead},
+    fs::File,
+    hint::black_box,
+    io::{stdin, BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
 };
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{ensure, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use thiserror::Error;
 
+use queue::{BucketQueue, PriorityQueue};
+
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Dijkstra)]
+    solver: Solver,
+    #[arg(long, global = true, value_enum, default_value_t = Queue::Bucket)]
+    queue: Queue,
+    /// Blocks the crucible must move in a straight line before it can turn or stop, instead of the part's
+    #[arg(long, global = true)]
+    min_run: Option<usize>,
+    /// Blocks the crucible can move in a straight line before it has to turn, instead of the part's
+    #[arg(long, global = true)]
+    max_run: Option<usize>,
+    /// Extra heat loss for every turn
+    #[arg(long, global = true, default_value_t = 0)]
+    turn_cost: u32,
+    /// Extra heat loss for every block moved straight on from the last, not counting the first move
+    #[arg(long, global = true, default_value_t = 0)]
+    straight_cost: u32,
+    /// Draw the grid with the chosen route overlaid before printing the answer
+    #[arg(long, global = true)]
+    render: bool,
+    /// Print the chosen route in the given format instead of the answer
+    #[arg(long, global = true, value_enum)]
+    export: Option<ExportFormat>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Expand states in order of heat loss so far
+    Dijkstra,
+    /// Expand states in order of heat loss so far plus a lower bound on the heat loss still to come
+    Astar,
+    /// Expand states from the start and, with every move reversed, from the end, until the two searches meet
+    Bidirectional,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Queue {
+    /// A binary heap
+    Heap,
+    /// One bucket per heat loss, relying on each step costing at most 9
+    Bucket,
 }
 
 #[derive(PartialEq, Eq, Subcommand)]
 enum Part {
     Part1,
     Part2,
+    /// Time every solver with every queue, using part1's run lengths unless others are given
+    Bench {
+        /// Number of times to solve the grid with each combination
+        #[arg(default_value_t = 10)]
+        rounds: usize,
+    },
+    /// Search to every block instead of stopping at the bottom-right corner, and write the least heat loss for
+    /// each to a CSV file, using part1's run lengths unless others are given
+    Distances {
+        #[arg(value_name = "PATH")]
+        output: PathBuf,
+        /// Measure the least heat loss from each block to the bottom-right corner instead, and check that the
+        /// A* heuristic never exceeds it
+        #[arg(long)]
+        to_goal: bool,
+    },
 }
 
 #[derive(Debug, Error)]
 #[error("Non-numeric input")]
 struct ParseValError;
 
+#[derive(Debug, Error, PartialEq, Eq)]
+enum RunLengthError {
+    #[error("Maximum run length must be at least 1")]
+    ZeroMax,
+    #[error("Minimum run length {min} exceeds maximum run length {max}")]
+    MinAboveMax { min: usize, max: usize },
+}
+
+/// The maximum run length to search with, no longer than the longest side of the grid since no run could be longer
+fn validate_run_lengths(
+    min_run_length: usize,
+    max_run_length: usize,
+    grid_size: usize,
+) -> Result<usize, RunLengthError> {
+    if max_run_length == 0 {
+        return Err(RunLengthError::ZeroMax);
+    }
+    if min_run_length > max_run_length {
+        return Err(RunLengthError::MinAboveMax {
+            min: min_run_length,
+            max: max_run_length,
+        });
+    }
+    Ok(max_run_length.min(grid_size))
+}
+
+#[derive(Debug, Error)]
+#[error("No path to the bottom-right corner keeps to the run lengths")]
+struct NoPathError;
+
 type Row = Vec<u32>;
 
 fn row(inp: &str) -> Result<Row> {
@@ -49,6 +147,24 @@ enum Direction {
 }
 
 impl Direction {
+    fn reverse(&self) -> Self {
+        match self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+        }
+    }
+
+    fn arrow(&self) -> char {
+        match self {
+            Direction::North => '^',
+            Direction::South => 'v',
+            Direction::East => '>',
+            Direction::West => '<',
+        }
+    }
+
     fn turn_left(&self) -> Self {
         match self {
             Direction::North => Direction::West,
@@ -85,67 +201,782 @@ struct State {
     run_length: usize,
 }
 
-fn heat_loss(grid: &Grid, min_run_length: usize, max_run_length: usize) -> u32 {
-    let mut heap = BinaryHeap::new();
-    let mut best: HashMap<State, u32> = HashMap::new();
+/// The crucible starts out at the top-left corner, free to head either east or south
+const START: State = State {
+    coords: (0, 0),
+    direction: Direction::East,
+    run_length: 0,
+};
+
+/// The states one move on from `state`
+fn successors(
+    grid: &Grid,
+    state: &State,
+    min_run_length: usize,
+    max_run_length: usize,
+) -> Vec<State> {
+    let mut possible = Vec::new();
+    if state.run_length == 0 || state.run_length >= min_run_length {
+        possible.push((state.direction.turn_left(), 1));
+        possible.push((state.direction.turn_right(), 1));
+    }
+    if state.run_length < max_run_length {
+        possible.push((state.direction, state.run_length + 1));
+    }
+
+    possible
+        .into_iter()
+        .filter_map(|(direction, run_length)| {
+            Some(State {
+                coords: try_move(grid, &state.coords, &direction)?,
+                direction,
+                run_length,
+            })
+        })
+        .collect()
+}
 
-    let state = State {
-        coords: (0, 0),
-        direction: Direction::East,
-        run_length: 0,
+/// The states one move before `state`, so that `state` is among the [`successors`] of each of them
+fn predecessors(
+    grid: &Grid,
+    state: &State,
+    min_run_length: usize,
+    max_run_length: usize,
+) -> Vec<State> {
+    let Some(coords) = try_move(grid, &state.coords, &state.direction.reverse()) else {
+        return Vec::new();
     };
-    best.insert(state, 0);
-    heap.push(Reverse((0, state)));
 
-    while let Some(Reverse((heat_loss, state))) = heap.pop() {
-        if state.coords == (grid.len() - 1, grid[0].len() - 1) && state.run_length >= min_run_length
-        {
-            return heat_loss;
+    match state.run_length {
+        // only the start has a run length of 0, and nothing moves into it
+        0 => Vec::new(),
+        // a turn at the end of a long enough run, or the first move from the start
+        1 => {
+            let mut possible: Vec<_> = [state.direction.turn_left(), state.direction.turn_right()]
+                .into_iter()
+                .flat_map(|direction| {
+                    (min_run_length.max(1)..=max_run_length).map(move |run_length| State {
+                        coords,
+                        direction,
+                        run_length,
+                    })
+                })
+                .collect();
+            if coords == START.coords && state.direction != START.direction.reverse() {
+                possible.push(START);
+            }
+            possible
+        }
+        // carrying straight on
+        run_length => vec![State {
+            coords,
+            direction: state.direction,
+            run_length: run_length - 1,
+        }],
+    }
+}
+
+/// Extra heat loss layered onto the blocks' own, depending on how the crucible moves
+#[derive(Clone, Copy, Default)]
+struct CostModifiers {
+    turn: u32,
+    straight: u32,
+}
+
+impl CostModifiers {
+    /// The heat loss of moving from `from` to `to`, one of its [`successors`]
+    fn step_cost(&self, grid: &Grid, from: &State, to: &State) -> u32 {
+        let modifier = if to.run_length > 1 {
+            self.straight
+        } else if from.run_length > 0 {
+            self.turn
+        } else {
+            // the first move from the start is neither
+            0
+        };
+        grid[to.coords.0][to.coords.1] + modifier
+    }
+
+    /// The most any move adds on top of the block's own heat loss
+    fn max(&self) -> u32 {
+        self.turn.max(self.straight)
+    }
+}
+
+fn is_goal(grid: &Grid, state: &State, min_run_length: usize) -> bool {
+    state.coords == (grid.len() - 1, grid[0].len() - 1) && state.run_length >= min_run_length
+}
+
+/// Every state at the bottom-right corner the crucible is allowed to stop in
+fn goals(grid: &Grid, min_run_length: usize, max_run_length: usize) -> Vec<State> {
+    let coords = (grid.len() - 1, grid[0].len() - 1);
+    let mut goals: Vec<_> = [
+        Direction::North,
+        Direction::South,
+        Direction::East,
+        Direction::West,
+    ]
+    .into_iter()
+    .flat_map(|direction| {
+        (min_run_length.max(1)..=max_run_length).map(move |run_length| State {
+            coords,
+            direction,
+            run_length,
+        })
+    })
+    .collect();
+    if is_goal(grid, &START, min_run_length) {
+        goals.push(START);
+    }
+    goals
+}
+
+/// A lower bound on the heat loss from each space to the bottom-right corner: every step towards it costs at
+/// least as much as the cheapest block. It never overestimates, so A* still finds the minimum.
+fn manhattan_heuristic(grid: &Grid) -> impl Fn(Coords) -> u32 {
+    let min_cost = grid.iter().flatten().copied().min().unwrap_or(0);
+    let target = (grid.len() - 1, grid[0].len() - 1);
+    move |(r, c)| ((target.0 - r) + (target.1 - c)) as u32 * min_cost
+}
+
+/// A way to the bottom-right corner with the least heat loss
+struct Route {
+    heat_loss: u32,
+    /// Every state along the way, starting from the top-left corner
+    states: Vec<State>,
+}
+
+/// The route with the least heat loss to the bottom-right corner, if it can be reached at all
+fn best_route(
+    grid: &Grid,
+    min_run_length: usize,
+    max_run_length: usize,
+    modifiers: CostModifiers,
+    solver: Solver,
+    queue: Queue,
+) -> Option<Route> {
+    let min_cost = grid.iter().flatten().copied().min().unwrap_or(0);
+    let max_cost = grid.iter().flatten().copied().max().unwrap_or(0) + modifiers.max();
+    let run_lengths = (min_run_length, max_run_length);
+    match solver {
+        Solver::Bidirectional => match queue {
+            Queue::Heap => bidirectional(
+                grid,
+                run_lengths,
+                modifiers,
+                BinaryHeap::new(),
+                BinaryHeap::new(),
+            ),
+            Queue::Bucket => bidirectional(
+                grid,
+                run_lengths,
+                modifiers,
+                BucketQueue::new(max_cost),
+                BucketQueue::new(max_cost),
+            ),
+        },
+        Solver::Dijkstra => search_with(grid, run_lengths, modifiers, |_| 0, queue, max_cost),
+        // the heuristic drops by at most the cheapest block per step, so priorities can rise by that much more.
+        // Modifiers only ever add heat loss, so it still never overestimates.
+        Solver::Astar => search_with(
+            grid,
+            run_lengths,
+            modifiers,
+            manhattan_heuristic(grid),
+            queue,
+            max_cost + min_cost,
+        ),
+    }
+}
+
+/// [`search`] with the chosen queue, where no step raises the priority by more than `max_step`
+fn search_with(
+    grid: &Grid,
+    (min_run_length, max_run_length): (usize, usize),
+    modifiers: CostModifiers,
+    heuristic: impl Fn(Coords) -> u32,
+    queue: Queue,
+    max_step: u32,
+) -> Option<Route> {
+    match queue {
+        Queue::Heap => search(
+            grid,
+            min_run_length,
+            max_run_length,
+            modifiers,
+            heuristic,
+            BinaryHeap::new(),
+        ),
+        Queue::Bucket => search(
+            grid,
+            min_run_length,
+            max_run_length,
+            modifiers,
+            heuristic,
+            BucketQueue::new(max_step),
+        ),
+    }
+}
+
+/// Best-first search ordered by heat loss so far plus `heuristic`, which must never overestimate the heat
+/// loss left to the bottom-right corner
+fn search(
+    grid: &Grid,
+    min_run_length: usize,
+    max_run_length: usize,
+    modifiers: CostModifiers,
+    heuristic: impl Fn(Coords) -> u32,
+    mut queue: impl PriorityQueue<(u32, State)>,
+) -> Option<Route> {
+    let mut best: HashMap<State, u32> = HashMap::new();
+    let mut predecessors: HashMap<State, State> = HashMap::new();
+
+    best.insert(START, 0);
+    queue.push(heuristic(START.coords), (0, START));
+
+    while let Some((_, (heat_loss, state))) = queue.pop() {
+        if is_goal(grid, &state, min_run_length) {
+            let mut states = vec![state];
+            while let Some(&predecessor) = predecessors.get(states.last().unwrap()) {
+                states.push(predecessor);
+            }
+            states.reverse();
+            return Some(Route { heat_loss, states });
         } else if heat_loss > *best.get(&state).unwrap() {
             continue;
         }
 
-        let mut possible = Vec::new();
-        if state.run_length == 0 || state.run_length >= min_run_length {
-            possible.push((state.direction.turn_left(), 1));
-            possible.push((state.direction.turn_right(), 1));
+        for next in successors(grid, &state, min_run_length, max_run_length) {
+            let heat_loss = heat_loss + modifiers.step_cost(grid, &state, &next);
+            let best_for_state = best.entry(next).or_insert(u32::MAX);
+            if heat_loss < *best_for_state {
+                queue.push(heat_loss + heuristic(next.coords), (heat_loss, next));
+                *best_for_state = heat_loss;
+                predecessors.insert(next, state);
+            }
         }
-        if state.run_length < max_run_length {
-            possible.push((state.direction, state.run_length + 1));
+    }
+
+    None
+}
+
+/// The least heat loss from the start to every block the crucible can stop at, or with `to_goal`, from every
+/// block to the bottom-right corner, searching until every state is settled
+fn all_heat_losses(
+    grid: &Grid,
+    min_run_length: usize,
+    max_run_length: usize,
+    modifiers: CostModifiers,
+    to_goal: bool,
+) -> Vec<Vec<Option<u32>>> {
+    let max_cost = grid.iter().flatten().copied().max().unwrap_or(0) + modifiers.max();
+    let mut queue = BucketQueue::new(max_cost);
+    let mut best: HashMap<State, u32> = HashMap::new();
+
+    let sources = if to_goal {
+        goals(grid, min_run_length, max_run_length)
+    } else {
+        vec![START]
+    };
+    for source in sources {
+        best.insert(source, 0);
+        queue.push(0, (0, source));
+    }
+
+    while let Some((_, (heat_loss, state))) = queue.pop() {
+        if heat_loss > best[&state] {
+            continue;
         }
 
-        for (direction, run_length) in possible {
-            let Some(coords) = try_move(grid, &state.coords, &direction) else {
-                continue;
+        let neighbors = if to_goal {
+            predecessors(grid, &state, min_run_length, max_run_length)
+        } else {
+            successors(grid, &state, min_run_length, max_run_length)
+        };
+        for next in neighbors {
+            let step = if to_goal {
+                modifiers.step_cost(grid, &next, &state)
+            } else {
+                modifiers.step_cost(grid, &state, &next)
             };
+            let heat_loss = heat_loss + step;
+            let best_for_state = best.entry(next).or_insert(u32::MAX);
+            if heat_loss < *best_for_state {
+                queue.push(heat_loss, (heat_loss, next));
+                *best_for_state = heat_loss;
+            }
+        }
+    }
 
-            let heat_loss = heat_loss + grid[coords.0][coords.1];
-            let state = State {
-                coords,
-                direction,
-                run_length,
+    let mut heat_losses = vec![vec![None; grid[0].len()]; grid.len()];
+    for (state, heat_loss) in best {
+        if !to_goal && state.run_length < min_run_length {
+            continue;
+        }
+        let cell: &mut Option<u32> = &mut heat_losses[state.coords.0][state.coords.1];
+        *cell = Some(cell.map_or(heat_loss, |cell| cell.min(heat_loss)));
+    }
+    heat_losses
+}
+
+fn export_heat_losses(path: &Path, heat_losses: &[Vec<Option<u32>>]) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "row,col,heat_loss")?;
+    for (r, row) in heat_losses.iter().enumerate() {
+        for (c, heat_loss) in row.iter().enumerate() {
+            match heat_loss {
+                Some(heat_loss) => writeln!(out, "{r},{c},{heat_loss}")?,
+                None => writeln!(out, "{r},{c},")?,
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// One side of a bidirectional search: the least heat loss found to (or from) each state, and the neighbor it
+/// was found through
+#[derive(Default)]
+struct Frontier {
+    best: HashMap<State, u32>,
+    via: HashMap<State, State>,
+    /// The priority last taken off this side's queue, which nothing left in it is below
+    reached: u32,
+}
+
+impl Frontier {
+    /// Record `heat_loss` for `state` if it improves on what is known, returning whether it did
+    fn relax(&mut self, state: State, heat_loss: u32, via: State) -> bool {
+        let best_for_state = self.best.entry(state).or_insert(u32::MAX);
+        if heat_loss >= *best_for_state {
+            return false;
+        }
+        *best_for_state = heat_loss;
+        self.via.insert(state, via);
+        true
+    }
+
+    /// The states followed back from `state` to where this side started, beginning with `state`
+    fn chain(&self, state: State) -> Vec<State> {
+        let mut states = vec![state];
+        while let Some(&next) = self.via.get(states.last().unwrap()) {
+            states.push(next);
+        }
+        states
+    }
+}
+
+/// Dijkstra's algorithm from the start and from the goals at once, taking a step on each side in turn.
+///
+/// A reversed move costs the same as the forward move it undoes, which pays for entering `state`'s block. The
+/// searches stop once the lowest priorities left on the two sides add up to at least the best meeting found.
+fn bidirectional(
+    grid: &Grid,
+    (min_run_length, max_run_length): (usize, usize),
+    modifiers: CostModifiers,
+    mut forward_queue: impl PriorityQueue<(u32, State)>,
+    mut backward_queue: impl PriorityQueue<(u32, State)>,
+) -> Option<Route> {
+    let mut forward = Frontier::default();
+    let mut backward = Frontier::default();
+    // the least heat loss of a route through a state both sides have reached, and that state
+    let mut meeting: Option<(u32, State)> = None;
+
+    forward.best.insert(START, 0);
+    forward_queue.push(0, (0, START));
+    for goal in goals(grid, min_run_length, max_run_length) {
+        backward.best.insert(goal, 0);
+        backward_queue.push(0, (0, goal));
+    }
+    if is_goal(grid, &START, min_run_length) {
+        meeting = Some((0, START));
+    }
+
+    let mut forward_turn = true;
+    loop {
+        let (queue, this, other): (&mut dyn PriorityQueue<_>, _, _) = if forward_turn {
+            (&mut forward_queue, &mut forward, &backward)
+        } else {
+            (&mut backward_queue, &mut backward, &forward)
+        };
+        let Some((priority, (heat_loss, state))) = queue.pop() else {
+            break;
+        };
+        this.reached = priority;
+        if meeting.is_some_and(|(best, _)| this.reached + other.reached >= best) {
+            break;
+        }
+        if heat_loss > this.best[&state] {
+            continue;
+        }
+
+        let neighbors = if forward_turn {
+            successors(grid, &state, min_run_length, max_run_length)
+        } else {
+            predecessors(grid, &state, min_run_length, max_run_length)
+        };
+        for next in neighbors {
+            let step = if forward_turn {
+                modifiers.step_cost(grid, &state, &next)
+            } else {
+                modifiers.step_cost(grid, &next, &state)
             };
+            let heat_loss = heat_loss + step;
+            if !this.relax(next, heat_loss, state) {
+                continue;
+            }
+            queue.push(heat_loss, (heat_loss, next));
+            if let Some(&rest) = other.best.get(&next) {
+                if heat_loss + rest < meeting.map_or(u32::MAX, |(best, _)| best) {
+                    meeting = Some((heat_loss + rest, next));
+                }
+            }
+        }
+        forward_turn = !forward_turn;
+    }
 
-            let best_for_state = best.entry(state).or_insert(u32::MAX);
-            if heat_loss < *best_for_state {
-                heap.push(Reverse((heat_loss, state)));
-                *best_for_state = heat_loss;
+    let (heat_loss, state) = meeting?;
+    let mut states = forward.chain(state);
+    states.reverse();
+    states.extend(&backward.chain(state)[1..]);
+    Some(Route { heat_loss, states })
+}
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// The grid with each block on the route replaced by the direction the crucible entered it, and the rest dimmed
+fn render(grid: &Grid, route: &Route) {
+    let mut arrows = HashMap::new();
+    for state in &route.states[1..] {
+        arrows.insert(state.coords, state.direction.arrow());
+    }
+
+    for (r, row) in grid.iter().enumerate() {
+        let mut line = String::new();
+        for (c, cost) in row.iter().enumerate() {
+            match arrows.get(&(r, c)) {
+                Some(&arrow) => line.push(arrow),
+                None => line.push_str(&format!("{DIM}{cost}{RESET}")),
             }
         }
+        println!("{line}");
     }
+}
 
-    unreachable!()
+fn export_json(route: &Route) {
+    println!("{{");
+    println!("  \"heat_loss\": {},", route.heat_loss);
+    println!(
+        "  \"path\": [{}]",
+        route
+            .states
+            .iter()
+            .map(|state| format!("[{}, {}]", state.coords.0, state.coords.1))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!("}}");
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     let grid = grid(stdin().lock())?;
+    let (min_run_length, max_run_length) = match args.part {
+        Part::Part1 | Part::Bench { .. } | Part::Distances { .. } => (0, 3),
+        Part::Part2 => (4, 10),
+    };
+    let min_run_length = args.min_run.unwrap_or(min_run_length);
+    let max_run_length = args.max_run.unwrap_or(max_run_length);
+    let grid_size = grid.len().max(grid.first().map_or(0, Vec::len));
+    let max_run_length = validate_run_lengths(min_run_length, max_run_length, grid_size)?;
+    let modifiers = CostModifiers {
+        turn: args.turn_cost,
+        straight: args.straight_cost,
+    };
+
     let res = match args.part {
-        Part::Part1 => heat_loss(&grid, 0, 3),
-        Part::Part2 => heat_loss(&grid, 4, 10),
+        Part::Part1 | Part::Part2 => {
+            let route = best_route(
+                &grid,
+                min_run_length,
+                max_run_length,
+                modifiers,
+                args.solver,
+                args.queue,
+            )
+            .ok_or(NoPathError)?;
+            if let Some(ExportFormat::Json) = args.export {
+                export_json(&route);
+                return Ok(());
+            }
+            if args.render {
+                render(&grid, &route);
+            }
+            route.heat_loss
+        }
+        Part::Bench { rounds } => {
+            let mut results = Vec::new();
+            for solver in Solver::value_variants() {
+                for queue in Queue::value_variants() {
+                    let start = Instant::now();
+                    let mut res = None;
+                    for _ in 0..rounds {
+                        res = best_route(
+                            black_box(&grid),
+                            min_run_length,
+                            max_run_length,
+                            modifiers,
+                            *solver,
+                            *queue,
+                        )
+                        .map(|route| route.heat_loss);
+                    }
+                    let elapsed = start.elapsed() / rounds.max(1) as u32;
+                    let solver = solver.to_possible_value().expect("No skipped variants");
+                    let queue = queue.to_possible_value().expect("No skipped variants");
+                    eprintln!(
+                        "{:>13} with {:>6}: {elapsed:?} per round",
+                        solver.get_name(),
+                        queue.get_name()
+                    );
+                    results.push(res);
+                }
+            }
+
+            for &res in &results[1..] {
+                ensure!(
+                    res == results[0],
+                    "Solvers disagree: {res:?} != {:?}",
+                    results[0]
+                );
+            }
+            results[0].ok_or(NoPathError)?
+        }
+        Part::Distances {
+            ref output,
+            to_goal,
+        } => {
+            let heat_losses =
+                all_heat_losses(&grid, min_run_length, max_run_length, modifiers, to_goal);
+            export_heat_losses(output, &heat_losses)?;
+
+            if to_goal {
+                let heuristic = manhattan_heuristic(&grid);
+                for (r, row) in heat_losses.iter().enumerate() {
+                    for (c, &heat_loss) in row.iter().enumerate() {
+                        if let Some(heat_loss) = heat_loss {
+                            ensure!(
+                                heuristic((r, c)) <= heat_loss,
+                                "A* heuristic overestimates at row {r}, column {c}: {} > {heat_loss}",
+                                heuristic((r, c))
+                            );
+                        }
+                    }
+                }
+                heat_losses[0][0]
+            } else {
+                heat_losses[grid.len() - 1][grid[0].len() - 1]
+            }
+            .ok_or(NoPathError)?
+        }
     };
     println!("{res}");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+2413432311323
+3215453535623
+3255245654254
+3446585845452
+4546657867536
+1438598798454
+4457876987766
+3637877979653
+4654967986887
+4564679986453
+1224686865563
+2546548887735
+4322674655533
+";
+
+    /// The heat loss of a route's moves, checking that it only moves one block at a time
+    fn route_heat_loss(grid: &Grid, route: &Route, modifiers: CostModifiers) -> u32 {
+        assert_eq!(route.states[0].coords, (0, 0));
+        route
+            .states
+            .windows(2)
+            .map(|pair| {
+                let (from, to) = (pair[0].coords, pair[1].coords);
+                assert_eq!(from.0.abs_diff(to.0) + from.1.abs_diff(to.1), 1);
+                modifiers.step_cost(grid, &pair[0], &pair[1])
+            })
+            .sum()
+    }
+
+    /// The answer every solver and queue agree on
+    fn solve(inp: &str, min_run_length: usize, max_run_length: usize) -> Option<u32> {
+        solve_with(
+            inp,
+            min_run_length,
+            max_run_length,
+            CostModifiers::default(),
+        )
+    }
+
+    fn solve_with(
+        inp: &str,
+        min_run_length: usize,
+        max_run_length: usize,
+        modifiers: CostModifiers,
+    ) -> Option<u32> {
+        let grid = grid(inp.as_bytes()).unwrap();
+        let mut results = Vec::new();
+        for &solver in Solver::value_variants() {
+            for &queue in Queue::value_variants() {
+                let route = best_route(
+                    &grid,
+                    min_run_length,
+                    max_run_length,
+                    modifiers,
+                    solver,
+                    queue,
+                );
+                if let Some(route) = &route {
+                    assert_eq!(route_heat_loss(&grid, route, modifiers), route.heat_loss);
+                }
+                results.push(route.map(|route| route.heat_loss));
+            }
+        }
+        assert!(results.iter().all(|&res| res == results[0]));
+        results[0]
+    }
+
+    /// Every state reachable from the start
+    fn reachable(grid: &Grid, min_run_length: usize, max_run_length: usize) -> Vec<State> {
+        let mut seen = vec![START];
+        let mut i = 0;
+        while let Some(&state) = seen.get(i) {
+            for next in successors(grid, &state, min_run_length, max_run_length) {
+                if !seen.contains(&next) {
+                    seen.push(next);
+                }
+            }
+            i += 1;
+        }
+        seen
+    }
+
+    #[test]
+    fn predecessors_reverse_successors() {
+        let grid = grid("24134\n32154\n32552\n34465".as_bytes()).unwrap();
+        for (min_run_length, max_run_length) in [(0, 3), (4, 10), (1, 1), (2, 2)] {
+            for state in reachable(&grid, min_run_length, max_run_length) {
+                for next in successors(&grid, &state, min_run_length, max_run_length) {
+                    assert!(
+                        predecessors(&grid, &next, min_run_length, max_run_length).contains(&state)
+                    );
+                }
+                for previous in predecessors(&grid, &state, min_run_length, max_run_length) {
+                    assert!(successors(&grid, &previous, min_run_length, max_run_length)
+                        .contains(&state));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn all_heat_losses_agree_with_search() {
+        let grid = grid(EXAMPLE.as_bytes()).unwrap();
+        for (min_run_length, max_run_length) in [(0, 3), (4, 10)] {
+            let expected = solve(EXAMPLE, min_run_length, max_run_length);
+            let modifiers = CostModifiers::default();
+            let from_start =
+                all_heat_losses(&grid, min_run_length, max_run_length, modifiers, false);
+            assert_eq!(from_start[grid.len() - 1][grid[0].len() - 1], expected);
+            let to_goal = all_heat_losses(&grid, min_run_length, max_run_length, modifiers, true);
+            assert_eq!(to_goal[0][0], expected);
+        }
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(solve(EXAMPLE, 0, 3), Some(102));
+        assert_eq!(solve(EXAMPLE, 4, 10), Some(94));
+    }
+
+    #[test]
+    fn rejects_invalid_run_lengths() {
+        assert_eq!(validate_run_lengths(0, 0, 13), Err(RunLengthError::ZeroMax));
+        assert_eq!(
+            validate_run_lengths(5, 4, 13),
+            Err(RunLengthError::MinAboveMax { min: 5, max: 4 })
+        );
+        assert_eq!(validate_run_lengths(4, 4, 13), Ok(4));
+        assert_eq!(validate_run_lengths(0, 1, 13), Ok(1));
+    }
+
+    #[test]
+    fn caps_max_run_length_at_grid_size() {
+        assert_eq!(validate_run_lengths(0, 100_000_000, 13), Ok(13));
+        assert_eq!(validate_run_lengths(20, 100_000_000, 13), Ok(13));
+    }
+
+    #[test]
+    fn max_run_length_limits_straight_lines() {
+        // in a single row the crucible can never turn
+        assert_eq!(solve("11111", 0, 3), None);
+        assert_eq!(solve("11111", 0, 4), Some(4));
+    }
+
+    #[test]
+    fn min_run_length_applies_at_the_end() {
+        assert_eq!(solve("11111", 4, 4), Some(4));
+        assert_eq!(solve("11111", 5, 5), None);
+    }
+
+    #[test]
+    fn equal_min_and_max_run_lengths() {
+        // runs of exactly one block force a staircase
+        assert_eq!(solve("19\n11", 1, 1), Some(2));
+        assert_eq!(solve("111\n111\n111", 2, 2), Some(4));
+    }
+
+    #[test]
+    fn cost_modifiers() {
+        let straight = CostModifiers {
+            turn: 0,
+            straight: 1,
+        };
+        // four blocks, three of them straight on from the last
+        assert_eq!(solve_with("11111", 0, 4, straight), Some(7));
+
+        let turn = CostModifiers {
+            turn: 5,
+            straight: 0,
+        };
+        // down first, then a single turn
+        assert_eq!(solve_with("19\n11", 0, 3, turn), Some(7));
+        // a staircase turns at every block, so a detour with fewer turns wins
+        assert_eq!(solve_with("111\n111\n111", 1, 1, turn), Some(19));
+        assert_eq!(solve_with("111\n111\n111", 0, 3, turn), Some(9));
+
+        let grid = grid(EXAMPLE.as_bytes()).unwrap();
+        let modifiers = CostModifiers {
+            turn: 3,
+            straight: 1,
+        };
+        let expected = solve_with(EXAMPLE, 4, 10, modifiers);
+        let from_start = all_heat_losses(&grid, 4, 10, modifiers, false);
+        assert_eq!(from_start[grid.len() - 1][grid[0].len() - 1], expected);
+        let to_goal = all_heat_losses(&grid, 4, 10, modifiers, true);
+        assert_eq!(to_goal[0][0], expected);
+    }
+}
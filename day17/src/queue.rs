@@ -0,0 +1,123 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Items popped in order of increasing priority
+pub trait PriorityQueue<T> {
+    fn push(&mut self, priority: u32, item: T);
+
+    /// The item with the lowest priority, along with that priority
+    fn pop(&mut self) -> Option<(u32, T)>;
+}
+
+impl<T: Ord> PriorityQueue<T> for BinaryHeap<Reverse<(u32, T)>> {
+    fn push(&mut self, priority: u32, item: T) {
+        BinaryHeap::push(self, Reverse((priority, item)));
+    }
+
+    fn pop(&mut self) -> Option<(u32, T)> {
+        BinaryHeap::pop(self).map(|Reverse(entry)| entry)
+    }
+}
+
+/// A monotone priority queue for small integer steps (Dial's algorithm): one bucket per priority, in a ring
+/// covering the lowest priority still queued up to the largest step above it.
+///
+/// Every pushed priority must be at least the last popped one, and at most `max_step` above it. The exception
+/// is a push into an empty queue beyond that window, which moves the window to start there. Buckets are
+/// reused once emptied, so the queue stops allocating once it has warmed up.
+pub struct BucketQueue<T> {
+    buckets: Vec<Vec<T>>,
+    current: u32,
+    len: usize,
+}
+
+impl<T> BucketQueue<T> {
+    pub fn new(max_step: u32) -> Self {
+        Self {
+            buckets: (0..=max_step).map(|_| Vec::new()).collect(),
+            current: 0,
+            len: 0,
+        }
+    }
+
+    fn bucket(&mut self, priority: u32) -> &mut Vec<T> {
+        let num_buckets = self.buckets.len();
+        &mut self.buckets[priority as usize % num_buckets]
+    }
+}
+
+impl<T> PriorityQueue<T> for BucketQueue<T> {
+    fn push(&mut self, priority: u32, item: T) {
+        if self.len == 0 && priority >= self.current + self.buckets.len() as u32 {
+            self.current = priority;
+        }
+        debug_assert!(
+            priority >= self.current && priority - self.current < self.buckets.len() as u32,
+            "Priority {priority} is outside the window starting at {}",
+            self.current
+        );
+        self.bucket(priority).push(item);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(u32, T)> {
+        if self.len == 0 {
+            return None;
+        }
+        loop {
+            if let Some(item) = self.bucket(self.current).pop() {
+                self.len -= 1;
+                return Some((self.current, item));
+            }
+            self.current += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_queue_pops_in_order() {
+        let mut queue = BucketQueue::new(9);
+        queue.push(3, 'a');
+        queue.push(9, 'b');
+        queue.push(0, 'c');
+        assert_eq!(queue.pop(), Some((0, 'c')));
+        assert_eq!(queue.pop(), Some((3, 'a')));
+
+        // the window has moved on, so this wraps around to a bucket before the current one
+        queue.push(12, 'd');
+        queue.push(5, 'e');
+        assert_eq!(queue.pop(), Some((5, 'e')));
+        assert_eq!(queue.pop(), Some((9, 'b')));
+        assert_eq!(queue.pop(), Some((12, 'd')));
+        assert_eq!(queue.pop(), None);
+
+        // once empty, a push beyond the window moves it
+        queue.push(100, 'f');
+        queue.push(105, 'g');
+        assert_eq!(queue.pop(), Some((100, 'f')));
+        assert_eq!(queue.pop(), Some((105, 'g')));
+        queue.push(107, 'h');
+        queue.push(106, 'i');
+        assert_eq!(queue.pop(), Some((106, 'i')));
+    }
+
+    #[test]
+    fn bucket_queue_matches_heap() {
+        let mut buckets = BucketQueue::new(4);
+        let mut heap = BinaryHeap::new();
+        let mut current = 0;
+        for i in 0..100u32 {
+            for step in [i % 5, (i * 7) % 5] {
+                PriorityQueue::push(&mut buckets, current + step, i);
+                PriorityQueue::push(&mut heap, current + step, i);
+            }
+            let (bucket_priority, _) = buckets.pop().unwrap();
+            let (heap_priority, _) = PriorityQueue::pop(&mut heap).unwrap();
+            assert_eq!(bucket_priority, heap_priority);
+            current = bucket_priority;
+        }
+    }
+}
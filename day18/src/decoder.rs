@@ -0,0 +1,117 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use thiserror::Error;
+
+use super::{Direction, Step};
+
+/// Turns the fields of one line of the dig plan into the direction and number of meters of its step
+pub trait Decoder {
+    fn decode(&self, step: &Step) -> Result<(Direction, usize)>;
+}
+
+/// The direction and length fields, as written
+pub struct Plain;
+
+impl Decoder for Plain {
+    fn decode(&self, step: &Step) -> Result<(Direction, usize)> {
+        Ok((
+            letter_direction(&step.direction)?,
+            decimal_distance(&step.length)?,
+        ))
+    }
+}
+
+/// The color: five hex digits of meters, then a digit for the direction
+pub struct Hex;
+
+impl Decoder for Hex {
+    fn decode(&self, step: &Step) -> Result<(Direction, usize)> {
+        Ok((
+            digit_direction(&step.color)?,
+            usize::from_str_radix(&step.color[..5], 16)?,
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Field {
+    /// The first field
+    Direction,
+    /// The second field
+    Length,
+    /// The color, read as for part2
+    Color,
+    /// The first byte of the color
+    Red,
+    /// The second byte of the color
+    Green,
+    /// The third byte of the color
+    Blue,
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.to_possible_value().expect("No skipped variants");
+        write!(f, "{}", name.get_name())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("`{0}` is not a valid direction")]
+    Direction(String),
+    #[error("Can't read a direction from the {0} field")]
+    NoDirection(Field),
+}
+
+/// The direction and distance each read from whichever field holds them
+pub struct Custom {
+    pub direction: Field,
+    pub distance: Field,
+}
+
+impl Decoder for Custom {
+    fn decode(&self, step: &Step) -> Result<(Direction, usize)> {
+        let direction = match self.direction {
+            Field::Direction => letter_direction(&step.direction)?,
+            Field::Length => letter_direction(&step.length)?,
+            Field::Color => digit_direction(&step.color)?,
+            field => return Err(DecodeError::NoDirection(field).into()),
+        };
+        let distance = match self.distance {
+            Field::Direction => decimal_distance(&step.direction)?,
+            Field::Length => decimal_distance(&step.length)?,
+            Field::Color => usize::from_str_radix(&step.color[..5], 16)?,
+            Field::Red => usize::from_str_radix(&step.color[..2], 16)?,
+            Field::Green => usize::from_str_radix(&step.color[2..4], 16)?,
+            Field::Blue => usize::from_str_radix(&step.color[4..], 16)?,
+        };
+        Ok((direction, distance))
+    }
+}
+
+/// One of `U`, `D`, `L` or `R`
+fn letter_direction(s: &str) -> Result<Direction> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Direction::try_from(c)?),
+        _ => Err(DecodeError::Direction(s.to_owned()).into()),
+    }
+}
+
+/// The last digit of the color, counting clockwise from right
+fn digit_direction(color: &str) -> Result<Direction> {
+    match color.chars().last() {
+        Some('0') => Ok(Direction::Right),
+        Some('1') => Ok(Direction::Down),
+        Some('2') => Ok(Direction::Left),
+        Some('3') => Ok(Direction::Up),
+        _ => bail!("Failed to decode direction"),
+    }
+}
+
+fn decimal_distance(s: &str) -> Result<usize> {
+    Ok(s.parse()?)
+}
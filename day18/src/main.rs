@@ -1,26 +1,96 @@
-use std::io::stdin;
+mod decoder;
 
-use anyhow::{bail, Result};
-use clap::{Parser, Subcommand};
+use std::{
+    fs::File,
+    io::{stdin, BufRead, BufWriter, Write},
+    mem::take,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use nom::{
-    bytes::complete::{tag, take},
-    character::complete::{anychar, char, digit1, multispace1},
-    combinator::{map_res, recognize},
+    bytes::complete::{is_not, tag, take_while_m_n},
+    character::complete::{anychar, char, multispace1, u64},
     sequence::delimited,
-    IResult,
+    AsChar, IResult,
 };
 use thiserror::Error;
 
+use decoder::{Custom, Decoder, Field, Hex, Plain};
+
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Shoelace)]
+    solver: Solver,
+    #[arg(long, global = true, value_enum, default_value_t = Dialect::Absolute)]
+    dialect: Dialect,
+    /// How each line gives the direction and meters of its step, instead of the part's
+    #[arg(long, global = true, value_enum)]
+    decoder: Option<DecoderKind>,
+    /// The field the custom decoder reads the direction from
+    #[arg(long, global = true, value_enum, default_value_t = Field::Direction)]
+    direction_from: Field,
+    /// The field the custom decoder reads the meters from
+    #[arg(long, global = true, value_enum, default_value_t = Field::Length)]
+    distance_from: Field,
+    /// Print the quantities the volume is made up of as JSON before the answer
+    #[arg(long, global = true)]
+    explain: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Dialect {
+    /// A direction, a length and a color per line, read by the --decoder
+    Absolute,
+    /// `L` or `R` and a length per line, turning from the current heading before each step; the first step
+    /// turns from facing up
+    Relative,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DecoderKind {
+    /// The direction and length fields, as in part1
+    Plain,
+    /// The color, as in part2
+    Hex,
+    /// The fields given by --direction-from and --distance-from
+    Custom,
+}
+
+impl DecoderKind {
+    fn decoder(self, args: &Args) -> Box<dyn Decoder> {
+        match self {
+            DecoderKind::Plain => Box::new(Plain),
+            DecoderKind::Hex => Box::new(Hex),
+            DecoderKind::Custom => Box::new(Custom {
+                direction: args.direction_from,
+                distance: args.distance_from,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Area of the polygon through the trench's corners, plus the half of the trench outside it
+    Shoelace,
+    /// Dig out every cell of the trench and count those not reachable from outside; only for small plans
+    Floodfill,
 }
 
 #[derive(PartialEq, Eq, Subcommand)]
 enum Part {
     Part1,
     Part2,
+    /// Draw the lagoon as an SVG polygon, with each corner labeled by the step that ends there, decoding the
+    /// steps as in part1 unless told otherwise
+    Render {
+        #[arg(value_name = "PATH")]
+        output: PathBuf,
+    },
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -44,10 +114,41 @@ impl From<Direction> for Coords {
     }
 }
 
+impl Direction {
+    fn turn(self, turn: Turn) -> Self {
+        match (self, turn) {
+            (Direction::Up, Turn::Left) | (Direction::Down, Turn::Right) => Direction::Left,
+            (Direction::Up, Turn::Right) | (Direction::Down, Turn::Left) => Direction::Right,
+            (Direction::Left, Turn::Left) | (Direction::Right, Turn::Right) => Direction::Down,
+            (Direction::Left, Turn::Right) | (Direction::Right, Turn::Left) => Direction::Up,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Turn {
+    Left,
+    Right,
+}
+
 #[derive(Debug, Error)]
 enum ParseError {
     #[error("`{0}` is not a valid direction")]
     Direction(char),
+    #[error("`{0}` is not a valid turn")]
+    Turn(char),
+}
+
+impl TryFrom<char> for Turn {
+    type Error = ParseError;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            'L' => Ok(Turn::Left),
+            'R' => Ok(Turn::Right),
+            _ => Err(ParseError::Turn(value)),
+        }
+    }
 }
 
 impl TryFrom<char> for Direction {
@@ -64,31 +165,35 @@ impl TryFrom<char> for Direction {
     }
 }
 
-fn direction(input: &str) -> IResult<&str, Direction> {
-    map_res(anychar, Direction::try_from)(input)
-}
+fn field(input: &str) -> IResult<&str, String> {
+    let (input, field) = is_not(" \t")(input)?;
 
-fn num(input: &str) -> IResult<&str, usize> {
-    map_res(recognize(digit1), str::parse)(input)
+    Ok((input, field.to_owned()))
 }
 
 fn color(input: &str) -> IResult<&str, String> {
-    let (input, vals) = delimited(tag("(#"), take(6usize), char(')'))(input)?;
+    let (input, vals) = delimited(
+        tag("(#"),
+        take_while_m_n(6, 6, |c: char| c.is_hex_digit()),
+        char(')'),
+    )(input)?;
 
     Ok((input, vals.to_owned()))
 }
 
+/// The fields of one line of the dig plan, left for a [`Decoder`] to make sense of
 #[derive(Debug)]
-struct Step {
-    direction: Direction,
-    length: usize,
+pub struct Step {
+    direction: String,
+    length: String,
+    /// Six hex digits
     color: String,
 }
 
 fn step(input: &str) -> IResult<&str, Step> {
-    let (input, direction) = direction(input)?;
+    let (input, direction) = field(input)?;
     let (input, _) = multispace1(input)?;
-    let (input, length) = num(input)?;
+    let (input, length) = field(input)?;
     let (input, _) = multispace1(input)?;
     let (input, color) = color(input)?;
 
@@ -102,62 +207,411 @@ fn step(input: &str) -> IResult<&str, Step> {
     ))
 }
 
-fn decode_direction(c: char) -> Result<Direction> {
-    match c {
-        '0' => Ok(Direction::Right),
-        '1' => Ok(Direction::Down),
-        '2' => Ok(Direction::Left),
-        '3' => Ok(Direction::Up),
-        _ => bail!("Failed to decode direction"),
+/// The lines of each dig plan in the input, which are separated by blank lines
+fn plans(inp: impl BufRead) -> Result<Vec<Vec<String>>> {
+    let mut plans = Vec::new();
+    let mut cur = Vec::new();
+    for line in inp.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            // tolerate repeated blank lines between plans
+            if !cur.is_empty() {
+                plans.push(take(&mut cur));
+            }
+        } else {
+            cur.push(line);
+        }
     }
+    if !cur.is_empty() {
+        plans.push(cur);
+    }
+    Ok(plans)
 }
 
-fn decode_color(s: &str) -> Result<(Direction, usize)> {
-    let direction = decode_direction(s.chars().nth(5).unwrap())?;
-    let meters = usize::from_str_radix(&s[..5], 16)?;
+/// The direction and number of meters of each step of the dig plan
+fn instructions(plan: &[String], decoder: &dyn Decoder) -> Result<Vec<(Direction, usize)>> {
+    plan.iter()
+        .map(|line| {
+            let (_, step) = step(line).map_err(|e| e.to_owned())?;
+            decoder.decode(&step)
+        })
+        .collect()
+}
+
+fn turn_step(input: &str) -> IResult<&str, (char, u64)> {
+    let (input, turn) = anychar(input)?;
+    let (input, _) = multispace1(input)?;
+    let (input, meters) = u64(input)?;
 
-    Ok((direction, meters))
+    Ok((input, (turn, meters)))
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// The direction and number of meters of each step of a dig plan in the relative dialect
+fn relative_instructions(plan: &[String]) -> Result<Vec<(Direction, usize)>> {
+    let mut heading = Direction::Up;
+    plan.iter()
+        .map(|line| {
+            let (_, (turn, meters)) = turn_step(line).map_err(|e| e.to_owned())?;
+            heading = heading.turn(Turn::try_from(turn)?);
+            Ok((heading, meters as usize))
+        })
+        .collect()
+}
 
+/// The corner reached after each step, starting from the origin
+fn corners(instructions: &[(Direction, usize)]) -> Vec<Coords> {
     let mut cur = (0, 0);
-    let mut edge = vec![cur];
-    let mut boundary_points = 0; // avoid double counting the origin
+    let mut corners = vec![cur];
+    for &(direction, meters) in instructions {
+        let (dr, dc) = Coords::from(direction);
+        cur = (cur.0 + dr * meters as isize, cur.1 + dc * meters as isize);
+        corners.push(cur);
+    }
+    corners
+}
 
-    for line in stdin().lines() {
-        let (_, step) = step(&line?).map_err(|e| e.to_owned())?;
+/// The trench dug by one step, as the inclusive ranges of rows and columns it covers
+struct Segment {
+    rows: (isize, isize),
+    cols: (isize, isize),
+}
 
-        let (direction, meters) = match args.part {
-            Part::Part1 => (step.direction, step.length),
-            Part::Part2 => decode_color(&step.color)?,
-        };
+impl Segment {
+    fn new((r1, c1): Coords, (r2, c2): Coords) -> Self {
+        Self {
+            rows: (r1.min(r2), r1.max(r2)),
+            cols: (c1.min(c2), c1.max(c2)),
+        }
+    }
+
+    /// The cells both segments cover, if any
+    fn overlap(&self, other: &Self) -> Option<Segment> {
+        let rows = (self.rows.0.max(other.rows.0), self.rows.1.min(other.rows.1));
+        let cols = (self.cols.0.max(other.cols.0), self.cols.1.min(other.cols.1));
+        (rows.0 <= rows.1 && cols.0 <= cols.1).then_some(Segment { rows, cols })
+    }
+}
+
+/// Pairs of steps, numbered from 1, whose trenches cross or overlap anywhere other than the corner between
+/// consecutive steps. Zero-length steps dig nothing, so the steps either side of them count as consecutive.
+///
+/// Segments are swept in order of their top row, comparing each only against those still reaching that row.
+fn self_intersections(instructions: &[(Direction, usize)]) -> Vec<(usize, usize)> {
+    let corners = corners(instructions);
+    let steps: Vec<_> = (0..instructions.len())
+        .filter(|&step| instructions[step].1 > 0)
+        .collect();
+    let segments: Vec<_> = steps
+        .iter()
+        .map(|&step| Segment::new(corners[step], corners[step + 1]))
+        .collect();
+    let n = segments.len();
+
+    // the only cell consecutive steps may share, including the last and first steps of a closed plan
+    let shared_corner = |i: usize, j: usize| {
+        let (i, j) = (i.min(j), i.max(j));
+        if j == i + 1 {
+            Some(corners[steps[j]])
+        } else if i == 0 && j == n - 1 && corners.last() == corners.first() {
+            Some(corners[0])
+        } else {
+            None
+        }
+    };
+
+    let mut order: Vec<_> = (0..n).collect();
+    order.sort_by_key(|&i| segments[i].rows.0);
+    let mut active: Vec<usize> = Vec::new();
+    let mut intersections = Vec::new();
+    for i in order {
+        active.retain(|&j| segments[j].rows.1 >= segments[i].rows.0);
+        for &j in &active {
+            let Some(overlap) = segments[i].overlap(&segments[j]) else {
+                continue;
+            };
+            let allowed = shared_corner(i, j)
+                .is_some_and(|(r, c)| overlap.rows == (r, r) && overlap.cols == (c, c));
+            if !allowed {
+                let (a, b) = (steps[i.min(j)], steps[i.max(j)]);
+                intersections.push((a + 1, b + 1));
+            }
+        }
+        active.push(i);
+    }
 
+    intersections.sort();
+    intersections
+}
+
+#[derive(Debug, Error)]
+#[error("The dig plan ends at {0:?} instead of returning to where it started")]
+struct UnclosedPlanError(Coords);
+
+#[derive(Debug, Error)]
+#[error("The dig plan crosses itself, at steps {}", describe_pairs(.0))]
+struct SelfIntersectionError(Vec<(usize, usize)>);
+
+fn describe_pairs(pairs: &[(usize, usize)]) -> String {
+    pairs
+        .iter()
+        .map(|(i, j)| format!("{i} and {j}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The quantities the shoelace formula and Pick's theorem combine into the volume
+struct Breakdown {
+    /// Area of the polygon through the centers of the trench's corner cells
+    area: usize,
+    /// Cells of trench, each counted once
+    boundary: usize,
+    vertices: usize,
+    /// Top-left and bottom-right corners of the trench
+    bounding_box: (Coords, Coords),
+}
+
+impl Breakdown {
+    fn new(instructions: &[(Direction, usize)]) -> Self {
+        let edge = corners(instructions);
+        // the last corner is back at the origin, so every cell of trench is counted once
+        let boundary = instructions.iter().map(|&(_, meters)| meters).sum();
+
+        // shoelace formula
+        let area = edge
+            .windows(2)
+            .map(|window| {
+                let &[(x1, y1), (x2, y2)] = window else {
+                    unreachable!()
+                };
+                x1 * y2 - y1 * x2
+            })
+            .sum::<isize>()
+            .unsigned_abs()
+            / 2;
+
+        let min = (
+            edge.iter().map(|&(r, _)| r).min().unwrap_or(0),
+            edge.iter().map(|&(_, c)| c).min().unwrap_or(0),
+        );
+        let max = (
+            edge.iter().map(|&(r, _)| r).max().unwrap_or(0),
+            edge.iter().map(|&(_, c)| c).max().unwrap_or(0),
+        );
+
+        Self {
+            area,
+            boundary,
+            vertices: instructions.len(),
+            bounding_box: (min, max),
+        }
+    }
+
+    /// Cells strictly inside the trench, by Pick's theorem
+    fn interior(&self) -> usize {
+        self.area + 1 - self.boundary / 2
+    }
+
+    fn volume(&self) -> usize {
+        self.interior() + self.boundary
+    }
+}
+
+fn shoelace_volume(instructions: &[(Direction, usize)]) -> usize {
+    Breakdown::new(instructions).volume()
+}
+
+fn explain_json(breakdown: &Breakdown, total: usize) {
+    let ((min_r, min_c), (max_r, max_c)) = breakdown.bounding_box;
+
+    println!("{{");
+    println!("  \"area\": {},", breakdown.area);
+    println!("  \"interior\": {},", breakdown.interior());
+    println!("  \"boundary\": {},", breakdown.boundary);
+    println!("  \"vertices\": {},", breakdown.vertices);
+    println!("  \"bounding_box\": {{\"min\": [{min_r}, {min_c}], \"max\": [{max_r}, {max_c}]}},");
+    println!("  \"total\": {total}");
+    println!("}}");
+}
+
+/// The most cells [`flood_fill_volume`] will allocate
+const MAX_FLOOD_CELLS: usize = 1 << 28;
+
+#[derive(Debug, Error)]
+#[error("The dig plan covers {0} cells, too many to flood fill")]
+struct TooLargeError(usize);
+
+fn flood_fill_volume(instructions: &[(Direction, usize)]) -> Result<usize> {
+    let corners = corners(instructions);
+
+    // leave a ring of undug cells around the trench so the outside is connected
+    let min_r = corners.iter().map(|&(r, _)| r).min().unwrap_or(0) - 1;
+    let max_r = corners.iter().map(|&(r, _)| r).max().unwrap_or(0) + 1;
+    let min_c = corners.iter().map(|&(_, c)| c).min().unwrap_or(0) - 1;
+    let max_c = corners.iter().map(|&(_, c)| c).max().unwrap_or(0) + 1;
+    let height = (max_r - min_r + 1) as usize;
+    let width = (max_c - min_c + 1) as usize;
+    let cells = height.saturating_mul(width);
+    if cells > MAX_FLOOD_CELLS {
+        return Err(TooLargeError(cells).into());
+    }
+
+    let mut dug = vec![false; cells];
+    let mut cur: Coords = (0, 0);
+    dug[(cur.0 - min_r) as usize * width + (cur.1 - min_c) as usize] = true;
+    for &(direction, meters) in instructions {
         let (dr, dc) = Coords::from(direction);
-        let end = (cur.0 + dr * meters as isize, cur.1 + dc * meters as isize);
+        for _ in 0..meters {
+            cur = (cur.0 + dr, cur.1 + dc);
+            dug[(cur.0 - min_r) as usize * width + (cur.1 - min_c) as usize] = true;
+        }
+    }
+
+    let mut outside = vec![false; cells];
+    let mut pending = vec![0];
+    outside[0] = true;
+    while let Some(i) = pending.pop() {
+        let (r, c) = (i / width, i % width);
+        let neighbors = [
+            (r > 0).then(|| i - width),
+            (r < height - 1).then(|| i + width),
+            (c > 0).then(|| i - 1),
+            (c < width - 1).then(|| i + 1),
+        ];
+        for j in neighbors.into_iter().flatten() {
+            if !dug[j] && !outside[j] {
+                outside[j] = true;
+                pending.push(j);
+            }
+        }
+    }
+
+    Ok(outside.iter().filter(|&&outside| !outside).count())
+}
 
-        edge.push(end);
-        cur = end;
-        boundary_points += meters;
+/// Width of the longer side of the rendered lagoon; coordinates are scaled to fit, however large they are
+const SVG_SIZE: f64 = 1000.0;
+const SVG_MARGIN: f64 = 20.0;
+
+fn render_svg(path: &Path, instructions: &[(Direction, usize)]) -> Result<()> {
+    let corners = corners(instructions);
+    let min_r = corners.iter().map(|&(r, _)| r).min().unwrap_or(0);
+    let max_r = corners.iter().map(|&(r, _)| r).max().unwrap_or(0);
+    let min_c = corners.iter().map(|&(_, c)| c).min().unwrap_or(0);
+    let max_c = corners.iter().map(|&(_, c)| c).max().unwrap_or(0);
+    let scale = SVG_SIZE / (max_r - min_r).max(max_c - min_c).max(1) as f64;
+    let point = |(r, c): Coords| {
+        (
+            SVG_MARGIN + (c - min_c) as f64 * scale,
+            SVG_MARGIN + (r - min_r) as f64 * scale,
+        )
+    };
+    let (width, height) = point((max_r, max_c));
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}">"#,
+        width + SVG_MARGIN,
+        height + SVG_MARGIN
+    )?;
+    let points: Vec<_> = corners
+        .iter()
+        .map(|&corner| {
+            let (x, y) = point(corner);
+            format!("{x:.1},{y:.1}")
+        })
+        .collect();
+    writeln!(
+        out,
+        r##"  <polygon points="{}" fill="#9cf" fill-opacity="0.5" stroke="#036" stroke-width="1"/>"##,
+        points.join(" ")
+    )?;
+    // the last corner is back at the origin, which is labeled as the start
+    for (i, &corner) in corners[..corners.len() - 1].iter().enumerate() {
+        let (x, y) = point(corner);
+        writeln!(
+            out,
+            r##"  <circle cx="{x:.1}" cy="{y:.1}" r="2" fill="#c00"/>"##
+        )?;
+        writeln!(
+            out,
+            r#"  <text x="{:.1}" y="{:.1}" font-size="8" font-family="monospace">{i}</text>"#,
+            x + 3.0,
+            y - 3.0
+        )?;
     }
+    writeln!(out, "</svg>")?;
+    out.flush()?;
+    Ok(())
+}
 
-    // shoelace formula
-    let area = edge
-        .windows(2)
-        .map(|window| {
-            let &[(x1, y1), (x2, y2)] = window else {
-                unreachable!()
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let decoder = args.decoder.unwrap_or(match args.part {
+        Part::Part1 | Part::Render { .. } => DecoderKind::Plain,
+        Part::Part2 => DecoderKind::Hex,
+    });
+    let decoder = decoder.decoder(&args);
+    let plans = plans(stdin().lock())?;
+    // only number the plans in errors and output when there's more than one
+    let numbered = plans.len() > 1;
+    let in_plan = |i: usize, e: anyhow::Error| {
+        if numbered {
+            e.context(format!("In dig plan {}", i + 1))
+        } else {
+            e
+        }
+    };
+    let plans = plans
+        .iter()
+        .enumerate()
+        .map(|(i, plan)| {
+            let instructions = match args.dialect {
+                Dialect::Absolute => instructions(plan, decoder.as_ref()),
+                Dialect::Relative => relative_instructions(plan),
             };
-            x1 * y2 - y1 * x2
+            instructions.map_err(|e| in_plan(i, e))
         })
-        .sum::<isize>()
-        .abs()
-        / 2;
+        .collect::<Result<Vec<_>>>()?;
+    if let Part::Render { output, .. } = &args.part {
+        ensure!(
+            plans.len() == 1,
+            "Can only render one dig plan, not {}",
+            plans.len()
+        );
+        return render_svg(output, &plans[0]);
+    }
 
-    let res = area + boundary_points as isize / 2 + 1;
+    let mut total = 0;
+    for (i, instructions) in plans.iter().enumerate() {
+        // neither solver can make sense of a trench that is left open or crosses itself
+        let end = corners(instructions).last().copied().unwrap_or((0, 0));
+        if end != (0, 0) {
+            return Err(in_plan(i, UnclosedPlanError(end).into()));
+        }
+        let intersections = self_intersections(instructions);
+        if !intersections.is_empty() {
+            return Err(in_plan(i, SelfIntersectionError(intersections).into()));
+        }
+        let res = match args.solver {
+            Solver::Shoelace => shoelace_volume(instructions),
+            Solver::Floodfill => flood_fill_volume(instructions).map_err(|e| in_plan(i, e))?,
+        };
+        if args.explain {
+            explain_json(&Breakdown::new(instructions), res);
+        }
+        if numbered {
+            println!("Lagoon {}: {res}", i + 1);
+        }
+        total += res;
+    }
 
-    println!("{res}");
+    if numbered {
+        println!("Total: {total}");
+    } else {
+        println!("{total}");
+    }
 
     Ok(())
 }
@@ -1,9 +1,10 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fmt::Display,
     io::{stdin, BufRead, Lines},
 };
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
 use clap::{Parser, Subcommand};
 use enum_map::{enum_map, Enum, EnumMap};
 use nom::{
@@ -21,12 +22,32 @@ use thiserror::Error;
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// List the ratings part2 accepts, one box of intervals at a time, with the rules that lead there
+    #[arg(long, global = true)]
+    explain: bool,
 }
 
 #[derive(PartialEq, Eq, Subcommand)]
 enum Part {
     Part1,
     Part2,
+    /// Print the workflows as a DOT digraph, with each edge labeled by the condition sending parts along it
+    Graph,
+    /// Warn about rules that can never fire, workflows that can't be reached or don't exist, and cycles
+    Lint,
+    /// Print a smaller set of workflows accepting the same parts, followed by the input's part ratings
+    Optimize,
+    /// Route a single part through the workflows, printing the rule that fires in each
+    Query {
+        #[arg(long)]
+        x: Value,
+        #[arg(long)]
+        m: Value,
+        #[arg(long)]
+        a: Value,
+        #[arg(long)]
+        s: Value,
+    },
 }
 
 #[derive(Debug, Clone, Copy, Enum, PartialEq, Eq)]
@@ -55,11 +76,22 @@ impl TryFrom<char> for Category {
     }
 }
 
+impl From<Category> for char {
+    fn from(value: Category) -> Self {
+        match value {
+            Category::X => 'x',
+            Category::M => 'm',
+            Category::A => 'a',
+            Category::S => 's',
+        }
+    }
+}
+
 fn category(input: &str) -> IResult<&str, Category> {
     map_res(one_of("xmas"), Category::try_from)(input)
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ComparisonType {
     Greater,
     Less,
@@ -81,6 +113,15 @@ impl TryFrom<char> for ComparisonType {
     }
 }
 
+impl From<ComparisonType> for char {
+    fn from(value: ComparisonType) -> Self {
+        match value {
+            ComparisonType::Greater => '>',
+            ComparisonType::Less => '<',
+        }
+    }
+}
+
 fn comparison_type(input: &str) -> IResult<&str, ComparisonType> {
     map_res(one_of("<>"), ComparisonType::try_from)(input)
 }
@@ -91,19 +132,64 @@ fn value(input: &str) -> IResult<&str, usize> {
     map_res(digit1, str::parse)(input)
 }
 
-#[derive(Debug)]
-enum Rule {
+/// A rule sending parts to a workflow named by `D`, as written until the names are interned
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule<D = String> {
     Comparison {
         category: Category,
         t: ComparisonType,
         v: Value,
-        dest: String,
+        dest: D,
     },
     Default {
-        dest: String,
+        dest: D,
     },
 }
 
+impl<D> Rule<D> {
+    fn dest(&self) -> &D {
+        match self {
+            Rule::Comparison { dest, .. } | Rule::Default { dest } => dest,
+        }
+    }
+
+    /// The comparison as written, or `else` for the default rule
+    fn condition(&self) -> String {
+        match self {
+            Rule::Comparison { category, t, v, .. } => {
+                format!("{}{}{v}", char::from(*category), char::from(*t))
+            }
+            Rule::Default { .. } => "else".to_owned(),
+        }
+    }
+
+    fn map_dest<E, F: FnMut(&D) -> Result<E>>(&self, mut f: F) -> Result<Rule<E>> {
+        Ok(match self {
+            Rule::Comparison {
+                category,
+                t,
+                v,
+                dest,
+            } => Rule::Comparison {
+                category: *category,
+                t: *t,
+                v: *v,
+                dest: f(dest)?,
+            },
+            Rule::Default { dest } => Rule::Default { dest: f(dest)? },
+        })
+    }
+}
+
+impl<D: Display> Display for Rule<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Comparison { dest, .. } => write!(f, "{}:{dest}", self.condition()),
+            Rule::Default { dest } => write!(f, "{dest}"),
+        }
+    }
+}
+
 fn comparison_rule(input: &str) -> IResult<&str, Rule> {
     let (input, category) = category(input)?;
     let (input, t) = comparison_type(input)?;
@@ -137,7 +223,7 @@ fn rule(input: &str) -> IResult<&str, Rule> {
     alt((comparison_rule, default_rule))(input)
 }
 
-type Workflow = Vec<Rule>;
+type Workflow<D = String> = Vec<Rule<D>>;
 
 fn workflow(input: &str) -> IResult<&str, (String, Workflow)> {
     let (input, name) = alpha1(input)?;
@@ -168,7 +254,6 @@ fn part_ratings(input: &str) -> IResult<&str, PartRatings> {
 
 const ACCEPT: &str = "A";
 const REJECT: &str = "R";
-const TERMINAL_LABELS: &[&str] = &[ACCEPT, REJECT];
 const INIT_LABEL: &str = "in";
 
 type Workflows = HashMap<String, Workflow>;
@@ -176,7 +261,7 @@ type Workflows = HashMap<String, Workflow>;
 fn parse_workflows(inp: &mut Lines<impl BufRead>) -> Result<Workflows> {
     let mut workflows = Workflows::new();
 
-    while let Some(line) = inp.next() {
+    for line in inp.by_ref() {
         let line = line?;
         if line.is_empty() {
             break;
@@ -191,7 +276,7 @@ fn parse_workflows(inp: &mut Lines<impl BufRead>) -> Result<Workflows> {
 
 fn parse_parts(inp: &mut Lines<impl BufRead>) -> Result<Vec<PartRatings>> {
     let mut parts = Vec::new();
-    while let Some(line) = inp.next() {
+    for line in inp.by_ref() {
         let line = line?;
         let (_, part) = part_ratings(&line).map_err(|e| e.to_owned())?;
         parts.push(part);
@@ -200,8 +285,26 @@ fn parse_parts(inp: &mut Lines<impl BufRead>) -> Result<Vec<PartRatings>> {
     Ok(parts)
 }
 
-fn process_one<'a>(workflow: &'a Workflow, part: &PartRatings) -> &'a str {
-    for rule in workflow {
+fn print_dot(workflows: &Workflows) {
+    let mut names: Vec<&String> = workflows.keys().collect();
+    names.sort();
+
+    println!("digraph workflows {{");
+    println!("    \"{INIT_LABEL}\" [shape=box, style=filled, fillcolor=lightblue];");
+    println!("    \"{ACCEPT}\" [shape=doublecircle, style=filled, fillcolor=green];");
+    println!("    \"{REJECT}\" [shape=doublecircle, style=filled, fillcolor=red];");
+    for name in names {
+        for rule in &workflows[name] {
+            let (dest, condition) = (rule.dest(), rule.condition());
+            println!("    \"{name}\" -> \"{dest}\" [label=\"{condition}\"];");
+        }
+    }
+    println!("}}");
+}
+
+/// The index of the rule that fires for the part, and where it sends the part
+fn process_one<'a, D>(workflow: &'a Workflow<D>, part: &PartRatings) -> (usize, &'a D) {
+    for (i, rule) in workflow.iter().enumerate() {
         match rule {
             Rule::Comparison {
                 category,
@@ -210,7 +313,7 @@ fn process_one<'a>(workflow: &'a Workflow, part: &PartRatings) -> &'a str {
                 dest,
             } => {
                 if part[*category] < *v {
-                    return dest;
+                    return (i, dest);
                 }
             }
             Rule::Comparison {
@@ -220,43 +323,128 @@ fn process_one<'a>(workflow: &'a Workflow, part: &PartRatings) -> &'a str {
                 dest,
             } => {
                 if part[*category] > *v {
-                    return dest;
+                    return (i, dest);
                 }
             }
-            Rule::Default { dest } => return dest,
+            Rule::Default { dest } => return (i, dest),
         }
     }
 
     unreachable!("Should have encountered a default rule")
 }
 
-fn process(workflows: Workflows, parts: Vec<PartRatings>) -> (Vec<PartRatings>, Vec<PartRatings>) {
-    let mut labeled: Vec<(&str, PartRatings)> =
-        parts.into_iter().map(|part| (INIT_LABEL, part)).collect();
+/// Where a rule sends parts, with workflow names interned to their index in a [`Router`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Destination {
+    Accept,
+    Reject,
+    Workflow(usize),
+}
 
-    while !labeled
-        .iter()
-        .all(|(label, _)| TERMINAL_LABELS.contains(label))
-    {
-        labeled = labeled
-            .into_iter()
-            .map(|(label, part)| {
-                if TERMINAL_LABELS.contains(&label) {
-                    return (label, part);
-                }
-                let workflow = workflows.get(label).expect("Invalid label!");
-                (process_one(workflow, &part), part)
-            })
+#[derive(Debug, Error)]
+enum RouteError {
+    #[error("There is no `{INIT_LABEL}` workflow")]
+    NoInit,
+    #[error("Workflow `{0}` sends parts to `{1}`, which doesn't exist")]
+    UnknownWorkflow(String, String),
+    #[error("The workflows send part {0:?} around in a cycle")]
+    Cycle(PartRatings),
+}
+
+/// The workflows, indexed so each part can follow its destinations straight to A or R
+struct Router {
+    names: Vec<String>,
+    workflows: Vec<Workflow<Destination>>,
+    init: usize,
+}
+
+impl Router {
+    fn new(workflows: &Workflows) -> Result<Self> {
+        // sorted so the indices don't depend on the map's order
+        let mut names: Vec<&String> = workflows.keys().collect();
+        names.sort();
+        let indices: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
             .collect();
+
+        let interned = names
+            .iter()
+            .map(|name| {
+                workflows[*name]
+                    .iter()
+                    .map(|rule| {
+                        rule.map_dest(|dest| match dest.as_str() {
+                            ACCEPT => Ok(Destination::Accept),
+                            REJECT => Ok(Destination::Reject),
+                            dest => indices
+                                .get(dest)
+                                .map(|&i| Destination::Workflow(i))
+                                .ok_or_else(|| {
+                                    RouteError::UnknownWorkflow(name.to_string(), dest.to_owned())
+                                        .into()
+                                }),
+                        })
+                    })
+                    .collect()
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            names: names.into_iter().cloned().collect(),
+            workflows: interned,
+            init: *indices.get(INIT_LABEL).ok_or(RouteError::NoInit)?,
+        })
+    }
+
+    /// Whether the part ends up accepted, starting from the `in` workflow
+    fn accepts(&self, part: &PartRatings) -> Result<bool> {
+        self.route(part, |_, _| ())
     }
 
-    let (accept, reject): (Vec<_>, Vec<_>) =
-        labeled.into_iter().partition(|(label, _)| *label == ACCEPT);
+    /// Whether the part ends up accepted, calling `hop` with the index of each workflow it passes through and
+    /// of the rule that fires there
+    fn route(&self, part: &PartRatings, mut hop: impl FnMut(usize, usize)) -> Result<bool> {
+        let mut cur = self.init;
+        // a part that doesn't cycle visits each workflow at most once
+        for _ in 0..self.workflows.len() {
+            let (rule, dest) = process_one(&self.workflows[cur], part);
+            hop(cur, rule);
+            match *dest {
+                Destination::Accept => return Ok(true),
+                Destination::Reject => return Ok(false),
+                Destination::Workflow(next) => cur = next,
+            }
+        }
+        Err(RouteError::Cycle(*part).into())
+    }
 
-    (
-        accept.into_iter().map(|(_, part)| part).collect(),
-        reject.into_iter().map(|(_, part)| part).collect(),
-    )
+    /// Where the rule sends parts, by name
+    fn dest_name(&self, workflow: usize, rule: usize) -> &str {
+        match self.workflows[workflow][rule].dest() {
+            Destination::Accept => ACCEPT,
+            Destination::Reject => REJECT,
+            Destination::Workflow(i) => &self.names[*i],
+        }
+    }
+}
+
+fn process(
+    router: &Router,
+    parts: Vec<PartRatings>,
+) -> Result<(Vec<PartRatings>, Vec<PartRatings>)> {
+    let mut accept = Vec::new();
+    let mut reject = Vec::new();
+    for part in parts {
+        if router.accepts(&part)? {
+            accept.push(part);
+        } else {
+            reject.push(part);
+        }
+    }
+
+    Ok((accept, reject))
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
@@ -285,30 +473,61 @@ impl Interval {
     fn refine_if(&self, t: ComparisonType, v: Value) -> Self {
         match t {
             ComparisonType::Greater => Self {
-                lower_bound_incl: v + 1,
+                lower_bound_incl: self.lower_bound_incl.max(v + 1),
                 upper_bound_excl: self.upper_bound_excl,
             },
             ComparisonType::Less => Self {
                 lower_bound_incl: self.lower_bound_incl,
-                upper_bound_excl: v,
+                upper_bound_excl: self.upper_bound_excl.min(v),
             },
         }
     }
 
+    fn intersect(&self, other: &Self) -> Self {
+        Self {
+            lower_bound_incl: self.lower_bound_incl.max(other.lower_bound_incl),
+            upper_bound_excl: self.upper_bound_excl.min(other.upper_bound_excl),
+        }
+    }
+
     fn refine_else(&self, t: ComparisonType, v: Value) -> Self {
         let (t, v) = match t {
             ComparisonType::Greater => (ComparisonType::Less, v + 1),
+            // nothing is less than 0, so every value fails the condition
+            ComparisonType::Less if v == 0 => return *self,
             ComparisonType::Less => (ComparisonType::Greater, v - 1),
         };
         self.refine_if(t, v)
     }
 }
 
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}..={}",
+            self.lower_bound_incl,
+            self.upper_bound_excl - 1
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Accepted {
     map: EnumMap<Category, Interval>,
 }
 
+impl Display for Accepted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let intervals: Vec<_> = self
+            .map
+            .iter()
+            .map(|(category, interval)| format!("{}={interval}", char::from(category)))
+            .collect();
+        write!(f, "{}", intervals.join(" "))
+    }
+}
+
 impl Accepted {
     fn all() -> Self {
         Self {
@@ -333,6 +552,12 @@ impl Accepted {
             .product()
     }
 
+    fn intersect(&self, other: &Self) -> Self {
+        Self {
+            map: enum_map! { c => self.map[c].intersect(&other.map[c]) },
+        }
+    }
+
     fn split_comparison(&self, category: Category, t: ComparisonType, v: usize) -> (Self, Self) {
         let if_case = Self {
             map: enum_map! {
@@ -350,16 +575,24 @@ impl Accepted {
     }
 }
 
-fn determine_accepted(workflows: &Workflows, s: &String, mut prior: Accepted) -> Vec<Accepted> {
-    if *s == ACCEPT.to_string() {
-        return vec![prior];
-    } else if *s == REJECT.to_string() {
+/// The workflows, with the number of the rule that fired in each, that ratings pass through on the way to A
+type Path<'a> = Vec<(&'a str, usize)>;
+
+fn determine_accepted<'a>(
+    workflows: &'a Workflows,
+    s: &'a str,
+    mut prior: Accepted,
+    path: &mut Path<'a>,
+) -> Vec<(Accepted, Path<'a>)> {
+    if s == ACCEPT {
+        return vec![(prior, path.clone())];
+    } else if s == REJECT {
         return Vec::new();
     }
     let workflow = workflows.get(s).expect("Invalid workflow name");
 
     let mut res = Vec::new();
-    for rule in workflow {
+    for (i, rule) in workflow.iter().enumerate() {
         let ((if_case, else_case), dep) = match rule {
             Rule::Comparison {
                 category,
@@ -369,17 +602,371 @@ fn determine_accepted(workflows: &Workflows, s: &String, mut prior: Accepted) ->
             } => (prior.split_comparison(*category, *t, *v), dest),
             Rule::Default { dest } => ((prior, Accepted::none()), dest),
         };
-        res.extend(determine_accepted(workflows, dep, if_case).into_iter());
+        path.push((s, i + 1));
+        res.extend(determine_accepted(workflows, dep, if_case, path));
+        path.pop();
         prior = else_case;
     }
 
     res
 }
 
-fn count_accepted(workflows: &Workflows) -> Value {
-    let accepted = determine_accepted(workflows, &INIT_LABEL.to_string(), Accepted::all());
+/// A problem with the workflows, located by workflow name and rule number (counting from 1)
+enum Lint {
+    NoInit,
+    /// Earlier rules in the workflow take every part this one could
+    Shadowed {
+        workflow: String,
+        rule: usize,
+    },
+    /// No rating is in range of the comparison
+    NeverMatches {
+        workflow: String,
+        rule: usize,
+    },
+    UnknownDestination {
+        workflow: String,
+        rule: usize,
+        dest: String,
+    },
+    Unreachable {
+        workflow: String,
+    },
+    /// Workflows that send parts back to the first of them
+    Cycle(Vec<String>),
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lint::NoInit => write!(f, "There is no `{INIT_LABEL}` workflow"),
+            Lint::Shadowed { workflow, rule } => write!(
+                f,
+                "{workflow}, rule {rule}: earlier rules leave no parts for this one"
+            ),
+            Lint::NeverMatches { workflow, rule } => {
+                write!(f, "{workflow}, rule {rule}: the condition matches no part")
+            }
+            Lint::UnknownDestination {
+                workflow,
+                rule,
+                dest,
+            } => write!(
+                f,
+                "{workflow}, rule {rule}: sends parts to `{dest}`, which doesn't exist"
+            ),
+            Lint::Unreachable { workflow } => {
+                write!(f, "{workflow}: can't be reached from `{INIT_LABEL}`")
+            }
+            Lint::Cycle(workflows) => {
+                write!(f, "{}: form a cycle", workflows.join(" -> "))
+            }
+        }
+    }
+}
+
+/// The first lint that leaves some part without a way through the workflows, which counting and optimizing the
+/// accepted ratings both assume can't happen
+fn unroutable(workflows: &Workflows) -> Option<Lint> {
+    lint(workflows).into_iter().find(|lint| {
+        matches!(
+            lint,
+            Lint::NoInit | Lint::UnknownDestination { .. } | Lint::Cycle(_)
+        )
+    })
+}
+
+fn lint(workflows: &Workflows) -> Vec<Lint> {
+    let mut names: Vec<&String> = workflows.keys().collect();
+    names.sort();
+    let mut lints = Vec::new();
+
+    // rules are checked against every part, not just those that can reach the workflow
+    for &name in &names {
+        let mut prior = Accepted::all();
+        for (i, rule) in workflows[name].iter().enumerate() {
+            let (dest, else_case) = match rule {
+                Rule::Comparison {
+                    category,
+                    t,
+                    v,
+                    dest,
+                } => {
+                    let (if_case, else_case) = prior.split_comparison(*category, *t, *v);
+                    let (never_matches, _) = Accepted::all().split_comparison(*category, *t, *v);
+                    if never_matches.count() == 0 {
+                        lints.push(Lint::NeverMatches {
+                            workflow: name.clone(),
+                            rule: i + 1,
+                        });
+                    } else if if_case.count() == 0 {
+                        lints.push(Lint::Shadowed {
+                            workflow: name.clone(),
+                            rule: i + 1,
+                        });
+                    }
+                    (dest, else_case)
+                }
+                Rule::Default { dest } => {
+                    if prior.count() == 0 {
+                        lints.push(Lint::Shadowed {
+                            workflow: name.clone(),
+                            rule: i + 1,
+                        });
+                    }
+                    (dest, Accepted::none())
+                }
+            };
+            if dest != ACCEPT && dest != REJECT && !workflows.contains_key(dest) {
+                lints.push(Lint::UnknownDestination {
+                    workflow: name.clone(),
+                    rule: i + 1,
+                    dest: dest.clone(),
+                });
+            }
+            prior = else_case;
+        }
+    }
+
+    if !workflows.contains_key(INIT_LABEL) {
+        lints.push(Lint::NoInit);
+    }
+    let mut reached = HashSet::new();
+    let mut pending = vec![INIT_LABEL];
+    while let Some(name) = pending.pop() {
+        let Some(workflow) = workflows.get(name) else {
+            continue;
+        };
+        if !reached.insert(name) {
+            continue;
+        }
+        pending.extend(workflow.iter().map(|rule| rule.dest().as_str()));
+    }
+    for &name in &names {
+        if !reached.contains(name.as_str()) {
+            lints.push(Lint::Unreachable {
+                workflow: name.clone(),
+            });
+        }
+    }
+
+    let mut finished = HashSet::new();
+    let mut path = Vec::new();
+    for &name in &names {
+        find_cycles(workflows, name, &mut path, &mut finished, &mut lints);
+    }
+
+    lints
+}
+
+/// Depth-first search recording a cycle whenever it comes back to a workflow still on the `path` to it
+fn find_cycles<'a>(
+    workflows: &'a Workflows,
+    name: &'a str,
+    path: &mut Vec<&'a str>,
+    finished: &mut HashSet<&'a str>,
+    lints: &mut Vec<Lint>,
+) {
+    if let Some(pos) = path.iter().position(|&on_path| on_path == name) {
+        let mut cycle: Vec<String> = path[pos..].iter().map(|&name| name.to_owned()).collect();
+        cycle.push(name.to_owned());
+        lints.push(Lint::Cycle(cycle));
+        return;
+    }
+    let Some(workflow) = workflows.get(name) else {
+        return;
+    };
+    if finished.contains(name) {
+        return;
+    }
+
+    path.push(name);
+    for rule in workflow {
+        find_cycles(workflows, rule.dest(), path, finished, lints);
+    }
+    path.pop();
+    finished.insert(name);
+}
+
+/// Drop the rules of a workflow that can never fire, cut it short at a rule that always fires, and merge
+/// adjacent rules that send parts to the same place
+fn simplify_rules(workflow: &mut Workflow) {
+    let mut prior = Accepted::all();
+    let mut kept: Workflow = Vec::new();
+    for rule in workflow.drain(..) {
+        let Rule::Comparison {
+            category,
+            t,
+            v,
+            dest,
+        } = rule
+        else {
+            kept.push(rule);
+            break;
+        };
+        let (if_case, else_case) = prior.split_comparison(category, t, v);
+        if if_case.count() == 0 {
+            continue;
+        }
+        if else_case.count() == 0 {
+            kept.push(Rule::Default { dest });
+            break;
+        }
+        prior = else_case;
+
+        // x<a:dest,x<b:dest sends x<max(a,b) to dest, and likewise for >
+        if let Some(Rule::Comparison {
+            category: last_category,
+            t: last_t,
+            v: last_v,
+            dest: last_dest,
+        }) = kept.last_mut()
+        {
+            if *last_category == category && *last_t == t && *last_dest == dest {
+                *last_v = match t {
+                    ComparisonType::Less => v.max(*last_v),
+                    ComparisonType::Greater => v.min(*last_v),
+                };
+                continue;
+            }
+        }
+        kept.push(Rule::Comparison {
+            category,
+            t,
+            v,
+            dest,
+        });
+    }
+
+    // a rule sending parts where the default would anyway is redundant
+    while let [.., second_last, last] = kept.as_slice() {
+        if second_last.dest() != last.dest() {
+            break;
+        }
+        kept.remove(kept.len() - 2);
+    }
+
+    *workflow = kept;
+}
+
+/// Send parts straight past workflows that only forward them somewhere else
+fn collapse_forwarding(workflows: &mut Workflows) {
+    let forward: HashMap<String, String> = workflows
+        .iter()
+        .filter_map(|(name, workflow)| match workflow.as_slice() {
+            [Rule::Default { dest }] if name != INIT_LABEL => Some((name.clone(), dest.clone())),
+            _ => None,
+        })
+        .collect();
+    let resolve = |dest: &String| {
+        let mut dest = dest.clone();
+        while let Some(next) = forward.get(&dest) {
+            dest = next.clone();
+        }
+        dest
+    };
+
+    for workflow in workflows.values_mut() {
+        for rule in workflow.iter_mut() {
+            *rule = rule
+                .map_dest(|dest| Ok(resolve(dest)))
+                .expect("Resolving can't fail");
+        }
+    }
+
+    // the entry point can't be skipped, but can take on the rules of the workflow it forwards to
+    if let Some([Rule::Default { dest }]) = workflows.get(INIT_LABEL).map(Vec::as_slice) {
+        if let Some(workflow) = workflows
+            .get(dest)
+            .filter(|_| dest != ACCEPT && dest != REJECT)
+        {
+            let workflow = workflow.clone();
+            workflows.insert(INIT_LABEL.to_owned(), workflow);
+        }
+    }
+}
+
+fn remove_unreachable(workflows: &mut Workflows) {
+    let mut reached = HashSet::new();
+    let mut pending = vec![INIT_LABEL.to_owned()];
+    while let Some(name) = pending.pop() {
+        let Some(workflow) = workflows.get(&name) else {
+            continue;
+        };
+        if reached.insert(name) {
+            pending.extend(workflow.iter().map(|rule| rule.dest().clone()));
+        }
+    }
+    workflows.retain(|name, _| reached.contains(name));
+}
+
+fn num_rules(workflows: &Workflows) -> usize {
+    workflows.values().map(Vec::len).sum()
+}
+
+fn optimize(workflows: &Workflows) -> Workflows {
+    let mut workflows = workflows.clone();
+    loop {
+        let before = (workflows.len(), num_rules(&workflows));
+        for workflow in workflows.values_mut() {
+            simplify_rules(workflow);
+        }
+        collapse_forwarding(&mut workflows);
+        remove_unreachable(&mut workflows);
+        if (workflows.len(), num_rules(&workflows)) == before {
+            return workflows;
+        }
+    }
+}
+
+fn print_workflows(workflows: &Workflows) {
+    let mut names: Vec<&String> = workflows.keys().collect();
+    names.sort();
+    for name in names {
+        let rules: Vec<_> = workflows[name].iter().map(Rule::to_string).collect();
+        println!("{name}{{{}}}", rules.join(","));
+    }
+}
+
+fn count_accepted(workflows: &Workflows, explain: bool) -> Value {
+    let accepted = determine_accepted(workflows, INIT_LABEL, Accepted::all(), &mut Vec::new());
+
+    if explain {
+        for (accepted, path) in &accepted {
+            if accepted.count() == 0 {
+                continue;
+            }
+            let path: Vec<_> = path
+                .iter()
+                .map(|(workflow, rule)| format!("{workflow} (rule {rule})"))
+                .collect();
+            println!(
+                "{accepted}: {} via {} -> {ACCEPT}",
+                accepted.count(),
+                path.join(" -> ")
+            );
+        }
+    }
 
-    accepted.into_iter().map(|accepted| accepted.count()).sum()
+    accepted
+        .into_iter()
+        .map(|(accepted, _)| accepted.count())
+        .sum()
+}
+
+/// The combinations of ratings accepted by each set of workflows, and by both. The sets accept exactly the same
+/// ratings when all three are equal.
+fn compare_accepted(a: &Workflows, b: &Workflows) -> (Value, Value, Value) {
+    let a = determine_accepted(a, INIT_LABEL, Accepted::all(), &mut Vec::new());
+    let b = determine_accepted(b, INIT_LABEL, Accepted::all(), &mut Vec::new());
+    let count = |accepted: &[(Accepted, Path)]| accepted.iter().map(|(x, _)| x.count()).sum();
+
+    // each set's boxes are disjoint, as every rating takes only one path through the workflows
+    let both = a
+        .iter()
+        .flat_map(|(x, _)| b.iter().map(|(y, _)| x.intersect(y).count()))
+        .sum();
+    (count(&a), count(&b), both)
 }
 
 fn main() -> Result<()> {
@@ -391,17 +978,148 @@ fn main() -> Result<()> {
     let res = match args.part {
         Part::Part1 => {
             let parts = parse_parts(&mut inp)?;
-            let (accept, _) = process(workflows, parts);
+            let (accept, _) = process(&Router::new(&workflows)?, parts)?;
 
             accept
                 .into_iter()
                 .map(|part| part.values().sum::<Value>())
                 .sum::<Value>()
         }
-        Part::Part2 => count_accepted(&workflows),
+        Part::Part2 => {
+            if let Some(problem) = unroutable(&workflows) {
+                bail!("Can't count the accepted ratings: {problem}");
+            }
+            count_accepted(&workflows, args.explain)
+        }
+        Part::Graph => {
+            print_dot(&workflows);
+            return Ok(());
+        }
+        Part::Query { x, m, a, s } => {
+            let part = enum_map! { Category::X => x, Category::M => m, Category::A => a, Category::S => s };
+            let router = Router::new(&workflows)?;
+            let accepted = router.route(&part, |workflow, rule| {
+                let condition = router.workflows[workflow][rule].condition();
+                println!(
+                    "{}: rule {} ({condition}) -> {}",
+                    router.names[workflow],
+                    rule + 1,
+                    router.dest_name(workflow, rule)
+                );
+            })?;
+            if accepted {
+                println!(
+                    "Accepted, with ratings summing to {}",
+                    part.values().sum::<Value>()
+                );
+            } else {
+                println!("Rejected");
+            }
+            return Ok(());
+        }
+        Part::Optimize => {
+            if let Some(problem) = unroutable(&workflows) {
+                bail!("Can't optimize these workflows: {problem}");
+            }
+
+            let optimized = optimize(&workflows);
+            let (before, after, both) = compare_accepted(&workflows, &optimized);
+            ensure!(
+                before == both && after == both,
+                "The optimized workflows accept {after} combinations of ratings, {both} of them among the {before} the \
+                 originals accept"
+            );
+            eprintln!(
+                "{} workflows with {} rules became {} with {}, all accepting {after} combinations",
+                workflows.len(),
+                num_rules(&workflows),
+                optimized.len(),
+                num_rules(&optimized)
+            );
+
+            print_workflows(&optimized);
+            println!();
+            for line in inp {
+                println!("{}", line?);
+            }
+            return Ok(());
+        }
+        Part::Lint => {
+            let lints = lint(&workflows);
+            for lint in &lints {
+                println!("warning: {lint}");
+            }
+            println!("{} warnings", lints.len());
+            return Ok(());
+        }
     };
 
     println!("{res}");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workflows(inp: &str) -> Workflows {
+        parse_workflows(&mut inp.as_bytes().lines()).unwrap()
+    }
+
+    #[test]
+    fn nested_comparisons_narrow_the_range() {
+        let workflows = workflows("in{x>1000:a,R}\na{x>10:A,R}\n");
+        assert_eq!(count_accepted(&workflows, false), 3000 * 4000 * 4000 * 4000);
+    }
+
+    #[test]
+    fn optimizing_keeps_the_accepted_ratings() {
+        let workflows = workflows("in{x<100:R,x>99:a,A}\na{m>10:b,m>5:A,R}\nb{m<5:R,x>50:A,A}\n");
+        let (before, after, both) = compare_accepted(&workflows, &optimize(&workflows));
+        assert_eq!((before, after), (both, both));
+    }
+
+    #[test]
+    fn comparing_tells_apart_workflows_accepting_as_many_ratings() {
+        let low = workflows("in{x<2001:A,R}\n");
+        let high = workflows("in{x>2000:A,R}\n");
+        let (a, b, both) = compare_accepted(&low, &high);
+        assert_eq!(a, b);
+        assert_eq!(both, 0);
+    }
+
+    #[test]
+    fn missing_and_cyclic_workflows_are_unroutable() {
+        assert!(unroutable(&workflows("in{x>5:zz,R}\n")).is_some());
+        assert!(unroutable(&workflows("in{x>5:a,R}\na{m>5:in,A}\n")).is_some());
+        assert!(unroutable(&workflows("in{x>5:a,R}\na{m>5:R,A}\n")).is_none());
+    }
+
+    #[test]
+    fn lint_finds_rules_shadowed_by_several_earlier_ones() {
+        let workflows = workflows("in{x<100:R,x>99:R,x>10:A,A}\n");
+        let lints: Vec<_> = lint(&workflows).iter().map(Lint::to_string).collect();
+        assert_eq!(
+            lints,
+            [
+                "in, rule 3: earlier rules leave no parts for this one",
+                "in, rule 4: earlier rules leave no parts for this one",
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_finds_conditions_out_of_the_rating_range() {
+        let workflows = workflows("in{x<0:A,x>4000:A,a<1:R,R}\n");
+        let lints: Vec<_> = lint(&workflows).iter().map(Lint::to_string).collect();
+        assert_eq!(
+            lints,
+            [
+                "in, rule 1: the condition matches no part",
+                "in, rule 2: the condition matches no part",
+                "in, rule 3: the condition matches no part",
+            ]
+        );
+    }
+}
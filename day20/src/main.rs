@@ -1,25 +1,52 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    io::{stdin, BufRead},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    io::{stderr, stdin, BufRead, Stderr},
+    time::Duration,
 };
 
-use anyhow::{bail, Result};
-use clap::{Parser, Subcommand};
+use anyhow::{bail, ensure, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use crossterm::{
+    event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
 use multimap::MultiMap;
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{alpha1, char},
-    combinator::map,
+    character::complete::{alpha1, char, digit1},
+    combinator::{map, map_res},
     multi::separated_list1,
     sequence::{preceded, separated_pair},
     IResult,
 };
+use num::integer::{ExtendedGcd, Integer};
+use ratatui::{
+    backend::CrosstermBackend,
+    style::Stylize,
+    text::{Line, Span},
+    widgets::Paragraph,
+    Terminal,
+};
+use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// Also accept inverters (`!name`), delays (`~name:presses`) and sinks (`?name`), which after part1 report
+    /// the pulses they received
+    #[arg(long, global = true)]
+    extended: bool,
+    /// Step through part1's presses, showing every module's state, before giving the answer. Controls are read
+    /// from the terminal a key at a time.
+    #[arg(long, global = true)]
+    visualize: bool,
+    /// Delay between presses while playing the visualization, in milliseconds
+    #[arg(long, global = true, default_value_t = 200)]
+    frame_delay: u64,
 }
 
 #[derive(PartialEq, Eq, Subcommand)]
@@ -28,21 +55,68 @@ enum Part {
         #[arg(default_value_t = 1000)]
         n: usize,
     },
-    Part2,
+    /// Count the presses until the target first receives the pulse
+    Part2 {
+        #[arg(long, default_value = TARGET)]
+        target: ModuleId,
+        #[arg(long, value_enum, default_value_t = Pulse::Low)]
+        pulse: Pulse,
+        /// Give up on finding when the target receives the pulse after this many presses
+        #[arg(long, default_value_t = 1_000_000)]
+        max_presses: usize,
+    },
+    /// Print the modules as a DOT digraph, shaped by type, with the conjunctions leading to `rx` highlighted
+    Graph,
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, ValueEnum)]
 enum Pulse {
     High,
     Low,
 }
 
+impl Pulse {
+    fn inverted(self) -> Self {
+        match self {
+            Pulse::High => Pulse::Low,
+            Pulse::Low => Pulse::High,
+        }
+    }
+}
+
+impl Display for Pulse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pulse::High => write!(f, "high"),
+            Pulse::Low => write!(f, "low"),
+        }
+    }
+}
+
 type ModuleId = String;
 
+#[derive(Clone, PartialEq, Eq)]
 enum Module {
-    FlipFlop { memory: bool },
-    Conjunction { memory: HashMap<ModuleId, Pulse> },
+    FlipFlop {
+        memory: bool,
+    },
+    Conjunction {
+        memory: HashMap<ModuleId, Pulse>,
+    },
     Broadcast,
+    /// Sends the opposite of every pulse it receives
+    Inverter,
+    /// Sends each pulse it receives at the start of the press `presses` later, holding them with the number of
+    /// presses left
+    Delay {
+        presses: usize,
+        pending: VecDeque<(usize, Pulse)>,
+    },
+    /// Sends nothing, counting the pulses it receives
+    Sink {
+        low: usize,
+        high: usize,
+    },
 }
 
 struct Event {
@@ -76,7 +150,96 @@ impl Module {
                 }
             }
             Module::Broadcast => Some(event.pulse),
+            Module::Inverter => Some(event.pulse.inverted()),
+            Module::Delay { presses: 0, .. } => Some(event.pulse),
+            Module::Delay { presses, pending } => {
+                pending.push_back((*presses, event.pulse));
+                None
+            }
+            Module::Sink { low, high } => {
+                match event.pulse {
+                    Pulse::High => *high += 1,
+                    Pulse::Low => *low += 1,
+                }
+                None
+            }
+        }
+    }
+
+    /// The pulses a delay is due to send as a new press starts
+    fn release(&mut self) -> Vec<Pulse> {
+        let Module::Delay { pending, .. } = self else {
+            return Vec::new();
+        };
+        for (presses_left, _) in pending.iter_mut() {
+            *presses_left -= 1;
+        }
+        // every pulse is held for the same number of presses, so the due ones are at the front
+        let due = pending
+            .iter()
+            .take_while(|(presses_left, _)| *presses_left == 0)
+            .count();
+        pending.drain(..due).map(|(_, pulse)| pulse).collect()
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(
+            self,
+            Module::Inverter | Module::Delay { .. } | Module::Sink { .. }
+        )
+    }
+}
+
+/// Whether the modules are in the same state, ignoring what sinks have counted
+fn same_state(a: &ModuleRegistry, b: &ModuleRegistry) -> bool {
+    a.len() == b.len()
+        && a.iter().all(|(id, module)| match (module, b.get(id)) {
+            (Module::Sink { .. }, Some(Module::Sink { .. })) => true,
+            (module, Some(other)) => module == other,
+            (_, None) => false,
+        })
+}
+
+/// Brent's algorithm over the states the circuit is left in by each press, so only one earlier state is held at
+/// a time, along with whatever the caller tracked alongside it
+struct CycleDetector<T> {
+    tortoise: (ModuleRegistry, T, usize),
+    power: usize,
+    lambda: usize,
+}
+
+impl<T> CycleDetector<T> {
+    fn new(registry: &ModuleRegistry, data: T) -> Self {
+        Self {
+            tortoise: (registry.clone(), data, 0),
+            power: 1,
+            lambda: 1,
+        }
+    }
+
+    /// Called after each press with the state it left: the length of the cycle if the circuit is back in the
+    /// state held from an earlier press
+    fn step(
+        &mut self,
+        registry: &ModuleRegistry,
+        press: usize,
+        data: impl FnOnce() -> T,
+    ) -> Option<usize> {
+        if same_state(registry, &self.tortoise.0) {
+            return Some(self.lambda);
         }
+        if self.power == self.lambda {
+            self.tortoise = (registry.clone(), data(), press);
+            self.power *= 2;
+            self.lambda = 0;
+        }
+        self.lambda += 1;
+        None
+    }
+
+    /// The held state, what was tracked alongside it, and the press that left it
+    fn into_start(self) -> (ModuleRegistry, T, usize) {
+        self.tortoise
     }
 }
 
@@ -108,29 +271,63 @@ fn conjunction(input: &str) -> IResult<&str, (ModuleId, Module)> {
     ))
 }
 
+fn inverter(input: &str) -> IResult<&str, (ModuleId, Module)> {
+    let (input, id) = preceded(char('!'), module_id)(input)?;
+    Ok((input, (id, Module::Inverter)))
+}
+
+fn delay(input: &str) -> IResult<&str, (ModuleId, Module)> {
+    let (input, id) = preceded(char('~'), module_id)(input)?;
+    let (input, presses) = preceded(char(':'), map_res(digit1, str::parse))(input)?;
+    Ok((
+        input,
+        (
+            id,
+            Module::Delay {
+                presses,
+                pending: VecDeque::new(),
+            },
+        ),
+    ))
+}
+
 fn module(input: &str) -> IResult<&str, (ModuleId, Module)> {
-    alt((broadcaster, flipflop, conjunction))(input)
+    alt((broadcaster, flipflop, conjunction, inverter, delay))(input)
 }
 
 fn dests(input: &str) -> IResult<&str, Vec<ModuleId>> {
     separated_list1(tag(", "), module_id)(input)
 }
 
+/// A sink has no outputs, so no arrow
+fn sink(input: &str) -> IResult<&str, ((ModuleId, Module), Vec<ModuleId>)> {
+    let (input, id) = preceded(char('?'), module_id)(input)?;
+    Ok((input, ((id, Module::Sink { low: 0, high: 0 }), Vec::new())))
+}
+
 fn module_spec(input: &str) -> IResult<&str, ((ModuleId, Module), Vec<ModuleId>)> {
-    separated_pair(module, tag(" -> "), dests)(input)
+    alt((sink, separated_pair(module, tag(" -> "), dests)))(input)
 }
 
+#[derive(Debug, Error)]
+#[error("`{0}` is one of the extra module types, which need --extended")]
+struct ExtendedModuleError(ModuleId);
+
 type ModuleRegistry = HashMap<ModuleId, Module>;
 type ModuleConnections = MultiMap<ModuleId, ModuleId>;
 
 fn parse_input(
     input: impl BufRead,
+    extended: bool,
 ) -> Result<(ModuleRegistry, ModuleConnections, ModuleConnections)> {
     let mut registry = ModuleRegistry::new();
     let mut forward = ModuleConnections::new();
     let mut reverse = ModuleConnections::new();
     for line in input.lines() {
         let (_, ((id, module), dests)) = module_spec(&line?).map_err(|e| e.to_owned())?;
+        if module.is_extended() && !extended {
+            return Err(ExtendedModuleError(id).into());
+        }
         registry.insert(id.clone(), module);
         forward.insert_many(id.clone(), dests.clone());
         reverse.extend(dests.into_iter().map(|dest| (dest, id.clone())));
@@ -151,111 +348,750 @@ fn parse_input(
     Ok((registry, forward, reverse))
 }
 
-fn button_event(target: ModuleId) -> Event {
+const BROADCASTER: &str = "broadcaster";
+const TARGET: &str = "rx";
+
+fn button_event() -> Event {
     Event {
         source: "button".into(),
-        dest: target,
+        dest: BROADCASTER.into(),
         pulse: Pulse::Low,
     }
 }
 
-fn determine_activation_period(
+fn sink_ids(registry: &ModuleRegistry) -> Vec<ModuleId> {
+    let mut ids: Vec<_> = registry
+        .iter()
+        .filter(|(_, module)| matches!(module, Module::Sink { .. }))
+        .map(|(id, _)| id.clone())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Running totals after some number of presses: the low and high pulses sent, then the low and high pulses each
+/// of the given sinks has received. Wide enough for the totals over a huge number of presses.
+type Tally = Vec<u128>;
+
+/// Press the button once, adding the pulses it sends to `tally` along with what the sinks have received since
+fn press_and_tally(
+    registry: &mut ModuleRegistry,
+    forward: &ModuleConnections,
+    sinks: &[ModuleId],
+    tally: &mut Tally,
+) {
+    press_button(registry, forward, button_event(), |event| {
+        match event.pulse {
+            Pulse::Low => tally[0] += 1,
+            Pulse::High => tally[1] += 1,
+        }
+    });
+    for (i, sink) in sinks.iter().enumerate() {
+        if let Some(Module::Sink { low, high }) = registry.get(sink) {
+            tally[2 + 2 * i] = *low as u128;
+            tally[3 + 2 * i] = *high as u128;
+        }
+    }
+}
+
+/// The tally after `n` presses. Once the whole circuit is back in a state it was in after an earlier press, the
+/// presses in between repeat forever, so the rest of the tally is worked out from theirs. `None` if a count is
+/// too large.
+fn tally_presses(
+    registry: &mut ModuleRegistry,
+    forward: &ModuleConnections,
+    sinks: &[ModuleId],
+    n: usize,
+) -> Option<Tally> {
+    let mut tally = vec![0; 2 + 2 * sinks.len()];
+
+    let mut detector = CycleDetector::new(registry, tally.clone());
+    for press in 1..=n {
+        press_and_tally(registry, forward, sinks, &mut tally);
+        if let Some(lambda) = detector.step(registry, press, || tally.clone()) {
+            let (state, mut start_tally, start) = detector.into_start();
+            let (cycles, rest) = ((n - start) / lambda, (n - start) % lambda);
+            let cycles = cycles as u128;
+            let per_cycle: Tally = tally.iter().zip(&start_tally).map(|(a, b)| a - b).collect();
+
+            // replay the presses left over after the last whole cycle
+            *registry = state;
+            for _ in 0..rest {
+                press_and_tally(registry, forward, sinks, &mut start_tally);
+            }
+            return start_tally
+                .iter()
+                .zip(per_cycle)
+                .map(|(t, c)| cycles.checked_mul(c)?.checked_add(*t))
+                .collect();
+        }
+    }
+
+    Some(tally)
+}
+
+/// The target and the conjunctions that feed it, directly or through other conjunctions
+fn target_path(registry: &ModuleRegistry, reverse: &ModuleConnections) -> HashSet<ModuleId> {
+    let mut path = HashSet::new();
+    let mut pending = vec![TARGET.to_owned()];
+    while let Some(id) = pending.pop() {
+        if !path.insert(id.clone()) {
+            continue;
+        }
+        pending.extend(
+            reverse
+                .get_vec(&id)
+                .into_iter()
+                .flatten()
+                .filter(|input| matches!(registry.get(*input), Some(Module::Conjunction { .. })))
+                .cloned(),
+        );
+    }
+    path
+}
+
+fn print_dot(registry: &ModuleRegistry, forward: &ModuleConnections, reverse: &ModuleConnections) {
+    let path = target_path(registry, reverse);
+    let mut ids: Vec<&ModuleId> = registry.keys().chain(reverse.keys()).collect();
+    ids.sort();
+    ids.dedup();
+
+    println!("digraph modules {{");
+    for &id in &ids {
+        let shape = match registry.get(id) {
+            Some(Module::Broadcast) => "doublecircle",
+            Some(Module::FlipFlop { .. }) => "box",
+            Some(Module::Conjunction { .. }) => "diamond",
+            Some(Module::Inverter) => "invtriangle",
+            Some(Module::Delay { .. }) => "hexagon",
+            Some(Module::Sink { .. }) => "cylinder",
+            None => "doubleoctagon",
+        };
+        let highlight = if path.contains(id) {
+            ", style=filled, fillcolor=salmon"
+        } else {
+            ""
+        };
+        println!("    \"{id}\" [shape={shape}{highlight}];");
+    }
+    for &id in &ids {
+        for dest in forward.get_vec(id).into_iter().flatten() {
+            let highlight = if path.contains(id) && path.contains(dest) {
+                " [color=red, penwidth=2]"
+            } else {
+                ""
+            };
+            println!("    \"{id}\" -> \"{dest}\"{highlight};");
+        }
+    }
+    println!("}}");
+}
+
+/// Deliver pulses and every pulse they set off, in the order they're sent, calling `on_event` with each. Pulses
+/// to modules missing from the registry go nowhere.
+fn propagate(
+    registry: &mut ModuleRegistry,
+    forward: &ModuleConnections,
+    init: impl IntoIterator<Item = Event>,
+    mut on_event: impl FnMut(&Event),
+) {
+    let mut events = VecDeque::from_iter(init);
+    while let Some(event) = deliver(registry, forward, &mut events) {
+        on_event(&event);
+    }
+}
+
+/// Deliver the next pulse, queueing the pulses it sets off, and return it
+fn deliver(
     registry: &mut ModuleRegistry,
     forward: &ModuleConnections,
-    input: &ModuleId,
-    output: &ModuleId,
-    expected_pulse: Pulse,
-) -> usize {
+    events: &mut VecDeque<Event>,
+) -> Option<Event> {
+    let event = events.pop_front()?;
+    let id = &event.dest;
+    if let Some(pulse) = registry
+        .get_mut(id)
+        .and_then(|module| module.handle(&event))
+    {
+        events.extend(
+            forward
+                .get_vec(id)
+                .expect("Could not find outputs for module")
+                .iter()
+                .map(|dest| Event {
+                    source: id.clone(),
+                    dest: dest.clone(),
+                    pulse,
+                }),
+        )
+    }
+    Some(event)
+}
+
+/// Start a new press with `init`, the button's pulse unless simulating part of the circuit: the pulses delays
+/// are due to send go first, then `init`, then every pulse they set off
+fn press_button(
+    registry: &mut ModuleRegistry,
+    forward: &ModuleConnections,
+    init: Event,
+    on_event: impl FnMut(&Event),
+) {
+    let events = start_press(registry, forward, init);
+    propagate(registry, forward, events, on_event);
+}
+
+/// The pulses a press starts with: those delays are due to send, then `init`
+fn start_press(
+    registry: &mut ModuleRegistry,
+    forward: &ModuleConnections,
+    init: Event,
+) -> VecDeque<Event> {
+    let mut released: Vec<(ModuleId, Vec<Pulse>)> = registry
+        .iter_mut()
+        .map(|(id, module)| (id.clone(), module.release()))
+        .filter(|(_, pulses)| !pulses.is_empty())
+        .collect();
+    // in a consistent order, however the registry is laid out
+    released.sort_by(|(a, _), (b, _)| a.cmp(b));
+
     let mut events = VecDeque::new();
-    let mut count = 0;
-    let mut received = false;
-    while !received {
-        events.push_back(button_event(input.clone()));
-        count += 1;
-
-        while let Some(event) = events.pop_front() {
-            if event.dest == *output && event.pulse == expected_pulse {
-                received = true;
+    for (id, pulses) in released {
+        for pulse in pulses {
+            events.extend(
+                forward
+                    .get_vec(&id)
+                    .into_iter()
+                    .flatten()
+                    .map(|dest| Event {
+                        source: id.clone(),
+                        dest: dest.clone(),
+                        pulse,
+                    }),
+            );
+        }
+    }
+    events.push_back(init);
+    events
+}
+
+fn module_label(id: &ModuleId, module: &Module) -> String {
+    let pulse_char = |pulse: &Pulse| match pulse {
+        Pulse::High => 'H',
+        Pulse::Low => 'L',
+    };
+    match module {
+        Module::Broadcast => id.clone(),
+        Module::FlipFlop { memory } => format!("%{id} {}", if *memory { "on" } else { "off" }),
+        Module::Conjunction { memory } => {
+            let mut inputs: Vec<_> = memory.iter().collect();
+            inputs.sort_by_key(|&(input, _)| input);
+            let memory: String = inputs
+                .into_iter()
+                .map(|(_, pulse)| pulse_char(pulse))
+                .collect();
+            format!("&{id} {memory}")
+        }
+        Module::Inverter => format!("!{id}"),
+        Module::Delay { pending, .. } => format!("~{id} {} held", pending.len()),
+        Module::Sink { low, high } => format!("?{id} {low}L {high}H"),
+    }
+}
+
+/// What the visualization has shown so far
+struct Frame<'a> {
+    press: usize,
+    presses: usize,
+    last: Option<&'a Event>,
+    low: usize,
+    high: usize,
+}
+
+/// Redraw every module's state, with the one that received the last pulse in bold
+fn draw(out: &mut ratatui::Frame, registry: &ModuleRegistry, ids: &[ModuleId], frame: &Frame) {
+    let labels: Vec<_> = ids
+        .iter()
+        .map(|id| module_label(id, &registry[id]))
+        .collect();
+    let width = labels.iter().map(String::len).max().unwrap_or(0) + 2;
+    let columns = (out.size().width as usize / width).max(1);
+
+    let status = format!(
+        "Press {} of {}: {} low and {} high pulses so far",
+        frame.press, frame.presses, frame.low, frame.high
+    );
+    let last = match frame.last {
+        Some(Event {
+            source,
+            dest,
+            pulse,
+        }) => format!("{source} -{pulse}-> {dest}"),
+        None => String::new(),
+    };
+    let mut lines = vec![Line::raw(status), Line::raw(last), Line::default()];
+    for (row_ids, row) in ids.chunks(columns).zip(labels.chunks(columns)) {
+        let spans: Vec<_> = row_ids
+            .iter()
+            .zip(row)
+            .map(|(id, label)| {
+                let span = Span::raw(format!("{label:<width$}"));
+                if frame.last.is_some_and(|event| event.dest == *id) {
+                    span.bold()
+                } else {
+                    span
+                }
+            })
+            .collect();
+        lines.push(Line::from(spans));
+    }
+    lines.push(Line::default());
+    lines.push(
+        Line::raw("[enter] finish the press  [e] next pulse  [p] play/pause  [q] quit").dim(),
+    );
+
+    out.render_widget(Paragraph::new(lines), out.size());
+}
+
+/// The terminal, in raw mode so that controls arrive a key at a time and on the alternate screen of stderr so
+/// that the frames stay out of the answer, until dropped
+struct Tui(Terminal<CrosstermBackend<Stderr>>);
+
+impl Tui {
+    fn enter() -> Result<Self> {
+        let terminal = Terminal::new(CrosstermBackend::new(stderr()))?;
+        terminal::enable_raw_mode()
+            .context("Can't put the terminal in raw mode to read controls")?;
+        let tui = Self(terminal);
+        execute!(stderr(), EnterAlternateScreen)?;
+        Ok(tui)
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        // nothing more can be done if the terminal won't go back to normal
+        let _ = execute!(stderr(), LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+enum Control {
+    Press,
+    Pulse,
+    Play,
+    Quit,
+}
+
+/// The control bound to a key, if any. Raw mode swallows Ctrl-C, so it quits like `q`.
+fn control(event: TermEvent) -> Option<Control> {
+    let TermEvent::Key(KeyEvent {
+        code,
+        modifiers,
+        kind: KeyEventKind::Press,
+        ..
+    }) = event
+    else {
+        return None;
+    };
+    match code {
+        KeyCode::Enter => Some(Control::Press),
+        KeyCode::Char('e') => Some(Control::Pulse),
+        KeyCode::Char('p') => Some(Control::Play),
+        KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => Some(Control::Quit),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Control::Quit),
+        _ => None,
+    }
+}
+
+/// Step through `presses` presses of the button, a pulse or a press at a time, or playing them back
+fn visualize(
+    mut registry: ModuleRegistry,
+    forward: &ModuleConnections,
+    presses: usize,
+    frame_delay: u64,
+) -> Result<()> {
+    // stdin holds the circuit, so crossterm reads keys from the terminal itself
+    let mut tui = Tui::enter()?;
+
+    let mut ids: Vec<ModuleId> = registry.keys().cloned().collect();
+    ids.sort();
+    let mut events = VecDeque::new();
+    let (mut press, mut low, mut high) = (0, 0, 0);
+    let mut last: Option<Event> = None;
+    let mut playing = false;
+    loop {
+        let frame = Frame {
+            press,
+            presses,
+            last: last.as_ref(),
+            low,
+            high,
+        };
+        tui.0.draw(|out| draw(out, &registry, &ids, &frame))?;
+        if press == presses && events.is_empty() {
+            return Ok(());
+        }
+
+        let whole_press = if playing {
+            if event::poll(Duration::from_millis(frame_delay))? {
+                // any key pauses
+                if let Some(Control::Quit) = control(event::read()?) {
+                    return Ok(());
+                }
+                playing = false;
+                continue;
+            }
+            true
+        } else {
+            match control(event::read()?) {
+                Some(Control::Press) => true,
+                Some(Control::Pulse) => false,
+                Some(Control::Play) => {
+                    playing = true;
+                    continue;
+                }
+                Some(Control::Quit) => return Ok(()),
+                None => continue,
+            }
+        };
+        if events.is_empty() {
+            press += 1;
+            events = start_press(&mut registry, forward, button_event());
+        }
+        while let Some(event) = deliver(&mut registry, forward, &mut events) {
+            match event.pulse {
+                Pulse::Low => low += 1,
+                Pulse::High => high += 1,
+            }
+            last = Some(event);
+            if !whole_press {
                 break;
             }
-            let id = &event.dest;
-            if let Some(pulse) = registry
-                .get_mut(id)
-                .and_then(|module| module.handle(&event))
-            {
-                events.extend(
-                    forward
-                        .get_vec(id)
-                        .expect("Could not find outputs for module")
-                        .iter()
-                        .map(|dest| Event {
-                            source: id.clone(),
-                            dest: dest.clone(),
-                            pulse,
-                        }),
-                )
+        }
+    }
+}
+
+/// Modules reachable from `start`, including it, without passing through `avoid`
+fn reachable(forward: &ModuleConnections, start: &str, avoid: Option<&str>) -> HashSet<ModuleId> {
+    let mut reached = HashSet::new();
+    let mut pending = vec![start.to_owned()];
+    while let Some(id) = pending.pop() {
+        if Some(id.as_str()) == avoid || reached.contains(&id) {
+            continue;
+        }
+        pending.extend(forward.get_vec(&id).into_iter().flatten().cloned());
+        reached.insert(id);
+    }
+    reached
+}
+
+#[derive(Debug, Error)]
+enum StructureError {
+    #[error("`{0}` isn't fed by exactly one conjunction")]
+    NoCollector(ModuleId),
+    #[error("`{0}` would have to send a high pulse, which doesn't take its inputs lining up")]
+    NoCoincidence(ModuleId),
+    #[error("The broadcaster has no outputs")]
+    NoCounters,
+    #[error("`{0}` and `{1}` drive overlapping parts of the circuit")]
+    Overlapping(ModuleId, ModuleId),
+    #[error("The part of the circuit driven by `{0}` doesn't feed `{1}`")]
+    Disconnected(ModuleId, ModuleId),
+    #[error("`{0}` feeds back into the parts of the circuit driving it")]
+    Feedback(ModuleId),
+    #[error("The part of the circuit driven by `{0}` leaves `{1}` remembering a high pulse between presses")]
+    Latched(ModuleId, ModuleId),
+    #[error(
+        "The part of the circuit driven by `{0}` doesn't settle into a cycle within {1} presses"
+    )]
+    NotPeriodic(ModuleId, usize),
+}
+
+/// Part of the circuit driven by one of the broadcaster's outputs, independent of the others, which counts
+/// presses and sends a high pulse to the conjunction feeding the target when it overflows
+struct Counter {
+    root: ModuleId,
+    modules: HashSet<ModuleId>,
+}
+
+/// The conjunction that sends a low pulse, setting off the target's pulse, when all its inputs are high at once
+///
+/// Conjunctions with a single input invert it, so they're walked back through, flipping the pulse needed.
+fn find_collector(
+    registry: &ModuleRegistry,
+    reverse: &ModuleConnections,
+    target: &str,
+    pulse: Pulse,
+) -> Result<ModuleId, StructureError> {
+    let (mut target, mut pulse) = (target.to_owned(), pulse);
+    let mut visited = HashSet::new();
+    loop {
+        let input = match reverse.get_vec(&target).map(Vec::as_slice) {
+            Some([input]) if visited.insert(input.clone()) => input,
+            _ => return Err(StructureError::NoCollector(target)),
+        };
+        let Some(Module::Conjunction { memory }) = registry.get(input) else {
+            return Err(StructureError::NoCollector(target));
+        };
+        if memory.len() == 1 {
+            (target, pulse) = (input.clone(), pulse.inverted());
+        } else if pulse == Pulse::High {
+            return Err(StructureError::NoCoincidence(input.clone()));
+        } else {
+            return Ok(input.clone());
+        }
+    }
+}
+
+/// The collector for the target's pulse, and the counters feeding it
+fn find_counters(
+    registry: &ModuleRegistry,
+    forward: &ModuleConnections,
+    reverse: &ModuleConnections,
+    target: &str,
+    pulse: Pulse,
+) -> Result<(ModuleId, Vec<Counter>), StructureError> {
+    let collector = find_collector(registry, reverse, target, pulse)?;
+    let roots = forward
+        .get_vec(BROADCASTER)
+        .ok_or(StructureError::NoCounters)?;
+
+    let mut counters: Vec<Counter> = Vec::new();
+    for root in roots {
+        let modules = reachable(forward, root, Some(&collector));
+        if let Some(other) = counters
+            .iter()
+            .find(|counter| !counter.modules.is_disjoint(&modules))
+        {
+            return Err(StructureError::Overlapping(
+                other.root.clone(),
+                root.clone(),
+            ));
+        }
+        if !modules.iter().any(|id| {
+            forward
+                .get_vec(id)
+                .is_some_and(|dests| dests.contains(&collector))
+        }) {
+            return Err(StructureError::Disconnected(root.clone(), collector));
+        }
+        counters.push(Counter {
+            root: root.clone(),
+            modules,
+        });
+    }
+
+    let downstream = reachable(forward, &collector, None);
+    if counters
+        .iter()
+        .any(|counter| !counter.modules.is_disjoint(&downstream))
+    {
+        return Err(StructureError::Feedback(collector));
+    }
+
+    Ok((collector, counters))
+}
+
+/// The first press on which the counter sends a high pulse to the collector, and the number of presses between
+/// each time it does so from then on
+fn counter_period(
+    registry: &ModuleRegistry,
+    forward: &ModuleConnections,
+    counter: &Counter,
+    collector: &ModuleId,
+    max_presses: usize,
+) -> Result<(usize, usize), StructureError> {
+    // simulated on its own, so pulses leaving the counter go nowhere
+    let mut modules: ModuleRegistry = counter
+        .modules
+        .iter()
+        .filter_map(|id| Some((id.clone(), registry.get(id)?.clone())))
+        .collect();
+    let mut first_fired: Option<(usize, ModuleRegistry)> = None;
+    for press in 1..=max_presses {
+        let mut fired = false;
+        let mut last_pulses = HashMap::new();
+        let init = Event {
+            source: BROADCASTER.into(),
+            dest: counter.root.clone(),
+            pulse: Pulse::Low,
+        };
+        press_button(&mut modules, forward, init, |event| {
+            if event.dest == *collector {
+                fired |= event.pulse == Pulse::High;
+                last_pulses.insert(event.source.clone(), event.pulse);
             }
+        });
+        // otherwise counters could line up across presses rather than within one
+        if last_pulses.values().any(|&pulse| pulse == Pulse::High) {
+            return Err(StructureError::Latched(
+                counter.root.clone(),
+                collector.clone(),
+            ));
+        }
+        if !fired {
+            continue;
+        }
+
+        // the counter is periodic if it's back in the same state when it fires again
+        match &first_fired {
+            None => first_fired = Some((press, modules.clone())),
+            Some((first, state)) if same_state(state, &modules) => {
+                return Ok((*first, press - first))
+            }
+            Some(_) => break,
         }
     }
 
-    count
+    Err(StructureError::NotPeriodic(
+        counter.root.clone(),
+        max_presses,
+    ))
+}
+
+/// The first press on which every counter fires, given the first press each fires on and its period, if there is
+/// one (by the Chinese remainder theorem, allowing for periods with common factors)
+fn first_coincidence(firings: &[(usize, usize)]) -> Option<usize> {
+    let (mut residue, mut modulus) = (0i128, 1i128);
+    for &(first, period) in firings {
+        let (first, period) = (first as i128, period as i128);
+        let ExtendedGcd { gcd, x, .. } = modulus.extended_gcd(&period);
+        let diff = first - residue;
+        if diff % gcd != 0 {
+            return None;
+        }
+        // residue + modulus * k ≡ first (mod period)
+        let step = period / gcd;
+        let k = (diff / gcd * x).rem_euclid(step);
+        residue += modulus * k;
+        modulus *= step;
+    }
+
+    // every counter has to have fired at least once
+    let start = firings.iter().map(|&(first, _)| first as i128).max()?;
+    let behind = (start - residue).max(0);
+    usize::try_from(residue + (behind + modulus - 1) / modulus * modulus).ok()
+}
+
+#[derive(Debug, Error)]
+enum SimulationError {
+    #[error("The circuit returns to an earlier state after {0} presses without `{1}` receiving a {2} pulse")]
+    Cycle(usize, ModuleId, Pulse),
+    #[error("`{1}` didn't receive a {2} pulse within {0} presses")]
+    TooManyPresses(usize, ModuleId, Pulse),
+}
+
+/// Press the button until the target receives the pulse, stopping if the whole circuit returns to an earlier
+/// state, after which nothing new can happen
+fn simulate_until(
+    registry: &mut ModuleRegistry,
+    forward: &ModuleConnections,
+    target: &str,
+    pulse: Pulse,
+    max_presses: usize,
+) -> Result<usize, SimulationError> {
+    let mut detector = CycleDetector::new(registry, ());
+    for press in 1..=max_presses {
+        let mut received = false;
+        press_button(registry, forward, button_event(), |event| {
+            received |= event.dest == target && event.pulse == pulse;
+        });
+        if received {
+            return Ok(press);
+        }
+        if detector.step(registry, press, || ()).is_some() {
+            return Err(SimulationError::Cycle(press, target.to_owned(), pulse));
+        }
+    }
+
+    Err(SimulationError::TooManyPresses(
+        max_presses,
+        target.to_owned(),
+        pulse,
+    ))
+}
+
+/// The first press on which the target receives the pulse, found from the periods of the counters feeding it
+fn presses_from_counters(
+    registry: &ModuleRegistry,
+    forward: &ModuleConnections,
+    reverse: &ModuleConnections,
+    target: &str,
+    pulse: Pulse,
+    max_presses: usize,
+) -> Result<Option<usize>, StructureError> {
+    let (collector, counters) = find_counters(registry, forward, reverse, target, pulse)?;
+    let firings = counters
+        .iter()
+        .map(|counter| counter_period(registry, forward, counter, &collector, max_presses))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(first_coincidence(&firings))
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let (mut registry, forward, reverse) = parse_input(stdin().lock())?;
+    let (mut registry, forward, reverse) = parse_input(stdin().lock(), args.extended)?;
+
+    ensure!(
+        !args.visualize || matches!(args.part, Part::Part1 { .. }),
+        "--visualize only applies to part1"
+    );
+    if let (true, Part::Part1 { n }) = (args.visualize, &args.part) {
+        visualize(registry.clone(), &forward, *n, args.frame_delay)?;
+    }
 
     let res = match args.part {
         Part::Part1 { n } => {
-            let mut counts: HashMap<Pulse, usize> = HashMap::new();
-            let mut events = VecDeque::new();
-            for _ in 0..n {
-                events.push_back(button_event("broadcaster".into()));
-
-                while let Some(event) = events.pop_front() {
-                    *counts.entry(event.pulse).or_default() += 1;
-                    let id = &event.dest;
-                    if let Some(pulse) = registry
-                        .get_mut(id)
-                        .and_then(|module| module.handle(&event))
-                    {
-                        events.extend(
-                            forward
-                                .get_vec(id)
-                                .expect("Could not find outputs for module")
-                                .iter()
-                                .map(|dest| Event {
-                                    source: id.clone(),
-                                    dest: dest.clone(),
-                                    pulse,
-                                }),
-                        )
-                    }
-                }
+            let sinks = sink_ids(&registry);
+            let tally = tally_presses(&mut registry, &forward, &sinks, n)
+                .context("The pulse counts are too large")?;
+            for (id, counts) in sinks.iter().zip(tally[2..].chunks(2)) {
+                eprintln!(
+                    "Sink `{id}` received {} low and {} high pulses",
+                    counts[0], counts[1]
+                );
             }
 
-            counts.values().product::<usize>()
+            tally[0]
+                .checked_mul(tally[1])
+                .context("The product of the pulse counts is too large")?
         }
-        Part::Part2 => {
-            let Some(origins) = forward.get_vec("broadcaster") else {
-                bail!("Could not find broadcast node's outputs");
-            };
-
-            let Some(dest) = reverse.get("rx") else {
-                bail!("Could not find 'rx' node's input");
-            };
-
-            origins
-                .iter()
-                .map(|source| {
-                    determine_activation_period(&mut registry, &forward, source, dest, Pulse::High)
-                })
-                .reduce(num::integer::lcm)
-                .expect("No outputs of broadcast node")
+        Part::Part2 {
+            target,
+            pulse,
+            max_presses,
+        } => match presses_from_counters(&registry, &forward, &reverse, &target, pulse, max_presses) {
+            Ok(Some(presses)) => presses as u128,
+            Ok(None) => bail!(
+                "The counters never all fire on the same press, so `{target}` never receives a {pulse} pulse"
+            ),
+            Err(e) => {
+                eprintln!("{e}, so simulating every press");
+                simulate_until(&mut registry, &forward, &target, pulse, max_presses)? as u128
+            }
+        },
+        Part::Graph => {
+            print_dot(&registry, &forward, &reverse);
+            return Ok(());
         }
     };
 
     println!("{res}");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_forwarded_tally_can_exceed_u64() {
+        let input = "broadcaster -> a, b, c\n%a -> b\n%b -> c\n%c -> inv\n&inv -> a\n";
+        let (mut registry, forward, _) = parse_input(input.as_bytes(), false).unwrap();
+        let n = 10_000_000_000;
+        let tally = tally_presses(&mut registry, &forward, &[], n).unwrap();
+        assert_eq!(tally, [8 * n as u128, 4 * n as u128]);
+        assert!(tally[0] * tally[1] > u64::MAX as u128);
+    }
+}
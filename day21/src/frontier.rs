@@ -0,0 +1,118 @@
+/// A set of cells in a rectangle, one bit per cell, packed along each row
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitGrid {
+    width: usize,
+    /// The number of words in each row
+    stride: usize,
+    words: Vec<u64>,
+}
+
+impl BitGrid {
+    pub fn new(height: usize, width: usize) -> Self {
+        let stride = width.div_ceil(64);
+        Self {
+            width,
+            stride,
+            words: vec![0; height * stride],
+        }
+    }
+
+    pub fn insert(&mut self, (r, c): (usize, usize)) {
+        debug_assert!(c < self.width);
+        self.words[r * self.stride + c / 64] |= 1 << (c % 64);
+    }
+
+    pub fn contains(&self, (r, c): (usize, usize)) -> bool {
+        let height = self.words.len() / self.stride;
+        r < height && c < self.width && self.words[r * self.stride + c / 64] & 1 << (c % 64) != 0
+    }
+
+    /// The height and width of the rectangle
+    pub fn size(&self) -> (usize, usize) {
+        (self.words.len() / self.stride, self.width)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Overwrite `next` with the cells of `open` one step north, south, east or west of a cell in this set
+    pub fn step_into(&self, open: &BitGrid, next: &mut BitGrid) {
+        let stride = self.stride;
+        let height = self.words.len() / stride;
+        for r in 0..height {
+            let row = &self.words[r * stride..(r + 1) * stride];
+            let above = (r > 0).then(|| &self.words[(r - 1) * stride..r * stride]);
+            let below = (r + 1 < height).then(|| &self.words[(r + 1) * stride..(r + 2) * stride]);
+            for i in 0..stride {
+                let mut word = row[i] << 1 | row[i] >> 1;
+                if i > 0 {
+                    word |= row[i - 1] >> 63;
+                }
+                if i + 1 < stride {
+                    word |= row[i + 1] << 63;
+                }
+                if let Some(above) = above {
+                    word |= above[i];
+                }
+                if let Some(below) = below {
+                    word |= below[i];
+                }
+                next.words[r * stride + i] = word & open.words[r * stride + i];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_crosses_words() {
+        let mut open = BitGrid::new(3, 130);
+        for r in 0..3 {
+            for c in 0..130 {
+                open.insert((r, c));
+            }
+        }
+        let mut reached = BitGrid::new(3, 130);
+        reached.insert((1, 63));
+        reached.insert((1, 128));
+
+        let mut next = BitGrid::new(3, 130);
+        reached.step_into(&open, &mut next);
+        let mut expected = BitGrid::new(3, 130);
+        for cell in [(0, 63), (2, 63), (1, 62), (1, 64)] {
+            expected.insert(cell);
+        }
+        for cell in [(0, 128), (2, 128), (1, 127), (1, 129)] {
+            expected.insert(cell);
+        }
+        assert_eq!(next, expected);
+        assert_eq!(next.len(), 8);
+    }
+
+    #[test]
+    fn step_stays_in_open_cells() {
+        let mut open = BitGrid::new(1, 3);
+        open.insert((0, 0));
+        open.insert((0, 1));
+        let mut reached = BitGrid::new(1, 3);
+        reached.insert((0, 1));
+
+        let mut expected = BitGrid::new(1, 3);
+        expected.insert((0, 0));
+        let mut next = BitGrid::new(1, 3);
+        next.insert((0, 2));
+        reached.step_into(&open, &mut next);
+        assert_eq!(next, expected);
+    }
+}
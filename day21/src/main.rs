@@ -1,37 +1,131 @@
+mod frontier;
+
 use std::{
-    collections::{hash_map::Entry, HashMap, VecDeque},
-    io::{stdin, BufRead},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    fmt::Display,
+    io::{self, stdin, stdout, BufRead, Write},
+    thread::sleep,
+    time::Duration,
 };
 
-use anyhow::bail;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, ensure};
+use clap::{Parser, Subcommand, ValueEnum};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use thiserror::Error;
+
+use frontier::BitGrid;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
     n: usize,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Bitset)]
+    solver: Solver,
+    /// Break part2's answer down by the copies of the map the plots are in, before the answer
+    #[arg(long, global = true)]
+    explain: bool,
+    /// Start from the plot at a row and column, counting from 1, e.g. `66,66`, instead of the map's `S`s; may be
+    /// repeated
+    #[arg(long = "start", global = true, value_name = "ROW,COL", value_parser = coords)]
+    starts: Vec<Coords>,
+    /// How the map extends beyond its edges for part2, and when drawing copies of it
+    #[arg(long, global = true, value_enum, default_value_t = Tiling::Repeat)]
+    tiling: Tiling,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Tiling {
+    /// Copies of the map side by side
+    Repeat,
+    /// Copies of the map reflected across each of its edges
+    Mirror,
+    /// The map alone, surrounded by rock
+    Walled,
+}
+
+impl Display for Tiling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.to_possible_value().expect("No skipped variants");
+        write!(f, "{}", name.get_name())
+    }
+}
+
+impl Tiling {
+    fn grid(self) -> &'static Grid {
+        match self {
+            Tiling::Repeat => &REPEATED,
+            Tiling::Mirror => &MIRRORED,
+            Tiling::Walled => &BOUNDED,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("`{0}` is not a position of the form ROW,COL")]
+struct ParseCoordsError(String);
+
+fn coords(s: &str) -> Result<Coords, ParseCoordsError> {
+    let parse = || {
+        let (r, c) = s.split_once(',')?;
+        Some((
+            r.trim().parse::<isize>().ok()? - 1,
+            c.trim().parse::<isize>().ok()? - 1,
+        ))
+    };
+    parse().ok_or_else(|| ParseCoordsError(s.to_owned()))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Step the set of plots reachable in exactly k steps, a row of bits at a time
+    Bitset,
+    /// Find the distance to every plot within `n` steps breadth first
+    Bfs,
+    /// Step the plots reached in each copy of the map separately, passing on those that step across an edge to
+    /// the neighbouring copy, so only copies the frontier has reached take up space
+    Tiled,
 }
 
 #[derive(Subcommand)]
 enum Part {
     Part1,
-    Part2,
+    Part2 {
+        /// Flood fill the infinite grid out to `n` steps, rather than extrapolating from smaller step counts
+        #[arg(long)]
+        simulate: bool,
+        /// Before the answer, check the extrapolation against simulating the next K step counts it applies to
+        #[arg(long, value_name = "K", conflicts_with = "simulate")]
+        verify: Option<usize>,
+    },
+    /// Draw the map with the plots reachable in exactly `n` steps marked
+    Render {
+        /// Step on the infinite grid, drawing this many copies of the map on every side of the original
+        #[arg(long)]
+        tiles: Option<usize>,
+        /// Animate the frontier spreading out a step at a time
+        #[arg(long)]
+        animate: bool,
+        /// Delay between animation frames, in milliseconds
+        #[arg(long, default_value_t = 100)]
+        frame_delay: u64,
+    },
 }
 
 type Coords = (isize, isize);
 type Map = Vec<Vec<bool>>;
 
-fn parse_input(input: impl BufRead) -> anyhow::Result<(Map, Coords)> {
+fn parse_input(input: impl BufRead) -> anyhow::Result<(Map, Vec<Coords>)> {
     let mut map = Vec::new();
-    let mut start = Coords::default();
+    let mut starts = Vec::new();
     for (i, line) in input.lines().enumerate() {
         let line = line?;
         let mut row = Vec::new();
         for (j, c) in line.chars().enumerate() {
             row.push(match c {
                 'S' => {
-                    start = (i as isize, j as isize);
+                    starts.push((i as isize, j as isize));
                     false
                 }
                 '.' => false,
@@ -43,7 +137,7 @@ fn parse_input(input: impl BufRead) -> anyhow::Result<(Map, Coords)> {
         map.push(row);
     }
 
-    Ok((map, start))
+    Ok((map, starts))
 }
 
 #[derive(Clone, Copy)]
@@ -77,19 +171,90 @@ fn move_p2(_: &Map, (r, c): Coords, direction: Direction) -> Option<Coords> {
     }
 }
 
-fn check_p2(map: &Map, (r, c): Coords) -> bool {
-    map[r.rem_euclid(map.len() as isize) as usize][c.rem_euclid(map[0].len() as isize) as usize]
+fn check_p2(map: &Map, coords: Coords) -> bool {
+    check_tiled(map, coords, repeat_index)
+}
+
+fn check_mirrored(map: &Map, coords: Coords) -> bool {
+    check_tiled(map, coords, mirror_index)
+}
+
+/// Whether there's a rock at `(r, c)`, finding the plot in the map it's a copy of a row and a column at a time
+fn check_tiled(map: &Map, (r, c): Coords, index_func: fn(isize, usize) -> usize) -> bool {
+    map[index_func(r, map.len())][index_func(c, map[0].len())]
+}
+
+/// The index in `0..len` that `i` is a copy of, with copies side by side
+fn repeat_index(i: isize, len: usize) -> usize {
+    i.rem_euclid(len as isize) as usize
+}
+
+/// The index in `0..len` that `i` is a copy of, with every other copy reversed
+fn mirror_index(i: isize, len: usize) -> usize {
+    let i = i.rem_euclid(2 * len as isize) as usize;
+    if i < len {
+        i
+    } else {
+        2 * len - 1 - i
+    }
+}
+
+/// How the map extends beyond its edges
+struct Grid {
+    move_func: fn(&Map, Coords, Direction) -> Option<Coords>,
+    check_func: fn(&Map, Coords) -> bool,
+    /// The corners of a region containing every plot within `n` steps of the starts
+    bounds_func: fn(&Map, &[Coords], usize) -> (Coords, Coords),
+}
+
+/// The map alone, surrounded by nothing that can be walked on
+const BOUNDED: Grid = Grid {
+    move_func: move_p1,
+    check_func: check_p1,
+    bounds_func: bounds_p1,
+};
+
+/// The map repeated infinitely in every direction
+const REPEATED: Grid = Grid {
+    move_func: move_p2,
+    check_func: check_p2,
+    bounds_func: bounds_p2,
+};
+
+/// The map reflected infinitely in every direction
+const MIRRORED: Grid = Grid {
+    move_func: move_p2,
+    check_func: check_mirrored,
+    bounds_func: bounds_p2,
+};
+
+fn bounds_p1(map: &Map, _: &[Coords], _: usize) -> (Coords, Coords) {
+    ((0, 0), (map.len() as isize - 1, map[0].len() as isize - 1))
+}
+
+fn bounds_p2(_: &Map, starts: &[Coords], n: usize) -> (Coords, Coords) {
+    let n = n as isize;
+    let rows = starts.iter().map(|&(r, _)| r);
+    let cols = starts.iter().map(|&(_, c)| c);
+    (
+        (
+            rows.clone().min().unwrap_or(0) - n,
+            cols.clone().min().unwrap_or(0) - n,
+        ),
+        (rows.max().unwrap_or(0) + n, cols.max().unwrap_or(0) + n),
+    )
 }
 
+/// The distance to every plot within `max_dist` steps of the nearest start
 fn floodfill(
     map: &Map,
-    start: Coords,
+    starts: &[Coords],
     max_dist: usize,
     move_func: fn(&Map, Coords, Direction) -> Option<Coords>,
     check_func: fn(&Map, Coords) -> bool,
 ) -> HashMap<Coords, usize> {
-    let mut distances: HashMap<Coords, usize> = HashMap::from([(start, 0)]);
-    let mut to_visit = VecDeque::from([(start, 0)]);
+    let mut distances: HashMap<Coords, usize> = starts.iter().map(|&start| (start, 0)).collect();
+    let mut to_visit: VecDeque<_> = starts.iter().map(|&start| (start, 0)).collect();
 
     while let Some((coords, dist)) = to_visit.pop_front() {
         let new_dist = dist + 1;
@@ -121,19 +286,619 @@ fn floodfill(
     distances
 }
 
+/// The number of plots reachable from any of the starts in exactly k steps, for each k up to `n`
+fn count_reachable(
+    map: &Map,
+    starts: &[Coords],
+    n: usize,
+    grid: &Grid,
+    solver: Solver,
+) -> Vec<usize> {
+    match solver {
+        Solver::Bitset => step_frontier(map, starts, n, grid),
+        Solver::Tiled => {
+            let mut frontier = TiledFrontier::new(map, starts, n, grid);
+            let mut counts = vec![frontier.len()];
+            for _ in 0..n {
+                frontier.step();
+                counts.push(frontier.len());
+            }
+            counts
+        }
+        Solver::Bfs => {
+            // every step swaps between plots with an even and an odd sum of coordinates, so the starts of each kind
+            // reach disjoint sets of plots on any given step, but the nearest start may be of the wrong kind
+            let (even, odd): (Vec<_>, Vec<_>) = starts
+                .iter()
+                .partition(|&&(r, c)| (r + c).rem_euclid(2) == 0);
+            let mut counts = vec![0; n + 1];
+            for starts in [even, odd] {
+                // a plot reachable in d steps is reachable in d+2, d+4, ... by stepping back and forth
+                let mut at_distance = vec![0; n + 1];
+                for dist in
+                    floodfill(map, &starts, n, grid.move_func, grid.check_func).into_values()
+                {
+                    at_distance[dist] += 1;
+                }
+                for k in 0..=n {
+                    if k >= 2 {
+                        at_distance[k] += at_distance[k - 2];
+                    }
+                    counts[k] += at_distance[k];
+                }
+            }
+            counts
+        }
+    }
+}
+
+/// The plots reachable in exactly some number of steps, within the region that can be reached in at most `n`
+struct Frontier {
+    /// The coordinates of the region's top left corner
+    origin: Coords,
+    open: BitGrid,
+    reached: BitGrid,
+    next: BitGrid,
+}
+
+impl Frontier {
+    fn new(map: &Map, starts: &[Coords], n: usize, grid: &Grid) -> Self {
+        let ((r0, c0), (r1, c1)) = (grid.bounds_func)(map, starts, n);
+        let (height, width) = ((r1 - r0 + 1) as usize, (c1 - c0 + 1) as usize);
+        let mut open = BitGrid::new(height, width);
+        for r in r0..=r1 {
+            for c in c0..=c1 {
+                if !(grid.check_func)(map, (r, c)) {
+                    open.insert(((r - r0) as usize, (c - c0) as usize));
+                }
+            }
+        }
+
+        let mut reached = BitGrid::new(height, width);
+        for &(r, c) in starts {
+            reached.insert(((r - r0) as usize, (c - c0) as usize));
+        }
+        Self {
+            origin: (r0, c0),
+            next: reached.clone(),
+            open,
+            reached,
+        }
+    }
+
+    fn step(&mut self) {
+        self.reached.step_into(&self.open, &mut self.next);
+        std::mem::swap(&mut self.reached, &mut self.next);
+    }
+
+    fn len(&self) -> usize {
+        self.reached.len()
+    }
+
+    fn contains(&self, (r, c): Coords) -> bool {
+        let (r, c) = (r - self.origin.0, c - self.origin.1);
+        r >= 0 && c >= 0 && self.reached.contains((r as usize, c as usize))
+    }
+}
+
+fn step_frontier(map: &Map, starts: &[Coords], n: usize, grid: &Grid) -> Vec<usize> {
+    let mut frontier = Frontier::new(map, starts, n, grid);
+    let mut counts = vec![frontier.len()];
+    for _ in 0..n {
+        frontier.step();
+        counts.push(frontier.len());
+    }
+    counts
+}
+
+/// Which copy of the map, counting rows and columns of copies from the original
+type TileId = (isize, isize);
+
+/// The plots reachable in exactly some number of steps, kept separately for each copy of the map
+struct TiledFrontier<'a> {
+    map: &'a Map,
+    grid: &'a Grid,
+    /// The corners of the region that can be reached at all
+    bounds: (Coords, Coords),
+    /// The plots in each copy of the map that aren't rocks, for the copies reached so far and their neighbours
+    open: HashMap<TileId, BitGrid>,
+    reached: HashMap<TileId, BitGrid>,
+}
+
+impl<'a> TiledFrontier<'a> {
+    fn new(map: &'a Map, starts: &[Coords], n: usize, grid: &'a Grid) -> Self {
+        let mut frontier = Self {
+            map,
+            grid,
+            bounds: (grid.bounds_func)(map, starts, n),
+            open: HashMap::new(),
+            reached: HashMap::new(),
+        };
+        let (height, width) = frontier.tile_size();
+        for &(r, c) in starts {
+            let tile = (r.div_euclid(height as isize), c.div_euclid(width as isize));
+            frontier
+                .reached
+                .entry(tile)
+                .or_insert_with(|| BitGrid::new(height, width))
+                .insert((
+                    r.rem_euclid(height as isize) as usize,
+                    c.rem_euclid(width as isize) as usize,
+                ));
+        }
+        frontier
+    }
+
+    fn tile_size(&self) -> (usize, usize) {
+        (self.map.len(), self.map[0].len())
+    }
+
+    fn open_plots(&self, (tr, tc): TileId) -> BitGrid {
+        let (height, width) = self.tile_size();
+        let mut open = BitGrid::new(height, width);
+        for r in 0..height {
+            for c in 0..width {
+                let (row, col) = (
+                    tr * height as isize + r as isize,
+                    tc * width as isize + c as isize,
+                );
+                let ((r0, c0), (r1, c1)) = self.bounds;
+                let inside = (r0..=r1).contains(&row) && (c0..=c1).contains(&col);
+                if inside && !(self.grid.check_func)(self.map, (row, col)) {
+                    open.insert((r, c));
+                }
+            }
+        }
+        open
+    }
+
+    fn step(&mut self) {
+        let tiles: HashSet<TileId> = self
+            .reached
+            .keys()
+            .flat_map(|&(tr, tc)| {
+                [
+                    (tr, tc),
+                    (tr - 1, tc),
+                    (tr + 1, tc),
+                    (tr, tc - 1),
+                    (tr, tc + 1),
+                ]
+            })
+            .collect();
+        for &tile in &tiles {
+            if !self.open.contains_key(&tile) {
+                let open = self.open_plots(tile);
+                self.open.insert(tile, open);
+            }
+        }
+        let tiles: Vec<TileId> = tiles.into_iter().collect();
+        self.reached = step_tiles(&tiles, &self.reached, &self.open);
+    }
+
+    fn len(&self) -> usize {
+        self.reached.values().map(BitGrid::len).sum()
+    }
+}
+
+/// The open plots of `tile` one step from a plot reached, either in it or across its edge in a neighbouring copy
+fn step_tile((tr, tc): TileId, reached: &HashMap<TileId, BitGrid>, open: &BitGrid) -> BitGrid {
+    let (height, width) = open.size();
+    let mut next = BitGrid::new(height, width);
+    if let Some(current) = reached.get(&(tr, tc)) {
+        current.step_into(open, &mut next);
+    }
+
+    let [above, below, left, right] =
+        [(tr - 1, tc), (tr + 1, tc), (tr, tc - 1), (tr, tc + 1)].map(|tile| reached.get(&tile));
+    let (last_r, last_c) = (height - 1, width - 1);
+    let mut cross = |neighbour: Option<&BitGrid>, from: (usize, usize), to: (usize, usize)| {
+        if neighbour.is_some_and(|neighbour| neighbour.contains(from)) && open.contains(to) {
+            next.insert(to);
+        }
+    };
+    for c in 0..width {
+        cross(above, (last_r, c), (0, c));
+        cross(below, (0, c), (last_r, c));
+    }
+    for r in 0..height {
+        cross(left, (r, last_c), (r, 0));
+        cross(right, (r, 0), (r, last_c));
+    }
+    next
+}
+
+/// Each copy's next step only depends on the current one, so with the `parallel` feature copies are stepped on
+/// separate threads
+fn step_tiles(
+    tiles: &[TileId],
+    reached: &HashMap<TileId, BitGrid>,
+    open: &HashMap<TileId, BitGrid>,
+) -> HashMap<TileId, BitGrid> {
+    #[cfg(feature = "parallel")]
+    let tiles = tiles.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let tiles = tiles.iter();
+
+    tiles
+        .map(|&tile| (tile, step_tile(tile, reached, &open[&tile])))
+        .filter(|(_, next)| !next.is_empty())
+        .collect()
+}
+
+#[derive(Debug, Error)]
+enum ExtrapolationError {
+    #[error("The map is {0}x{1}, not square")]
+    NotSquare(usize, usize),
+    #[error("There isn't a single start, in the center of the map")]
+    OffCenter,
+    #[error("The row and column through the start aren't clear of rocks")]
+    Obstructed,
+    #[error("{n} steps don't end on the edge of a copy of the map, as {n} isn't {0} more than a multiple of {1}", .size / 2, .size)]
+    Offset { n: usize, size: usize },
+    #[error("{0} steps don't leave the original map")]
+    Inside(usize),
+    #[error("Extrapolating relies on the map repeating, which it doesn't with --tiling {0}")]
+    Tiling(Tiling),
+}
+
+/// Check the map and `n` have the shape extrapolating relies on, returning the size of the map
+fn check_shape(
+    map: &Map,
+    starts: &[Coords],
+    n: usize,
+    tiling: Tiling,
+) -> Result<usize, ExtrapolationError> {
+    if !matches!(tiling, Tiling::Repeat) {
+        return Err(ExtrapolationError::Tiling(tiling));
+    }
+    let size = map.len();
+    if let Some(row) = map.iter().find(|row| row.len() != size) {
+        return Err(ExtrapolationError::NotSquare(size, row.len()));
+    }
+    let center = (size / 2) as isize;
+    if size.is_multiple_of(2) || starts != [(center, center)] {
+        return Err(ExtrapolationError::OffCenter);
+    }
+    if map[center as usize].iter().any(|&rock| rock) || map.iter().any(|row| row[center as usize]) {
+        return Err(ExtrapolationError::Obstructed);
+    }
+    if n % size != size / 2 {
+        return Err(ExtrapolationError::Offset { n, size });
+    }
+    Ok(size)
+}
+
+/// The number of plots reachable in exactly `n` steps on the infinite grid, for `n` reaching the edge of some copy
+/// of the map.
+///
+/// Since the row and column through the start are clear, each extra copy of the map walked across adds a ring of
+/// copies to the reachable diamond, so the count is a quadratic in the number of copies walked across, fit from the
+/// first three.
+fn extrapolate(
+    map: &Map,
+    starts: &[Coords],
+    n: usize,
+    tiling: Tiling,
+    solver: Solver,
+) -> Result<usize, ExtrapolationError> {
+    let size = check_shape(map, starts, n, tiling)?;
+    let samples = sample_copies(map, starts, size, 3, solver);
+    Ok(quadratic([samples[0], samples[1], samples[2]], n / size) as usize)
+}
+
+/// The number of plots reachable in exactly as many steps as it takes to walk across 0, 1, ... copies of the map,
+/// for the first `copies` of those
+fn sample_copies(
+    map: &Map,
+    starts: &[Coords],
+    size: usize,
+    copies: usize,
+    solver: Solver,
+) -> Vec<i64> {
+    let counts = count_reachable(
+        map,
+        starts,
+        size / 2 + (copies - 1) * size,
+        &REPEATED,
+        solver,
+    );
+    (0..copies)
+        .map(|k| counts[size / 2 + k * size] as i64)
+        .collect()
+}
+
+/// The quadratic through `(0, a0)`, `(1, a1)` and `(2, a2)`, at `k`
+fn quadratic([a0, a1, a2]: [i64; 3], k: usize) -> i64 {
+    let k = k as i64;
+    a0 + k * (a1 - a0) + k * (k - 1) / 2 * (a2 - 2 * a1 + a0)
+}
+
+/// Compare the extrapolation against simulating the `k` step counts following the three it's fit from, returning
+/// the largest residual
+fn verify_fit(
+    map: &Map,
+    starts: &[Coords],
+    n: usize,
+    tiling: Tiling,
+    solver: Solver,
+    k: usize,
+) -> Result<i64, ExtrapolationError> {
+    let size = check_shape(map, starts, n, tiling)?;
+    let samples = sample_copies(map, starts, size, k + 3, solver);
+    let fit = [samples[0], samples[1], samples[2]];
+    let mut largest = 0i64;
+    for (copies, &simulated) in samples.iter().enumerate().skip(3) {
+        let predicted = quadratic(fit, copies);
+        let residual = simulated - predicted;
+        println!(
+            "{} steps: predicted {predicted}, simulated {simulated}, residual {residual}",
+            size / 2 + copies * size
+        );
+        if residual.abs() > largest.abs() {
+            largest = residual;
+        }
+    }
+    Ok(largest)
+}
+
+/// The plots reachable in `n` steps on the infinite grid, split up by the copy of the map they're in: the copies
+/// the diamond `n` steps reach covers entirely, and the copies around its edge it covers in part.
+struct Decomposition {
+    /// The number of copies covered entirely whose plots are reached an even number of steps from their own start,
+    /// and the plots reached in each
+    even: (usize, usize),
+    /// Likewise, for an odd number of steps
+    odd: (usize, usize),
+    /// The copies at the diamond's north, south, east and west points, entered from the middle of an edge
+    tips: [usize; 4],
+    /// The number of copies along each of the diamond's northwest, northeast, southwest and southeast sides that
+    /// it cuts a corner off, and the plots reached in one on each side
+    small_edges: (usize, [usize; 4]),
+    /// Likewise, for the copies it covers all but a corner of
+    large_edges: (usize, [usize; 4]),
+}
+
+impl Decomposition {
+    fn new(
+        map: &Map,
+        starts: &[Coords],
+        n: usize,
+        tiling: Tiling,
+        solver: Solver,
+    ) -> Result<Self, ExtrapolationError> {
+        let size = check_shape(map, starts, n, tiling)?;
+        let copies = n / size;
+        if copies == 0 {
+            return Err(ExtrapolationError::Inside(n));
+        }
+        let reachable = |from: Coords, steps: usize| {
+            count_reachable(map, &[from], steps, &BOUNDED, solver)[steps]
+        };
+
+        // twice the size is enough steps to reach all of a copy from its center
+        let filled = count_reachable(map, starts, 2 * size + 1, &BOUNDED, solver);
+        let (even_plots, odd_plots) = (filled[2 * size], filled[2 * size + 1]);
+        // copies a manhattan distance d from the original are entered an odd number of steps later iff d is odd,
+        // and those within `copies - 1` are covered entirely
+        let last = copies - 1;
+        let (same_parity, other_parity) =
+            ((last / 2 * 2 + 1).pow(2), (last.div_ceil(2) * 2).pow(2));
+        let (even_copies, odd_copies) = if n.is_multiple_of(2) {
+            (same_parity, other_parity)
+        } else {
+            (other_parity, same_parity)
+        };
+
+        let (far, mid) = (size as isize - 1, size as isize / 2);
+        let corner_starts = [(far, far), (far, 0), (0, far), (0, 0)];
+        Ok(Self {
+            even: (even_copies, even_plots),
+            odd: (odd_copies, odd_plots),
+            tips: [(far, mid), (0, mid), (mid, 0), (mid, far)]
+                .map(|from| reachable(from, size - 1)),
+            small_edges: (
+                copies,
+                corner_starts.map(|from| reachable(from, size / 2 - 1)),
+            ),
+            large_edges: (
+                copies - 1,
+                corner_starts.map(|from| reachable(from, size + size / 2 - 1)),
+            ),
+        })
+    }
+
+    fn total(&self) -> usize {
+        self.even.0 * self.even.1
+            + self.odd.0 * self.odd.1
+            + self.tips.iter().sum::<usize>()
+            + self.small_edges.0 * self.small_edges.1.iter().sum::<usize>()
+            + self.large_edges.0 * self.large_edges.1.iter().sum::<usize>()
+    }
+}
+
+impl Display for Decomposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sum = |plots: &[usize; 4]| {
+            let terms: Vec<_> = plots.iter().map(usize::to_string).collect();
+            (terms.join(" + "), plots.iter().sum::<usize>())
+        };
+        for (parity, (copies, plots)) in [("even", self.even), ("odd", self.odd)] {
+            writeln!(
+                f,
+                "Full copies, {parity} steps from their start: {copies} x {plots} = {}",
+                copies * plots
+            )?;
+        }
+        let (terms, tips) = sum(&self.tips);
+        writeln!(f, "Tips (N, S, E, W): {terms} = {tips}")?;
+        for (name, (copies, plots)) in [("Small", &self.small_edges), ("Large", &self.large_edges)]
+        {
+            let (terms, plots) = sum(plots);
+            writeln!(
+                f,
+                "{name} edges (NW, NE, SW, SE): {copies} x ({terms}) = {}",
+                copies * plots
+            )?;
+        }
+        writeln!(f, "Total: {}", self.total())
+    }
+}
+
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Draw the plots reached as `O` over the map, from `top_left` to `bottom_right`, with copies of the map other
+/// than the original dimmed
+fn draw(
+    out: &mut impl Write,
+    map: &Map,
+    grid: &Grid,
+    frontier: &Frontier,
+    (top_left, bottom_right): (Coords, Coords),
+) -> io::Result<()> {
+    let (height, width) = (map.len() as isize, map[0].len() as isize);
+    for r in top_left.0..=bottom_right.0 {
+        let mut line = String::new();
+        for c in top_left.1..=bottom_right.1 {
+            let original = (0..height).contains(&r) && (0..width).contains(&c);
+            let reached = frontier.contains((r, c));
+            if !original {
+                line.push_str(DIM);
+            }
+            if reached {
+                line.push_str(BOLD);
+                line.push('O');
+            } else if (grid.check_func)(map, (r, c)) {
+                line.push('#');
+            } else {
+                line.push('.');
+            }
+            if !original || reached {
+                line.push_str(RESET);
+            }
+        }
+        writeln!(out, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Draw the plots reachable in exactly `n` steps, or with `frame_delay`, each step on the way there
+fn render(
+    map: &Map,
+    starts: &[Coords],
+    n: usize,
+    tiles: Option<usize>,
+    tiling: Tiling,
+    frame_delay: Option<u64>,
+) -> anyhow::Result<()> {
+    let (height, width) = (map.len() as isize, map[0].len() as isize);
+    let (grid, region) = match tiles {
+        None => (&BOUNDED, ((0, 0), (height - 1, width - 1))),
+        Some(tiles) => {
+            let tiles = tiles as isize;
+            (
+                tiling.grid(),
+                (
+                    (-tiles * height, -tiles * width),
+                    ((tiles + 1) * height - 1, (tiles + 1) * width - 1),
+                ),
+            )
+        }
+    };
+
+    let mut out = stdout().lock();
+    let mut frontier = Frontier::new(map, starts, n, grid);
+    for step in 0..=n {
+        if step > 0 {
+            frontier.step();
+        }
+        if let Some(frame_delay) = frame_delay {
+            write!(out, "\x1b[2J\x1b[H")?;
+            draw(&mut out, map, grid, &frontier, region)?;
+            writeln!(out, "Step {step}: {} plots", frontier.len())?;
+            out.flush()?;
+            sleep(Duration::from_millis(frame_delay));
+        }
+    }
+    if frame_delay.is_none() {
+        draw(&mut out, map, grid, &frontier, region)?;
+        writeln!(out, "{}", frontier.len())?;
+    }
+
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    let (map, start) = parse_input(stdin().lock())?;
+    let (map, mut starts) = parse_input(stdin().lock())?;
+    if !args.starts.is_empty() {
+        starts = args.starts.clone();
+    }
+    ensure!(
+        !starts.is_empty(),
+        "There's no `S` in the map, and no --start"
+    );
+    for &(r, c) in &starts {
+        ensure!(
+            (0..map.len() as isize).contains(&r) && (0..map[0].len() as isize).contains(&c),
+            "The start {},{} is outside the map",
+            r + 1,
+            c + 1
+        );
+        ensure!(
+            !check_p1(&map, (r, c)),
+            "The start {},{} is a rock",
+            r + 1,
+            c + 1
+        );
+    }
 
     let res = match args.part {
-        Part::Part1 => floodfill(&map, start, args.n, move_p1, check_p1),
-        Part::Part2 => floodfill(&map, start, args.n, move_p2, check_p2),
-    }
-    .values()
-    .filter(|&v| v % 2 == args.n % 2)
-    .count();
+        Part::Part1 => count_reachable(&map, &starts, args.n, &BOUNDED, args.solver)[args.n],
+        Part::Part2 { simulate: true, .. } => {
+            count_reachable(&map, &starts, args.n, args.tiling.grid(), args.solver)[args.n]
+        }
+        Part::Part2 {
+            simulate: false,
+            verify,
+        } => {
+            if let Some(k) = verify {
+                let residual = verify_fit(&map, &starts, args.n, args.tiling, args.solver, k)?;
+                if residual != 0 {
+                    eprintln!("warning: the extrapolation is off by as much as {residual}");
+                }
+            }
+            extrapolate(&map, &starts, args.n, args.tiling, args.solver)?
+        }
+        Part::Render {
+            tiles,
+            animate,
+            frame_delay,
+        } => {
+            return render(
+                &map,
+                &starts,
+                args.n,
+                tiles,
+                args.tiling,
+                animate.then_some(frame_delay),
+            )
+        }
+    };
 
+    if args.explain && matches!(args.part, Part::Part2 { .. }) {
+        let decomposition = Decomposition::new(&map, &starts, args.n, args.tiling, args.solver)?;
+        print!("{decomposition}");
+        if decomposition.total() != res {
+            eprintln!(
+                "warning: the copies add up to {}, not {res}",
+                decomposition.total()
+            );
+        }
+    }
     println!("{res}");
     Ok(())
 }
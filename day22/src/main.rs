@@ -1,33 +1,90 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    io::stdin,
-    rc::Rc,
+    collections::{HashMap, HashSet},
+    io::{self, stdin, stdout, Write},
+    thread::sleep,
+    time::Duration,
 };
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use nom::{
     character::complete::{char, digit1},
     combinator::map_res,
     sequence::{separated_pair, tuple},
     IResult,
 };
+use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// List the bricks safe to disintegrate, and the bricks that would fall for each of the others, before the
+    /// answer
+    #[arg(long, global = true)]
+    explain: bool,
+    #[arg(long, global = true, value_enum, default_value_t = Labels::Letters)]
+    labels: Labels,
+    /// List where each brick starts and comes to rest, in the order they're dropped, before the answer
+    #[arg(long, global = true)]
+    trace: bool,
+    /// Animate the bricks settling one at a time, seen from the front
+    #[arg(long, global = true)]
+    animate: bool,
+    /// Delay between animation frames, in milliseconds
+    #[arg(long, global = true, default_value_t = 100)]
+    frame_delay: u64,
+}
+
+/// How bricks are named when explaining
+#[derive(Clone, Copy, ValueEnum)]
+enum Labels {
+    /// A, B, ..., Z, AA, AB, ... in input order
+    Letters,
+    /// The line of the input each brick is on
+    Lines,
+}
+
+impl Labels {
+    /// The label of the brick on line `line` of the input, counting from 0
+    fn label(self, line: usize) -> String {
+        match self {
+            Labels::Letters => {
+                let mut label = Vec::new();
+                let mut n = line + 1;
+                while n > 0 {
+                    label.push(b'A' + ((n - 1) % 26) as u8);
+                    n = (n - 1) / 26;
+                }
+                label.iter().rev().map(|&b| b as char).collect()
+            }
+            Labels::Lines => (line + 1).to_string(),
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Part {
     Part1,
     Part2,
+    /// Print which bricks support which as a DOT digraph, with bricks resting on the ground outlined twice and sole
+    /// supports highlighted
+    Graph,
+    /// Disintegrate one brick and let the rest settle again, listing where each brick that falls comes to rest
+    /// before how many fell
+    Remove {
+        /// The label of the brick to disintegrate
+        #[arg(long)]
+        brick: String,
+    },
+    /// List bricks that are reversed, not in a straight line, or overlap another brick, then how many problems there
+    /// are
+    Validate,
 }
 
 type Coord = usize;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Range(Coord, Coord);
 
 impl Range {
@@ -36,13 +93,16 @@ impl Range {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug)]
 struct Brick {
     z: Range, // default sort order is by initial height
     x: Range,
     y: Range,
 }
 
+/// The position of a brick in the list of bricks, which is in order of initial height
+type BrickId = usize;
+
 impl Brick {
     fn horizontal_slice(&self) -> impl Iterator<Item = (Coord, Coord)> + '_ {
         self.x
@@ -51,6 +111,92 @@ impl Brick {
     }
 }
 
+/// Drop the bricks in order, calling `on_settled` with each brick, the z range it comes to rest at and the bricks it
+/// rests on, in order
+fn settle(bricks: &[Brick], mut on_settled: impl FnMut(BrickId, Range, Vec<BrickId>)) {
+    // (x, y) -> (highest z so far, brick that occupies that z)
+    let mut heightmap: HashMap<(Coord, Coord), (usize, BrickId)> = HashMap::new();
+
+    for (i, brick) in bricks.iter().enumerate() {
+        let mut max_height = 0;
+        let mut support_set = Vec::new();
+        for point in brick.horizontal_slice() {
+            if let Some(&(height, other)) = heightmap.get(&point) {
+                if height > max_height {
+                    max_height = height;
+                    support_set = vec![other];
+                } else if height == max_height {
+                    support_set.push(other);
+                }
+            }
+        }
+        support_set.sort();
+        support_set.dedup();
+
+        let Range(z1, z2) = brick.z;
+        let new_z1 = max_height + 1;
+        let new_z2 = z2 - z1 + new_z1;
+        for point in brick.horizontal_slice() {
+            heightmap.insert(point, (new_z2, i));
+        }
+
+        on_settled(i, Range(new_z1, new_z2), support_set);
+    }
+}
+
+/// Something wrong with a brick in the input, by the line it's on, counting from 1
+#[derive(Debug, Error)]
+enum BrickError {
+    #[error("Line {0}: the {1} coordinates are in reverse order")]
+    Reversed(usize, char),
+    #[error("Line {0}: the ends differ along more than one axis")]
+    NotAxisAligned(usize),
+    #[error("Line {0}: overlaps the brick on line {1}")]
+    Overlap(usize, usize),
+}
+
+/// Every problem with the bricks, each with the line it's on, in input order
+fn validate(numbered: &[(usize, Brick)]) -> Vec<BrickError> {
+    let mut problems = Vec::new();
+    // (x, y, z) -> line of the brick that occupies it
+    let mut occupied: HashMap<(Coord, Coord, Coord), usize> = HashMap::new();
+    for (line, brick) in numbered {
+        let line = line + 1;
+        let axes = [('x', brick.x), ('y', brick.y), ('z', brick.z)];
+        for (axis, Range(a, b)) in axes {
+            if a > b {
+                problems.push(BrickError::Reversed(line, axis));
+            }
+        }
+        if axes.iter().filter(|(_, Range(a, b))| a != b).count() > 1 {
+            problems.push(BrickError::NotAxisAligned(line));
+        }
+
+        // check the cells between the ends whichever order they're in
+        let [x, y, z] = axes.map(|(_, Range(a, b))| a.min(b)..=a.max(b));
+        let mut overlapped = Vec::new();
+        for x in x {
+            for y in y.clone() {
+                for z in z.clone() {
+                    if let Some(&other) = occupied.get(&(x, y, z)) {
+                        if !overlapped.contains(&other) {
+                            overlapped.push(other);
+                        }
+                    } else {
+                        occupied.insert((x, y, z), line);
+                    }
+                }
+            }
+        }
+        problems.extend(
+            overlapped
+                .into_iter()
+                .map(|other| BrickError::Overlap(line, other)),
+        );
+    }
+    problems
+}
+
 fn coord(input: &str) -> IResult<&str, Coord> {
     map_res(digit1, str::parse)(input)
 }
@@ -74,95 +220,373 @@ fn brick(input: &str) -> IResult<&str, Brick> {
     ))
 }
 
+/// The nearest brick that both `a` and `b` depend on, where `None` is the ground
+fn common_dominator(
+    idom: &[Option<BrickId>],
+    depth: &[usize],
+    mut a: Option<BrickId>,
+    mut b: Option<BrickId>,
+) -> Option<BrickId> {
+    loop {
+        match (a, b) {
+            (Some(i), Some(j)) if i == j => return a,
+            (Some(i), Some(j)) if depth[i] >= depth[j] => a = idom[i],
+            (Some(_), Some(j)) => b = idom[j],
+            _ => return None,
+        }
+    }
+}
+
+/// The immediate dominator of each brick in the support graph, where `None` is the ground.
+///
+/// A brick falls exactly when every chain of supports from it down to the ground passes through the disintegrated
+/// brick, i.e. when the disintegrated brick dominates it. Each brick's supports settle before it, so one pass in
+/// settling order finds every brick's immediate dominator, the nearest one its supports have in common.
+fn dominators(supported_by: &[Vec<BrickId>]) -> Vec<Option<BrickId>> {
+    // None is the ground
+    let mut idom: Vec<Option<BrickId>> = Vec::with_capacity(supported_by.len());
+    // the number of bricks above the ground in the chain of dominators
+    let mut depth = Vec::with_capacity(supported_by.len());
+    for supports in supported_by {
+        let dominator = supports
+            .iter()
+            .map(|&support| Some(support))
+            .reduce(|a, b| common_dominator(&idom, &depth, a, b))
+            .flatten();
+        idom.push(dominator);
+        depth.push(dominator.map_or(1, |d| depth[d] + 1));
+    }
+    idom
+}
+
+/// The total number of other bricks that would fall, over disintegrating each brick in turn
+fn count_falling(idom: &[Option<BrickId>]) -> usize {
+    // dominators settle before the bricks they dominate, so a pass back counts each one's bricks
+    let mut dominated = vec![0; idom.len()];
+    for i in (0..idom.len()).rev() {
+        if let Some(d) = idom[i] {
+            dominated[d] += dominated[i] + 1;
+        }
+    }
+    dominated.iter().sum()
+}
+
+/// Print the bricks safe to disintegrate, then the bricks that would fall for each of the others, in input order
+fn explain(idom: &[Option<BrickId>], lines: &[usize], labels: Labels) {
+    let mut children = vec![Vec::new(); idom.len()];
+    for (i, &dominator) in idom.iter().enumerate() {
+        if let Some(d) = dominator {
+            children[d].push(i);
+        }
+    }
+    let by_line = |bricks: &mut Vec<BrickId>| {
+        bricks.sort_by_key(|&i| lines[i]);
+        bricks
+            .iter()
+            .map(|&i| labels.label(lines[i]))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut safe: Vec<BrickId> = (0..idom.len())
+        .filter(|&i| children[i].is_empty())
+        .collect();
+    println!("Safe to disintegrate: {}", by_line(&mut safe));
+
+    let mut unsafe_bricks: Vec<BrickId> = (0..idom.len())
+        .filter(|&i| !children[i].is_empty())
+        .collect();
+    unsafe_bricks.sort_by_key(|&i| lines[i]);
+    for brick in unsafe_bricks {
+        let mut falling = Vec::new();
+        let mut stack = children[brick].clone();
+        while let Some(i) = stack.pop() {
+            falling.push(i);
+            stack.extend(&children[i]);
+        }
+        println!(
+            "{}: {} would fall ({})",
+            labels.label(lines[brick]),
+            falling.len(),
+            by_line(&mut falling)
+        );
+    }
+}
+
+/// Where each brick falls to, from the resting positions `settled`, once `removed` is disintegrated, if it moves
+fn remove(bricks: &[Brick], settled: &[Range], removed: BrickId) -> Vec<(BrickId, Range)> {
+    let mut remaining: Vec<(BrickId, Brick)> = bricks
+        .iter()
+        .zip(settled)
+        .enumerate()
+        .filter(|&(i, _)| i != removed)
+        .map(|(i, (brick, &z))| {
+            let brick = Brick {
+                z,
+                x: brick.x,
+                y: brick.y,
+            };
+            (i, brick)
+        })
+        .collect();
+    // falling may have changed the order of heights
+    remaining.sort_by_key(|(_, brick)| brick.z.0);
+    let (ids, remaining): (Vec<BrickId>, Vec<Brick>) = remaining.into_iter().unzip();
+
+    let mut moved = Vec::new();
+    settle(&remaining, |i, z, _| {
+        if z != remaining[i].z {
+            moved.push((ids[i], z));
+        }
+    });
+    moved
+}
+
+fn print_dot(lines: &[usize], labels: Labels, supported_by: &[Vec<BrickId>]) {
+    let label = |i: BrickId| labels.label(lines[i]);
+    let mut order: Vec<BrickId> = (0..supported_by.len()).collect();
+    order.sort_by_key(|&i| lines[i]);
+
+    // bricks something rests on alone fall when they're disintegrated
+    let sole_supports: HashSet<BrickId> = supported_by
+        .iter()
+        .filter(|supports| supports.len() == 1)
+        .flatten()
+        .copied()
+        .collect();
+
+    println!("digraph bricks {{");
+    println!("    \"ground\" [shape=box, style=filled, fillcolor=gray];");
+    for &i in &order {
+        let fill = if sole_supports.contains(&i) {
+            ", style=filled, fillcolor=salmon"
+        } else {
+            ""
+        };
+        let ground = if supported_by[i].is_empty() {
+            ", peripheries=2"
+        } else {
+            ""
+        };
+        println!("    \"{}\" [shape=box{fill}{ground}];", label(i));
+    }
+    for &i in &order {
+        if supported_by[i].is_empty() {
+            println!("    \"ground\" -> \"{}\";", label(i));
+        }
+        let mut supports = supported_by[i].clone();
+        supports.sort_by_key(|&j| lines[j]);
+        let highlight = if supports.len() == 1 {
+            " [color=red, penwidth=2]"
+        } else {
+            ""
+        };
+        for j in supports {
+            println!("    \"{}\" -> \"{}\"{highlight};", label(j), label(i));
+        }
+    }
+    println!("}}");
+}
+
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The bricks seen from the front, looking along the y axis, as each settles in turn
+struct Animation<'a> {
+    bricks: &'a [Brick],
+    labels: Vec<String>,
+    /// The z range of every brick before anything settles
+    start: Vec<Range>,
+    /// The brick that settled in each frame, and where
+    moves: Vec<(BrickId, Range)>,
+}
+
+impl<'a> Animation<'a> {
+    fn new(bricks: &'a [Brick], lines: &[usize], labels: Labels) -> Self {
+        Self {
+            bricks,
+            labels: lines.iter().map(|&line| labels.label(line)).collect(),
+            start: bricks.iter().map(|brick| brick.z).collect(),
+            moves: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, brick: BrickId, z: Range) {
+        self.moves.push((brick, z));
+    }
+
+    /// Draw the bricks at `positions`, labeled where only one is in view at a spot, with `moved` in bold
+    fn draw(
+        &self,
+        out: &mut impl Write,
+        positions: &[Range],
+        moved: Option<BrickId>,
+    ) -> io::Result<()> {
+        let width = self
+            .bricks
+            .iter()
+            .map(|brick| brick.x.1 + 1)
+            .max()
+            .unwrap_or(0);
+        let height = self.start.iter().map(|z| z.1).max().unwrap_or(0);
+        // the bricks in view at each (z, x), from the bottom
+        let mut view = vec![vec![Vec::new(); width]; height + 1];
+        for (i, (brick, z)) in self.bricks.iter().zip(positions).enumerate() {
+            for z in z.iter() {
+                for x in brick.x.iter() {
+                    view[z][x].push(i);
+                }
+            }
+        }
+
+        for row in view.iter().skip(1).rev() {
+            let mut line = String::new();
+            for bricks in row {
+                match bricks.as_slice() {
+                    [] => line.push('.'),
+                    &[i] if self.labels[i].len() == 1 => {
+                        if moved == Some(i) {
+                            line.push_str(BOLD);
+                        }
+                        line.push_str(&self.labels[i]);
+                        if moved == Some(i) {
+                            line.push_str(RESET);
+                        }
+                    }
+                    [_] => line.push('#'),
+                    _ => line.push('?'),
+                }
+            }
+            writeln!(out, "{line}")?;
+        }
+        writeln!(out, "{}", "-".repeat(width))
+    }
+
+    fn play(&self, frame_delay: u64) -> io::Result<()> {
+        let mut out = stdout().lock();
+        let mut positions = self.start.clone();
+        for frame in 0..=self.moves.len() {
+            let moved = frame.checked_sub(1).map(|i| {
+                let (brick, z) = self.moves[i];
+                positions[brick] = z;
+                brick
+            });
+            write!(out, "\x1b[2J\x1b[H")?;
+            self.draw(&mut out, &positions, moved)?;
+            match moved {
+                Some(brick) => writeln!(
+                    out,
+                    "{} fell {} to rest at z {}..={}",
+                    self.labels[brick],
+                    self.start[brick].0 - positions[brick].0,
+                    positions[brick].0,
+                    positions[brick].1
+                )?,
+                None => writeln!(out, "Before settling")?,
+            }
+            out.flush()?;
+            sleep(Duration::from_millis(frame_delay));
+        }
+        Ok(())
+    }
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    // store bricks in a min-heap so that we can later iterate in ascending z1 order
-    let mut bricks: Vec<Rc<Brick>> = Vec::new();
-    for line in stdin().lines() {
+    // sort bricks, with the line each is on, so that we can later iterate in ascending z1 order
+    let mut numbered: Vec<(usize, Brick)> = Vec::new();
+    for (i, line) in stdin().lines().enumerate() {
         let (_, brick) = brick(&line?).map_err(|e| e.to_owned())?;
-        bricks.push(brick.into());
+        numbered.push((i, brick));
     }
-    bricks.sort_by(|lhs, rhs| lhs.z.0.cmp(&rhs.z.0));
 
-    // (x, y) -> (highest z so far, brick that occupies that z)
-    let mut heightmap: HashMap<(Coord, Coord), (usize, Rc<Brick>)> = HashMap::new();
+    let problems = validate(&numbered);
+    if let Part::Validate = args.part {
+        for problem in &problems {
+            println!("{problem}");
+        }
+        println!("{}", problems.len());
+        return Ok(());
+    }
+    if let Some(problem) = problems.first() {
+        bail!("{problem}; run validate to list every problem");
+    }
+
+    numbered.sort_by_key(|(_, brick)| brick.z.0);
+    let (lines, bricks): (Vec<usize>, Vec<Brick>) = numbered.into_iter().unzip();
+
     // A -> [Bricks which A supports]
-    let mut supports: HashMap<Rc<Brick>, HashSet<Rc<Brick>>> = HashMap::new();
+    let mut supports: Vec<Vec<BrickId>> = vec![Vec::new(); bricks.len()];
     // A -> [Bricks which A is supported by]
-    let mut supported_by: HashMap<Rc<Brick>, HashSet<Rc<Brick>>> = HashMap::new();
+    let mut supported_by: Vec<Vec<BrickId>> = Vec::with_capacity(bricks.len());
+    // where each brick comes to rest, in drop order
+    let mut settled = Vec::with_capacity(bricks.len());
 
-    for brick in bricks.iter() {
-        let mut max_height = 0;
-        let mut support_set = HashSet::new();
-        for point in brick.horizontal_slice() {
-            if let Some((height, other)) = heightmap.get(&point) {
-                if *height > max_height {
-                    max_height = *height;
-                    support_set = HashSet::from([other.clone()]);
-                } else if *height == max_height {
-                    support_set.insert(other.clone());
-                }
-            }
+    let mut frames = args
+        .animate
+        .then(|| Animation::new(&bricks, &lines, args.labels));
+    settle(&bricks, |i, z, support_set| {
+        for &other in &support_set {
+            supports[other].push(i);
         }
-
-        let Range(z1, z2) = brick.z;
-        let new_z1 = max_height + 1;
-        let new_z2 = z2 - z1 + new_z1;
-        for point in brick.horizontal_slice() {
-            heightmap.insert(point, (new_z2, brick.clone()));
+        supported_by.push(support_set);
+        settled.push(z);
+        if let Some(frames) = &mut frames {
+            frames.push(i, z);
         }
+    });
 
-        for other in support_set.iter() {
-            supports
-                .entry(other.clone())
-                .or_default()
-                .insert(brick.clone());
+    if let Some(frames) = frames {
+        frames.play(args.frame_delay)?;
+    }
+    if args.trace {
+        for (i, z) in settled.iter().enumerate() {
+            println!(
+                "{}: z {}..={} -> {}..={}",
+                args.labels.label(lines[i]),
+                bricks[i].z.0,
+                bricks[i].z.1,
+                z.0,
+                z.1
+            );
         }
+    }
 
-        supported_by.insert(brick.clone(), support_set);
+    let idom = dominators(&supported_by);
+    if args.explain {
+        explain(&idom, &lines, args.labels);
     }
 
     let res = match args.part {
-        Part::Part1 => bricks
+        Part::Part1 => supports
             .iter()
-            .filter(|&a| {
-                let Some(others) = supports.get(a) else {
-                    return true;
-                };
-                others.iter().all(|b| {
-                    supported_by
-                        .get(b)
-                        .unwrap()
-                        .iter()
-                        .any(|c| !Rc::ptr_eq(a, c))
-                })
-            })
+            .filter(|others| others.iter().all(|&b| supported_by[b].len() > 1))
             .count(),
-        Part::Part2 => bricks
-            .iter()
-            .map(|brick| {
-                let mut count = 0;
-                let mut hypothetical_supports = supported_by.clone();
-                let mut fall_queue = VecDeque::from([brick.clone()]);
-
-                while let Some(a) = fall_queue.pop_front() {
-                    count += 1;
-                    let Some(others) = supports.get(&a) else {
-                        continue;
-                    };
-                    for other in others {
-                        let remaining_supports = hypothetical_supports.get_mut(other).unwrap();
-                        remaining_supports.remove(&a);
-                        if remaining_supports.is_empty() {
-                            fall_queue.push_back(other.clone());
-                        }
-                    }
-                }
-
-                count - 1 // the initial brick doesn't count
-            })
-            .sum(),
+        Part::Part2 => count_falling(&idom),
+        Part::Graph => {
+            print_dot(&lines, args.labels, &supported_by);
+            return Ok(());
+        }
+        Part::Remove { brick } => {
+            let removed = (0..bricks.len())
+                .find(|&i| args.labels.label(lines[i]) == brick)
+                .ok_or_else(|| anyhow!("No brick is labeled {brick}"))?;
+            let mut moved = remove(&bricks, &settled, removed);
+            moved.sort_by_key(|&(i, _)| lines[i]);
+            for (i, z) in &moved {
+                println!(
+                    "{}: z {}..={} -> {}..={}",
+                    args.labels.label(lines[*i]),
+                    settled[*i].0,
+                    settled[*i].1,
+                    z.0,
+                    z.1
+                );
+            }
+            moved.len()
+        }
+        Part::Validate => unreachable!("Validated before settling"),
     };
 
     println!("{res}");
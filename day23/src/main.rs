@@ -1,17 +1,37 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     io::{stdin, BufRead},
     ops::Index,
 };
 
-use anyhow::{bail, Context, Result};
-use clap::{Parser, Subcommand};
+use anyhow::{bail, ensure, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Dfs)]
+    solver: Solver,
+    /// Start from the tile at a row and column, counting from 1, e.g. `1,2`, instead of the only path in the top row
+    #[arg(long, global = true, value_name = "ROW,COL", value_parser = coords)]
+    start: Option<Coords>,
+    /// End at the tile at a row and column, counting from 1, instead of the only path in the bottom row
+    #[arg(long, global = true, value_name = "ROW,COL", value_parser = coords)]
+    end: Option<Coords>,
+}
+
+/// How to find the longest hike through the graph of junctions
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Depth-first search over every hike
+    Dfs,
+    /// The longest hike through each set of junctions ending at each one, from smaller sets to larger, for maps
+    /// with few junctions
+    Dp,
 }
 
 #[derive(Subcommand)]
@@ -38,6 +58,21 @@ struct Coords {
     c: Coord,
 }
 
+#[derive(Debug, Error)]
+#[error("`{0}` is not a position of the form ROW,COL")]
+struct ParseCoordsError(String);
+
+fn coords(s: &str) -> Result<Coords, ParseCoordsError> {
+    let parse = || {
+        let (r, c) = s.split_once(',')?;
+        Some(Coords {
+            r: r.trim().parse::<Coord>().ok()?.checked_sub(1)?,
+            c: c.trim().parse::<Coord>().ok()?.checked_sub(1)?,
+        })
+    };
+    parse().ok_or_else(|| ParseCoordsError(s.to_owned()))
+}
+
 #[derive(Debug)]
 enum Space {
     Empty,
@@ -118,14 +153,12 @@ impl Input {
         self[coords]
             .available_directions(part)
             .filter_map(move |dir| {
-                let Some(coords) = try_move(self, coords, dir) else {
-                    return None;
-                };
+                let coords = try_move(self, coords, dir)?;
                 if let Space::Forest = self[coords] {
                     return None;
                 }
 
-                return Some(coords);
+                Some(coords)
             })
     }
 }
@@ -152,7 +185,7 @@ fn parse_input(inp: impl BufRead) -> Result<Input> {
     Ok(Input { map })
 }
 
-fn find_only_empty(row: &Vec<Space>) -> Result<usize> {
+fn find_only_empty(row: &[Space]) -> Result<usize> {
     let empty_spaces: Vec<Coord> = row
         .iter()
         .enumerate()
@@ -171,27 +204,36 @@ fn find_only_empty(row: &Vec<Space>) -> Result<usize> {
     Ok(space)
 }
 
+/// The index of a node in the graph
+type NodeId = usize;
+
+/// The start, end and junctions of the map, numbered in order of discovery, with the trails between them
 #[derive(Debug)]
 struct Graph {
-    edges: HashMap<Coords, HashSet<Coords>>,
-    edge_weights: HashMap<(Coords, Coords), usize>,
+    /// Where each node is on the map
+    nodes: Vec<Coords>,
+    /// node -> [(node the trail leads to, steps along it)]
+    edges: Vec<Vec<(NodeId, usize)>>,
 }
 
+/// The most nodes a hike can track in its visited set
+const MAX_NODES: usize = u64::BITS as usize;
+
+/// Contract the map to the graph of trails between `start`, `end` and every junction, which are numbered 0, 1, and
+/// from 2 onwards respectively. Trails end at `start` and `end` wherever they are, as at a junction
 fn discover_graph(input: &Input, start: Coords, end: Coords, part: &Part) -> Graph {
-    let mut nodes = HashSet::from([start, end]);
-    let mut edges: HashMap<Coords, HashSet<Coords>> = HashMap::new();
-    let mut edge_weights = HashMap::new();
+    let mut nodes = vec![start, end];
+    let mut index = HashMap::from([(start, 0), (end, 1)]);
+    let mut edges = vec![Vec::new(), Vec::new()];
 
-    let mut to_explore = Vec::from([start]);
+    let mut to_explore = Vec::from([0]);
     while let Some(node) = to_explore.pop() {
-        for mut cur in input.successors(node, part) {
+        for mut cur in input.successors(nodes[node], part) {
             let mut steps = 1;
-            let mut prev = node;
-            let mut found_node = false;
-            loop {
-                if nodes.contains(&cur) {
-                    found_node = true;
-                    break;
+            let mut prev = nodes[node];
+            let next_node = loop {
+                if let Some(&id) = index.get(&cur) {
+                    break Some(id);
                 }
                 let successors: Vec<_> = input
                     .successors(cur, part)
@@ -199,85 +241,209 @@ fn discover_graph(input: &Input, start: Coords, end: Coords, part: &Part) -> Gra
                     .collect();
 
                 match successors[..] {
-                    [] => break,
+                    [] => break None,
                     [next] => {
                         prev = cur;
                         cur = next;
                         steps += 1;
                     }
                     _ => {
-                        found_node = true;
-                        nodes.insert(cur);
-                        to_explore.push(cur);
-                        break;
+                        let id = nodes.len();
+                        nodes.push(cur);
+                        index.insert(cur, id);
+                        edges.push(Vec::new());
+                        to_explore.push(id);
+                        break Some(id);
                     }
                 }
-            }
+            };
 
-            if found_node {
-                edges.entry(node).or_default().insert(cur);
-                edge_weights.insert((node, cur), steps);
+            if let Some(next_node) = next_node {
+                edges[node].push((next_node, steps));
             }
         }
     }
 
-    Graph {
-        edges,
-        edge_weights,
+    Graph { nodes, edges }
+}
+
+/// A partial hike through the graph
+#[derive(Debug, Clone, Copy)]
+struct Hike {
+    node: NodeId,
+    /// The nodes passed through so far, one bit each
+    visited: u64,
+    length: usize,
+}
+
+impl Hike {
+    fn start(node: NodeId) -> Self {
+        Hike {
+            node,
+            visited: 1 << node,
+            length: 0,
+        }
+    }
+
+    /// The hikes one trail further, to each node not yet visited
+    fn extend(self, graph: &Graph) -> impl Iterator<Item = Hike> + '_ {
+        graph.edges[self.node]
+            .iter()
+            .filter(move |&&(next, _)| self.visited & 1 << next == 0)
+            .map(move |&(next, steps)| Hike {
+                node: next,
+                visited: self.visited | 1 << next,
+                length: self.length + steps,
+            })
     }
 }
 
-fn find_longest_path(graph: &Graph, start: Coords, end: Coords) -> Option<usize> {
-    let mut paths = Vec::from([vec![start]]);
-
-    let mut hike_lengths = Vec::new();
-    while let Some(path) = paths.pop() {
-        let cur = *path.last().expect("Path must be non-empty");
-        if cur == end {
-            hike_lengths.push(
-                path.windows(2)
-                    .map(|window| {
-                        let &[x, y] = window else { unreachable!() };
-                        graph
-                            .edge_weights
-                            .get(&(x, y))
-                            .expect("Edges must have a corresponding weight")
-                    })
-                    .sum(),
-            );
+/// The length of the longest way to carry on `hike` to `end`
+fn longest_from(graph: &Graph, hike: Hike, end: NodeId) -> Option<usize> {
+    let mut hikes = Vec::from([hike]);
+
+    let mut longest = None;
+    while let Some(hike) = hikes.pop() {
+        if hike.node == end {
+            longest = longest.max(Some(hike.length));
             continue;
         }
-        for &next in graph.edges.get(&cur).expect("No out-edges found").iter() {
-            if path.contains(&next) {
+        hikes.extend(hike.extend(graph));
+    }
+
+    longest
+}
+
+#[cfg(not(feature = "parallel"))]
+fn find_longest_path(graph: &Graph, start: NodeId, end: NodeId) -> Option<usize> {
+    longest_from(graph, Hike::start(start), end)
+}
+
+/// How many trails from the start the search is split at, to hand out the subtrees below to threads
+#[cfg(feature = "parallel")]
+const SPLIT_DEPTH: usize = 3;
+
+#[cfg(feature = "parallel")]
+fn find_longest_path(graph: &Graph, start: NodeId, end: NodeId) -> Option<usize> {
+    let mut hikes = vec![Hike::start(start)];
+    for _ in 0..SPLIT_DEPTH {
+        hikes = hikes
+            .into_iter()
+            .flat_map(|hike| {
+                if hike.node == end {
+                    vec![hike]
+                } else {
+                    hike.extend(graph).collect()
+                }
+            })
+            .collect();
+    }
+
+    // each split hike carries its own visited set, so the subtree below it is searched independently of the others
+    hikes
+        .par_iter()
+        .filter_map(|&hike| longest_from(graph, hike, end))
+        .max()
+}
+
+/// The most nodes the table of subsets can cover
+const MAX_DP_NODES: usize = 20;
+
+/// The length of the longest hike from `start` to `end`, from the longest hike through each set of nodes ending at
+/// each node of the set
+fn longest_path_dp(graph: &Graph, start: NodeId, end: NodeId) -> Option<usize> {
+    let n = graph.nodes.len();
+    const UNREACHED: u32 = u32::MAX;
+    // (set of nodes, last node) -> length of the longest hike
+    let mut longest = vec![UNREACHED; (1 << n) * n];
+    longest[(1 << start) * n + start] = 0;
+
+    let mut res = None;
+    // adding a node makes a larger number, so every set is finished before any set it's part of
+    for visited in 0..1usize << n {
+        for node in 0..n {
+            let length = longest[visited * n + node];
+            if length == UNREACHED {
                 continue;
             }
-            let mut new_path = path.clone();
-            new_path.push(next);
-            paths.push(new_path)
+            if node == end {
+                res = res.max(Some(length as usize));
+                continue;
+            }
+            for &(next, steps) in &graph.edges[node] {
+                if visited & 1 << next != 0 {
+                    continue;
+                }
+                let entry = &mut longest[(visited | 1 << next) * n + next];
+                let length = length + steps as u32;
+                if *entry == UNREACHED || *entry < length {
+                    *entry = length;
+                }
+            }
         }
     }
 
-    hike_lengths.into_iter().max()
+    res
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     let input = parse_input(stdin().lock())?;
-
-    let start = Coords {
-        r: 0,
-        c: find_only_empty(&input.map[0]).context("Couldn't find start space")?,
+    let last_row = input.map.last().context("Empty input")?;
+
+    let start = match args.start {
+        Some(start) => start,
+        None => Coords {
+            r: 0,
+            c: find_only_empty(&input.map[0]).context("Couldn't find start space")?,
+        },
     };
 
-    let end = Coords {
-        r: input.map.len() - 1,
-        c: find_only_empty(input.map.last().context("Empty input")?)
-            .context("Couldn't find end space")?,
+    let end = match args.end {
+        Some(end) => end,
+        None => Coords {
+            r: input.map.len() - 1,
+            c: find_only_empty(last_row).context("Couldn't find end space")?,
+        },
     };
 
+    for (name, coords) in [("start", start), ("end", end)] {
+        ensure!(
+            coords.r < input.height() && coords.c < input.width(),
+            "The {name} {},{} is outside the map",
+            coords.r + 1,
+            coords.c + 1
+        );
+        ensure!(
+            !matches!(input[coords], Space::Forest),
+            "The {name} {},{} is forest",
+            coords.r + 1,
+            coords.c + 1
+        );
+    }
+    ensure!(start != end, "The start and end are the same tile");
+
     let graph = discover_graph(&input, start, end, &args.part);
-    let res = find_longest_path(&graph, start, end).context("No path found")?;
+    ensure!(
+        graph.nodes.len() <= MAX_NODES,
+        "The map has {} junctions, more than {} can be searched",
+        graph.nodes.len() - 2,
+        MAX_NODES - 2
+    );
+    let res = match args.solver {
+        Solver::Dfs => find_longest_path(&graph, 0, 1),
+        Solver::Dp => {
+            ensure!(
+                graph.nodes.len() <= MAX_DP_NODES,
+                "The map has {} junctions, more than the dp solver can handle ({})",
+                graph.nodes.len() - 2,
+                MAX_DP_NODES - 2
+            );
+            longest_path_dp(&graph, 0, 1)
+        }
+    }
+    .context("No path found")?;
 
     println!("{res}");
 
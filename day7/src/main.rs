@@ -6,8 +6,8 @@ use counter::Counter;
 use nom::{
     character::complete::{anychar, digit1, space1},
     combinator::{map_res, recognize},
-    multi::count,
-    sequence::separated_pair,
+    multi::{count, separated_list1},
+    sequence::{pair, separated_pair},
     IResult,
 };
 use thiserror::Error;
@@ -22,6 +22,8 @@ struct Args {
 enum Part {
     Part1,
     Part2,
+    /// Cards carry a suit (e.g. `Ah Kd Qs Jc Th`), enabling straights and flushes
+    Suited,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
@@ -71,14 +73,45 @@ impl TryFrom<char> for Card {
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Suit {
+    Clubs,
+    Diamonds,
+    Hearts,
+    Spades,
+}
+
+#[derive(Error, Debug)]
+enum ParseSuitError {
+    #[error("Character `{0}` does not correspond to a Suit")]
+    InvalidChar(char),
+}
+
+impl TryFrom<char> for Suit {
+    type Error = ParseSuitError;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        match value {
+            'c' => Ok(Suit::Clubs),
+            'd' => Ok(Suit::Diamonds),
+            'h' => Ok(Suit::Hearts),
+            's' => Ok(Suit::Spades),
+            c => Err(ParseSuitError::InvalidChar(c)),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 enum HandType {
     HighCard,
     OnePair,
     TwoPair,
     ThreeOfAKind,
+    Straight,
+    Flush,
     FullHouse,
     FourOfAKind,
+    StraightFlush,
     FiveOfAKind,
 }
 
@@ -109,6 +142,31 @@ fn get_type(cards: &[Card; 5]) -> HandType {
     }
 }
 
+fn is_straight(cards: &[Card; 5]) -> bool {
+    let mut ranks = cards.map(|c| c as u8);
+    ranks.sort_unstable();
+    // ace-low straight: A 2 3 4 5
+    if ranks == [Card::N2, Card::N3, Card::N4, Card::N5, Card::A].map(|c| c as u8) {
+        return true;
+    }
+    ranks.windows(2).all(|w| w[1] == w[0] + 1)
+}
+
+fn get_suited_type(cards: &[(Card, Suit); 5]) -> HandType {
+    let ranks = cards.map(|(card, _)| card);
+    let flush = cards.iter().all(|&(_, suit)| suit == cards[0].1);
+    let straight = is_straight(&ranks);
+
+    match (get_type(&ranks), straight, flush) {
+        (HandType::FiveOfAKind, _, _) => HandType::FiveOfAKind,
+        (_, true, true) => HandType::StraightFlush,
+        (t @ (HandType::FourOfAKind | HandType::FullHouse), _, _) => t,
+        (_, _, true) => HandType::Flush,
+        (_, true, _) => HandType::Straight,
+        (t, _, _) => t,
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 struct Hand {
     t: HandType,
@@ -120,11 +178,41 @@ fn cards(input: &str) -> IResult<&str, Vec<Card>> {
     count(map_res(anychar, Card::try_from), 5)(input)
 }
 
+fn suited_cards(input: &str) -> IResult<&str, [(Card, Suit); 5]> {
+    map_res(
+        separated_list1(
+            space1,
+            pair(
+                map_res(anychar, Card::try_from),
+                map_res(anychar, Suit::try_from),
+            ),
+        ),
+        <[(Card, Suit); 5]>::try_from,
+    )(input)
+}
+
 fn bid(input: &str) -> IResult<&str, usize> {
     map_res(recognize(digit1), str::parse)(input)
 }
 
+fn suited_hand(input: &str) -> IResult<&str, Hand> {
+    let (input, (cards, bid)) = separated_pair(suited_cards, space1, bid)(input)?;
+
+    Ok((
+        input,
+        Hand {
+            t: get_suited_type(&cards),
+            cards: cards.map(|(card, _)| card),
+            bid,
+        },
+    ))
+}
+
 fn hand<'a>(input: &'a str, part: &Part) -> IResult<&'a str, Hand> {
+    if let Part::Suited = part {
+        return suited_hand(input);
+    }
+
     let (input, (cards, bid)) = separated_pair(cards, space1, bid)(input)?;
 
     let mut cards: [Card; 5] = cards[..].try_into().expect("Invalid hand length");
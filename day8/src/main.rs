@@ -1,10 +1,9 @@
 use std::{
     collections::HashMap,
     io::{stdin, Error, ErrorKind},
-    mem::replace,
 };
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use nom::{
     bytes::complete::{tag, take},
     character::complete::anychar,
@@ -24,7 +23,27 @@ struct Args {
 #[derive(Subcommand)]
 enum Part {
     Part1,
-    Part2,
+    Part2 {
+        #[arg(long, value_enum, default_value_t = Solver::Lcm)]
+        solver: Solver,
+        /// Maximum number of steps to take when simulating
+        #[arg(long, default_value_t = 1_000_000_000)]
+        max_steps: usize,
+    },
+    /// Report the cycle structure followed by each ghost
+    Analyze,
+    /// Print the network in DOT format
+    Graph,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Assumes each ghost reaches its only end node after exactly one cycle length
+    Lcm,
+    /// Combines every ghost's cycle with the Chinese Remainder Theorem
+    Crt,
+    /// Steps all ghosts simultaneously until they are all on end nodes
+    Simulate,
 }
 
 #[derive(Debug)]
@@ -68,30 +87,308 @@ fn node(input: &str) -> IResult<&str, Node> {
     )(input)
 }
 
-type NodeMap = HashMap<String, (String, String)>;
+type NodeId = u32;
 
-fn find_length(
-    moves: &Vec<Move>,
-    node_map: &NodeMap,
-    start: &str,
-    end_pred: fn(&str) -> bool,
-) -> usize {
+#[derive(Debug, Error)]
+#[error("Node `{0}` is referenced but never defined")]
+struct UndefinedNodeError(String);
+
+/// The network, with node labels interned into dense ids so traversal only
+/// indexes into a flat transition table.
+#[derive(Default)]
+struct NodeMap {
+    labels: Vec<String>,
+    ids: HashMap<String, NodeId>,
+    next: Vec<(NodeId, NodeId)>,
+}
+
+impl NodeMap {
+    fn intern(&mut self, label: String) -> NodeId {
+        if let Some(&id) = self.ids.get(&label) {
+            return id;
+        }
+        let id = self.labels.len() as NodeId;
+        self.labels.push(label.clone());
+        self.ids.insert(label, id);
+        self.next.push((NodeId::MAX, NodeId::MAX));
+        id
+    }
+
+    fn insert(&mut self, label: String, (left, right): (String, String)) {
+        let id = self.intern(label);
+        let left = self.intern(left);
+        let right = self.intern(right);
+        self.next[id as usize] = (left, right);
+    }
+
+    fn validate(&self) -> Result<(), UndefinedNodeError> {
+        match self.next.iter().position(|&(l, _)| l == NodeId::MAX) {
+            Some(id) => Err(UndefinedNodeError(self.labels[id].clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    fn id(&self, label: &str) -> Option<NodeId> {
+        self.ids.get(label).copied()
+    }
+
+    fn label(&self, id: NodeId) -> &str {
+        &self.labels[id as usize]
+    }
+
+    fn step(&self, id: NodeId, m: &Move) -> NodeId {
+        let (left, right) = self.next[id as usize];
+        match m {
+            Move::Left => left,
+            Move::Right => right,
+        }
+    }
+
+    /// Ids of all nodes whose label satisfies `pred`, in label order
+    fn matching(&self, pred: fn(&str) -> bool) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = (0..self.len() as NodeId)
+            .filter(|&id| pred(self.label(id)))
+            .collect();
+        ids.sort_unstable_by_key(|&id| self.label(id));
+        ids
+    }
+
+    /// Flags, indexed by node id, marking nodes whose label satisfies `pred`
+    fn flags(&self, pred: fn(&str) -> bool) -> Vec<bool> {
+        self.labels.iter().map(|l| pred(l)).collect()
+    }
+}
+
+fn print_dot(node_map: &NodeMap) {
+    println!("digraph network {{");
+    for id in 0..node_map.len() as NodeId {
+        let label = node_map.label(id);
+        if label.ends_with('A') {
+            println!("    \"{label}\" [style=filled, fillcolor=green];");
+        } else if label.ends_with('Z') {
+            println!("    \"{label}\" [style=filled, fillcolor=red];");
+        }
+    }
+    for id in 0..node_map.len() as NodeId {
+        let label = node_map.label(id);
+        for m in [Move::Left, Move::Right] {
+            let next = node_map.label(node_map.step(id, &m));
+            let edge = match m {
+                Move::Left => "L",
+                Move::Right => "R",
+            };
+            println!("    \"{label}\" -> \"{next}\" [label=\"{edge}\"];");
+        }
+    }
+    println!("}}");
+}
+
+fn find_length(moves: &[Move], node_map: &NodeMap, start: NodeId, is_end: &[bool]) -> usize {
     let mut steps: usize = 0;
     let mut cur = start;
-    while !end_pred(cur) {
-        let m = &moves[steps % moves.len()];
-        let next = node_map.get(cur).expect("Invalid node");
-        let next = match *m {
-            Move::Left => next.0.as_str(),
-            Move::Right => next.1.as_str(),
-        };
-        let _ = replace(&mut cur, next);
+    while !is_end[cur as usize] {
+        cur = node_map.step(cur, &moves[steps % moves.len()]);
         steps += 1;
     }
 
     steps
 }
 
+#[derive(Debug, Error)]
+#[error("Ghosts did not synchronize within {0} steps")]
+struct StepLimitError(usize);
+
+const PROGRESS_INTERVAL: usize = 10_000_000;
+
+fn simulate(
+    moves: &[Move],
+    node_map: &NodeMap,
+    starts: Vec<NodeId>,
+    is_end: &[bool],
+    max_steps: usize,
+) -> Result<usize, StepLimitError> {
+    let mut ghosts = starts;
+    let mut steps: usize = 0;
+    while !ghosts.iter().all(|&g| is_end[g as usize]) {
+        if steps >= max_steps {
+            if steps >= PROGRESS_INTERVAL {
+                eprintln!();
+            }
+            return Err(StepLimitError(max_steps));
+        }
+        let m = &moves[steps % moves.len()];
+        for g in ghosts.iter_mut() {
+            *g = node_map.step(*g, m);
+        }
+        steps += 1;
+        if steps.is_multiple_of(PROGRESS_INTERVAL) {
+            eprint!(
+                "\r{steps} steps ({:.1}% of limit)",
+                100. * steps as f64 / max_steps as f64
+            );
+        }
+    }
+    if steps >= PROGRESS_INTERVAL {
+        eprintln!();
+    }
+
+    Ok(steps)
+}
+
+/// The path taken by a single ghost, which becomes periodic once it revisits a
+/// (node, position in move sequence) state.
+struct GhostCycle {
+    /// Step at which the ghost first enters the cycle
+    start: usize,
+    /// Number of steps in the cycle
+    length: usize,
+    /// Steps, prior to completing the first trip around the cycle, at which the ghost is on an end node
+    hits: Vec<usize>,
+    /// Distinct end nodes visited within the cycle
+    end_nodes: Vec<NodeId>,
+}
+
+impl GhostCycle {
+    /// Whether the ghost reaches an end node exactly at multiples of the first such step, as the LCM solver assumes
+    fn lcm_compatible(&self) -> bool {
+        match self.hits.first() {
+            Some(&first) if first > 0 && self.length.is_multiple_of(first) => self
+                .hits
+                .iter()
+                .copied()
+                .eq((first..self.start + self.length).step_by(first)),
+            _ => false,
+        }
+    }
+}
+
+fn find_cycle(moves: &[Move], node_map: &NodeMap, start: NodeId, is_end: &[bool]) -> GhostCycle {
+    // first step at which each (node, position in move sequence) state was visited
+    let mut seen = vec![usize::MAX; node_map.len() * moves.len()];
+    let mut hits = Vec::new();
+    let mut hit_nodes = Vec::new();
+    let mut steps: usize = 0;
+    let mut cur = start;
+    loop {
+        let state = cur as usize * moves.len() + steps % moves.len();
+        if seen[state] != usize::MAX {
+            let first = seen[state];
+            let mut end_nodes: Vec<NodeId> = hits
+                .iter()
+                .zip(hit_nodes)
+                .filter(|(&t, _)| t >= first)
+                .map(|(_, node)| node)
+                .collect();
+            end_nodes.sort_unstable();
+            end_nodes.dedup();
+
+            return GhostCycle {
+                start: first,
+                length: steps - first,
+                hits,
+                end_nodes,
+            };
+        }
+        seen[state] = steps;
+        if is_end[cur as usize] {
+            hits.push(steps);
+            hit_nodes.push(cur);
+        }
+
+        cur = node_map.step(cur, &moves[steps % moves.len()]);
+        steps += 1;
+    }
+}
+
+/// The steps at which a ghost (or group of ghosts) is on an end node
+struct HitSet {
+    /// Steps which occur only once
+    once: Vec<usize>,
+    /// Steps which recur every `period` steps thereafter
+    recurring: Vec<usize>,
+    period: usize,
+}
+
+impl From<&GhostCycle> for HitSet {
+    fn from(cycle: &GhostCycle) -> Self {
+        let (recurring, once) = cycle.hits.iter().partition(|&&t| t >= cycle.start);
+        HitSet {
+            once,
+            recurring,
+            period: cycle.length,
+        }
+    }
+}
+
+/// Solve x = a (mod m), x = b (mod n), returning x modulo lcm(m, n) if a solution exists
+fn crt(a: usize, m: usize, b: usize, n: usize) -> Option<(usize, usize)> {
+    let (a, m, b, n) = (a as i128, m as i128, b as i128, n as i128);
+    let egcd = num::integer::Integer::extended_gcd(&m, &n);
+    if (b - a) % egcd.gcd != 0 {
+        return None;
+    }
+    let lcm = m / egcd.gcd * n;
+    let k = ((b - a) / egcd.gcd * egcd.x).rem_euclid(n / egcd.gcd);
+    let x = (a + m * k).rem_euclid(lcm);
+    Some((x as usize, lcm as usize))
+}
+
+impl HitSet {
+    fn contains(&self, t: usize) -> bool {
+        self.once.contains(&t)
+            || self
+                .recurring
+                .iter()
+                .any(|&r| t >= r && (t - r).is_multiple_of(self.period))
+    }
+
+    fn intersect(&self, other: &HitSet) -> HitSet {
+        let mut once: Vec<usize> = self
+            .once
+            .iter()
+            .filter(|&&t| other.contains(t))
+            .chain(other.once.iter().filter(|&&t| self.contains(t)))
+            .copied()
+            .collect();
+        once.sort_unstable();
+        once.dedup();
+
+        let mut recurring = Vec::new();
+        let mut period = num::integer::lcm(self.period, other.period);
+        for &r1 in &self.recurring {
+            for &r2 in &other.recurring {
+                let Some((x, lcm)) = crt(r1, self.period, r2, other.period) else {
+                    continue;
+                };
+                period = lcm;
+                let min = r1.max(r2);
+                let first = if x >= min {
+                    x
+                } else {
+                    x + (min - x).div_ceil(lcm) * lcm
+                };
+                recurring.push(first);
+            }
+        }
+        recurring.sort_unstable();
+        recurring.dedup();
+
+        HitSet {
+            once,
+            recurring,
+            period,
+        }
+    }
+
+    fn first(&self) -> Option<usize> {
+        self.once.iter().chain(self.recurring.iter()).min().copied()
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
@@ -100,7 +397,7 @@ fn main() -> anyhow::Result<()> {
     let (_, moves) = move_seq(&moves).map_err(|e| e.to_owned())?;
     let _ = inp.next().ok_or(Error::from(ErrorKind::UnexpectedEof))??;
 
-    let mut node_map: NodeMap = NodeMap::new();
+    let mut node_map = NodeMap::default();
 
     for line in inp {
         let line = line?;
@@ -109,19 +406,84 @@ fn main() -> anyhow::Result<()> {
         node_map.insert(label, next);
     }
 
+    node_map.validate()?;
+
     let steps = match args.part {
-        Part::Part1 => find_length(&moves, &node_map, "AAA", |s| s == "ZZZ"),
-        Part::Part2 => {
+        Part::Part1 => {
+            let start = node_map
+                .id("AAA")
+                .ok_or(UndefinedNodeError("AAA".to_string()))?;
+            find_length(&moves, &node_map, start, &node_map.flags(|s| s == "ZZZ"))
+        }
+        Part::Part2 {
+            solver: Solver::Lcm,
+            ..
+        } => {
             // The problem is constructed such that each node ending with A connects to a separate chain which contains only one node ending with Z.
             // Furthermore the path length from A to Z is the same as the cycle length.
+            let is_end = node_map.flags(|c| c.ends_with('Z'));
             let path_lengths: Vec<usize> = node_map
-                .keys()
-                .filter(|&k| k.ends_with("A"))
-                .map(|s| find_length(&moves, &node_map, s, |c| c.ends_with("Z")))
+                .matching(|k| k.ends_with('A'))
+                .into_iter()
+                .map(|s| find_length(&moves, &node_map, s, &is_end))
                 .collect();
 
             path_lengths.into_iter().fold(1, num::integer::lcm)
         }
+        Part::Part2 {
+            solver: Solver::Crt,
+            ..
+        } => {
+            let is_end = node_map.flags(|c| c.ends_with('Z'));
+            node_map
+                .matching(|k| k.ends_with('A'))
+                .into_iter()
+                .map(|s| HitSet::from(&find_cycle(&moves, &node_map, s, &is_end)))
+                .reduce(|acc, hits| acc.intersect(&hits))
+                .and_then(|hits| hits.first())
+                .ok_or(anyhow::anyhow!(
+                    "Ghosts never simultaneously reach end nodes"
+                ))?
+        }
+        Part::Part2 {
+            solver: Solver::Simulate,
+            max_steps,
+        } => simulate(
+            &moves,
+            &node_map,
+            node_map.matching(|k| k.ends_with('A')),
+            &node_map.flags(|c| c.ends_with('Z')),
+            max_steps,
+        )?,
+        Part::Analyze => {
+            let is_end = node_map.flags(|c| c.ends_with('Z'));
+            let mut all_compatible = true;
+            for start in node_map.matching(|k| k.ends_with('A')) {
+                let cycle = find_cycle(&moves, &node_map, start, &is_end);
+                let first = cycle
+                    .hits
+                    .first()
+                    .map_or("never".to_string(), usize::to_string);
+                all_compatible &= cycle.lcm_compatible();
+                println!(
+                    "{}: first Z at {first}, cycle of length {} entered at {}, {} Z node(s) in cycle, LCM shortcut {}",
+                    node_map.label(start),
+                    cycle.length,
+                    cycle.start,
+                    cycle.end_nodes.len(),
+                    if cycle.lcm_compatible() { "valid" } else { "invalid" },
+                );
+            }
+            println!(
+                "LCM shortcut is {} for this input",
+                if all_compatible { "valid" } else { "invalid" }
+            );
+            return Ok(());
+        }
+        Part::Graph => {
+            print_dot(&node_map);
+            return Ok(());
+        }
     };
 
     println!("{steps}");
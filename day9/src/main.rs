@@ -1,12 +1,38 @@
-use std::{io::stdin, num::ParseIntError};
+use std::{fmt::Display, io::stdin, str::FromStr};
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use num::{BigInt, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, FromPrimitive, One, Zero};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use thiserror::Error;
 
 #[derive(Parser)]
 struct Args {
     #[command(subcommand)]
     part: Part,
+    /// Use arbitrary-precision arithmetic rather than i64
+    #[arg(long, global = true)]
+    bigint: bool,
+    /// Number of values to extrapolate
+    #[arg(long, global = true, default_value_t = 1)]
+    steps: usize,
+    /// Direction in which to extrapolate, overriding the part's default
+    #[arg(long, global = true, value_enum)]
+    direction: Option<Direction>,
+    /// Print the difference pyramid of each sequence, with extrapolated values in brackets
+    #[arg(long, global = true)]
+    explain: bool,
+    #[arg(long, global = true, value_enum, default_value_t = Solver::Difference)]
+    solver: Solver,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Solver {
+    /// Repeatedly take differences until they vanish
+    Difference,
+    /// Evaluate the interpolating polynomial directly
+    Lagrange,
 }
 
 #[derive(Subcommand)]
@@ -15,46 +41,266 @@ enum Part {
     Part2,
 }
 
-fn seq(input: &str) -> Result<Vec<i64>, ParseIntError> {
-    input.split(" ").map(|s| s.parse::<i64>()).collect()
+#[derive(Clone, Copy, ValueEnum)]
+enum Direction {
+    /// Beyond the end of the sequence
+    Forward,
+    /// Before the start of the sequence
+    Backward,
+}
+
+#[derive(Debug, Error)]
+#[error("Arithmetic overflow; try again with --bigint")]
+struct OverflowError;
+
+/// Numeric types in which the extrapolation can be computed
+trait Value:
+    Send
+    + Sync
+    + Clone
+    + Zero
+    + One
+    + CheckedAdd
+    + CheckedSub
+    + CheckedMul
+    + CheckedDiv
+    + FromPrimitive
+    + FromStr
+    + Display
+{
+}
+
+impl<T> Value for T where
+    T: Send
+        + Sync
+        + Clone
+        + Zero
+        + One
+        + CheckedAdd
+        + CheckedSub
+        + CheckedMul
+        + CheckedDiv
+        + FromPrimitive
+        + FromStr
+        + Display
+{
+}
+
+#[derive(Debug, Error)]
+#[error("`{token}` at line {line}, column {column} is not a valid number")]
+struct ParseSeqError {
+    token: String,
+    line: usize,
+    column: usize,
 }
 
-fn predict_next(seq: &Vec<i64>) -> i64 {
-    if seq.iter().all(|&v| v == 0) {
-        return 0;
+/// Parse a sequence of numbers separated by any mix of commas and whitespace
+fn seq<T: Value>(input: &str, line: usize) -> Result<Vec<T>, ParseSeqError> {
+    let is_sep = |c: char| c == ',' || c.is_whitespace();
+
+    let mut values = Vec::new();
+    let mut rest = input;
+    while let Some(start) = rest.find(|c| !is_sep(c)) {
+        rest = &rest[start..];
+        let end = rest.find(is_sep).unwrap_or(rest.len());
+        let token = &rest[..end];
+        let value = token.parse::<T>().map_err(|_| ParseSeqError {
+            token: token.to_string(),
+            line,
+            column: input.len() - rest.len() + 1,
+        })?;
+        values.push(value);
+        rest = &rest[end..];
     }
+    Ok(values)
+}
+
+fn differences<T: Value>(seq: &[T]) -> Result<Vec<T>, OverflowError> {
+    seq.windows(2)
+        .map(|slice| slice[1].checked_sub(&slice[0]).ok_or(OverflowError))
+        .collect()
+}
 
-    let lower_order_prediction =
-        predict_next(&seq.windows(2).map(|slice| slice[1] - slice[0]).collect());
+/// The last (or first) value of each level of the difference pyramid, excluding the final all-zero level
+fn edge<T: Value>(seq: &[T], direction: Direction) -> Result<Vec<T>, OverflowError> {
+    // each level of the pyramid overwrites the one above it, so the buffer holds one fewer value every iteration
+    let mut buf = seq.to_vec();
+    let mut len = buf.len();
+    let mut edge = Vec::new();
+    while !buf[..len].iter().all(|v| v.is_zero()) {
+        edge.push(match direction {
+            Direction::Forward => buf[len - 1].clone(),
+            Direction::Backward => buf[0].clone(),
+        });
+        for i in 0..len - 1 {
+            buf[i] = buf[i + 1].checked_sub(&buf[i]).ok_or(OverflowError)?;
+        }
+        len -= 1;
+    }
+    Ok(edge)
+}
 
-    *seq.last().unwrap() + lower_order_prediction
+/// Advance the pyramid edge by one value
+fn extend_edge<T: Value>(edge: &mut [T], direction: Direction) -> Result<(), OverflowError> {
+    // each level is extended using the already-extended level below it
+    for k in (0..edge.len().saturating_sub(1)).rev() {
+        edge[k] = match direction {
+            Direction::Forward => edge[k].checked_add(&edge[k + 1]),
+            Direction::Backward => edge[k].checked_sub(&edge[k + 1]),
+        }
+        .ok_or(OverflowError)?;
+    }
+    Ok(())
 }
 
-fn predict_prev(seq: &Vec<i64>) -> i64 {
-    if seq.iter().all(|&v| v == 0) {
-        return 0;
+/// The value `steps` positions beyond the end (or before the start) of the sequence
+fn predict<T: Value>(seq: &[T], direction: Direction, steps: usize) -> Result<T, OverflowError> {
+    let mut edge = edge(seq, direction)?;
+    for _ in 0..steps {
+        extend_edge(&mut edge, direction)?;
     }
 
-    let lower_order_prediction =
-        predict_prev(&seq.windows(2).map(|slice| slice[1] - slice[0]).collect());
+    Ok(edge.into_iter().next().unwrap_or_else(T::zero))
+}
 
-    *seq.first().unwrap() - lower_order_prediction
+/// The generalized binomial coefficient C(n, k), which is an integer for any integer n
+fn binomial<T: Value>(n: i64, k: usize) -> Result<T, OverflowError> {
+    let mut c = T::one();
+    for m in 0..k {
+        // c is C(n, m) here, so c * (n - m) is divisible by m + 1
+        let factor = T::from_i64(n - m as i64).ok_or(OverflowError)?;
+        let divisor = T::from_usize(m + 1).ok_or(OverflowError)?;
+        c = c
+            .checked_mul(&factor)
+            .and_then(|c| c.checked_div(&divisor))
+            .ok_or(OverflowError)?;
+    }
+    Ok(c)
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+/// Evaluate the polynomial through (0, seq[0]), (1, seq[1]), ... at `x` using Lagrange's formula
+fn lagrange<T: Value>(seq: &[T], x: i64) -> Result<T, OverflowError> {
+    let n = seq.len();
+    let mut total = T::zero();
+    for (i, y) in seq.iter().enumerate() {
+        // the i'th Lagrange basis polynomial evaluated at x is
+        // (-1)^(n-1-i) * C(x, i) * C(x - i - 1, n - 1 - i)
+        let basis = binomial::<T>(x, i)?
+            .checked_mul(&binomial(x - i as i64 - 1, n - 1 - i)?)
+            .ok_or(OverflowError)?;
+        let term = y.checked_mul(&basis).ok_or(OverflowError)?;
+        total = if (n - 1 - i).is_multiple_of(2) {
+            total.checked_add(&term)
+        } else {
+            total.checked_sub(&term)
+        }
+        .ok_or(OverflowError)?;
+    }
+    Ok(total)
+}
 
-    let mut total: i64 = 0;
-    for line in stdin().lines() {
+/// Print every level of the difference pyramid, including the extrapolated values
+fn explain<T: Value>(seq: &[T], direction: Direction, steps: usize) -> Result<(), OverflowError> {
+    let mut levels = vec![seq.to_vec()];
+    while !levels.last().unwrap().iter().all(|v| v.is_zero()) {
+        let next = differences(levels.last().unwrap())?;
+        levels.push(next);
+    }
+
+    let mut edge = edge(seq, direction)?;
+    let mut extrapolated: Vec<Vec<T>> = vec![Vec::new(); levels.len()];
+    for _ in 0..steps {
+        extend_edge(&mut edge, direction)?;
+        for (k, ext) in extrapolated.iter_mut().enumerate() {
+            ext.push(edge.get(k).cloned().unwrap_or_else(T::zero));
+        }
+    }
+
+    let cells: Vec<Vec<String>> = levels
+        .iter()
+        .zip(extrapolated)
+        .map(|(level, ext)| {
+            let level = level.iter().map(|v| format!(" {v} "));
+            let ext = ext.iter().map(|v| format!("[{v}]"));
+            match direction {
+                Direction::Forward => level.chain(ext).collect(),
+                Direction::Backward => ext.rev().chain(level).collect(),
+            }
+        })
+        .collect();
+    let width = cells.iter().flatten().map(String::len).max().unwrap_or(0);
+
+    for (k, row) in cells.iter().enumerate() {
+        let indent = " ".repeat(k * (width + 1) / 2);
+        let row: Vec<String> = row.iter().map(|c| format!("{c:>width$}")).collect();
+        println!("{indent}{}", row.join(" "));
+    }
+    println!();
+    Ok(())
+}
+
+fn extrapolate<T: Value>(seq: &[T], args: &Args, direction: Direction) -> Result<T, OverflowError> {
+    match args.solver {
+        Solver::Difference => predict(seq, direction, args.steps),
+        Solver::Lagrange => {
+            let x = match direction {
+                Direction::Forward => (seq.len() - 1 + args.steps) as i64,
+                Direction::Backward => -(args.steps as i64),
+            };
+            lagrange(seq, x)
+        }
+    }
+}
+
+fn extrapolate_all<T: Value>(
+    seqs: &[Vec<T>],
+    args: &Args,
+    direction: Direction,
+) -> Result<Vec<T>, OverflowError> {
+    #[cfg(feature = "parallel")]
+    let seqs = seqs.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let seqs = seqs.iter();
+
+    // collecting keeps the results in input order
+    seqs.map(|seq| extrapolate(seq, args, direction)).collect()
+}
+
+fn solve<T: Value>(args: &Args, direction: Direction) -> Result<T> {
+    let mut seqs = Vec::new();
+    for (i, line) in stdin().lines().enumerate() {
         let line = line?;
-        let seq = seq(&line)?;
+        let seq: Vec<T> = seq(&line, i + 1)?;
+        if seq.is_empty() {
+            continue;
+        }
+
+        if args.explain {
+            explain(&seq, direction, args.steps)?;
+        }
+        seqs.push(seq);
+    }
 
-        total += match args.part {
-            Part::Part1 => predict_next(&seq),
-            Part::Part2 => predict_prev(&seq),
-        };
+    let mut total = T::zero();
+    for prediction in extrapolate_all(&seqs, args, direction)? {
+        total = total.checked_add(&prediction).ok_or(OverflowError)?;
     }
 
-    println!("{total}");
+    Ok(total)
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let direction = args.direction.unwrap_or(match args.part {
+        Part::Part1 => Direction::Forward,
+        Part::Part2 => Direction::Backward,
+    });
+
+    if args.bigint {
+        println!("{}", solve::<BigInt>(&args, direction)?);
+    } else {
+        println!("{}", solve::<i64>(&args, direction)?);
+    }
     Ok(())
 }